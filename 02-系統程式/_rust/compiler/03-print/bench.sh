@@ -0,0 +1,7 @@
+# fib(25)：舊的字串鍵 VM (直接跑 .p0) 對上暫存器式位元組碼 VM (跑 .p0b)
+rustc -O compiler.rs -o compiler || exit 1
+./compiler --emit p0b p0/fib.p0
+echo "== VM::run (HashMap<String, Value>)"
+time ./compiler p0/fib.p0
+echo "== 位元組碼 VM"
+time ./compiler p0/fib.p0b
@@ -0,0 +1,527 @@
+// ==========================================================
+// 位元組碼 (.p0b) 與暫存器式直譯器
+//
+// VM::run 以名稱 (例如 "t12") 為鍵，把暫存值與變數放在 HashMap 裡，每次存取都要雜湊字串。
+// 這裡把每個函數用到的名稱 (參數、變數、暫存值) 編成暫存器編號，常數集中到常數池，
+// 呼叫目標事先解析成函數或內建函數，執行時只剩陣列索引。
+//
+// 每個 IR 指令恰好對應一個位元組碼指令 (LABEL 變成 NOP)，
+// 所以執行期錯誤回報的 IR 編號和 VM::run 一致。
+// 和 VM 一樣在明確的框架堆疊上執行，遵守 --max-depth，出錯時附上呼叫鏈 (.p0b 沒有行號)。
+//
+// .p0b 檔的格式 (整數一律 little-endian)：
+//   "P0B" 版本(u8)
+//   常數池：數量(u32)，每個常數是 0 + i32 或 1 + 長度(u32) + UTF-8
+//   呼叫目標：數量(u32)，每個是函數名稱的常數編號(u32)
+//   全域變數：數量(u32)，每個是變數名稱的常數編號(u32)
+//   函數：數量(u32)，每個是 名稱的常數編號(u32) 參數個數(u16) 暫存器個數(u16) 指令數(u32) 指令...
+//   指令：opcode(u8) 接運算元；暫存器是 u16，常數/呼叫目標/全域變數/跳躍位置是 u32，
+//         NEW_ARRAY 與 CALL 的參數列表是 個數(u16) + 暫存器...
+// ==========================================================
+
+use super::*;
+
+const MAGIC: &[u8; 3] = b"P0B";
+const VERSION: u8 = 2;
+
+type Reg = u16;
+
+#[derive(Debug, Clone)]
+enum Op {
+    LoadK(Reg, u32),
+    Move(Reg, Reg),
+    LoadG(Reg, u32),
+    StoreG(u32, Reg),
+    Add(Reg, Reg, Reg),
+    Sub(Reg, Reg, Reg),
+    Mul(Reg, Reg, Reg),
+    Div(Reg, Reg, Reg),
+    Mod(Reg, Reg, Reg),
+    Eq(Reg, Reg, Reg),
+    Ne(Reg, Reg, Reg),
+    Lt(Reg, Reg, Reg),
+    Le(Reg, Reg, Reg),
+    Gt(Reg, Reg, Reg),
+    Ge(Reg, Reg, Reg),
+    Neg(Reg, Reg),
+    Not(Reg, Reg),
+    NewArray(Reg, Vec<Reg>),
+    IndexLoad(Reg, Reg, Reg),
+    IndexStore(Reg, Reg, Reg),
+    Call(u32, Reg, Vec<Reg>), // 呼叫目標編號、結果、參數
+    Return(Reg),
+    IfFalse(Reg, u32),
+    Goto(u32),
+    Nop,
+}
+
+struct Function {
+    name: String,
+    params: u16,
+    regs: u16,
+    code: Vec<Op>,
+}
+
+// 一個函數呼叫：暫存器與目前執行到的位置
+struct Frame {
+    func: usize,
+    pc: usize,
+    regs: Vec<Value>,
+}
+
+// run_frame 停下來的原因
+enum Exit {
+    Call(usize, Vec<Value>), // 呼叫 p0 函數 (函數編號、參數)
+    Return(Value),
+}
+
+enum Callee {
+    Func(usize),
+    Native(NativeFn),
+}
+
+pub struct Program {
+    pub max_depth: usize,
+    consts: Vec<Value>,
+    callee_names: Vec<u32>, // 呼叫目標的名稱 (常數編號)，存檔用
+    callees: Vec<Callee>,
+    global_names: Vec<u32>, // 全域變數的名稱 (常數編號)
+    globals: RefCell<Vec<Value>>,
+    functions: Vec<Function>,
+}
+
+// ---------- 由 IR 轉換 ----------
+
+pub fn lower(functions: &HashMap<String, (Vec<String>, Vec<IR>)>) -> Result<Program, String> {
+    let mut names: Vec<&String> = functions.keys().collect();
+    names.sort();
+    let mut consts: Vec<Value> = Vec::new();
+    let mut konst = |v: Value| -> u32 {
+        let found = consts.iter().position(|c| match (c, &v) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            _ => false,
+        });
+        found.unwrap_or_else(|| { consts.push(v); consts.len() - 1 }) as u32
+    };
+    let mut callee_names: Vec<u32> = Vec::new();
+    let mut global_names: Vec<u32> = Vec::new();
+    let mut out = Vec::new();
+
+    for name in &names {
+        konst(Value::Str(name.to_string()));
+        let (params, code) = &functions[*name];
+        let mut regs: HashMap<String, Reg> = HashMap::new();
+        let mut reg = |n: &String| -> Result<Reg, String> {
+            if let Some(r) = regs.get(n) { return Ok(*r); }
+            let next = regs.len();
+            // 暫存器個數也要放得進 u16，所以編號最大是 Reg::MAX - 1
+            if next >= Reg::MAX as usize { return Err(format!("函數 {} 用到的名稱太多 (最多 {} 個)", name, Reg::MAX)); }
+            regs.insert(n.clone(), next as Reg);
+            Ok(next as Reg)
+        };
+        for p in params { reg(p)?; }
+        let mut ops = Vec::with_capacity(code.len());
+        for ir in code {
+            let op = match ir {
+                IR::LoadConst(t, v) => Op::LoadK(reg(t)?, konst(Value::Int(*v))),
+                IR::LoadStr(t, s) => Op::LoadK(reg(t)?, konst(Value::Str(s.clone()))),
+                IR::LoadVar(t, n) | IR::StoreVar(t, n) => Op::Move(reg(t)?, reg(n)?),
+                IR::LoadGlobal(t, g) => Op::LoadG(reg(t)?, global_slot(&mut global_names, konst(Value::Str(g.clone())))),
+                IR::StoreGlobal(g, t) => Op::StoreG(global_slot(&mut global_names, konst(Value::Str(g.clone()))), reg(t)?),
+                IR::Add(t, l, r) => Op::Add(reg(t)?, reg(l)?, reg(r)?),
+                IR::Sub(t, l, r) => Op::Sub(reg(t)?, reg(l)?, reg(r)?),
+                IR::Mul(t, l, r) => Op::Mul(reg(t)?, reg(l)?, reg(r)?),
+                IR::Div(t, l, r) => Op::Div(reg(t)?, reg(l)?, reg(r)?),
+                IR::Mod(t, l, r) => Op::Mod(reg(t)?, reg(l)?, reg(r)?),
+                IR::Eq(t, l, r) => Op::Eq(reg(t)?, reg(l)?, reg(r)?),
+                IR::Ne(t, l, r) => Op::Ne(reg(t)?, reg(l)?, reg(r)?),
+                IR::Lt(t, l, r) => Op::Lt(reg(t)?, reg(l)?, reg(r)?),
+                IR::Le(t, l, r) => Op::Le(reg(t)?, reg(l)?, reg(r)?),
+                IR::Gt(t, l, r) => Op::Gt(reg(t)?, reg(l)?, reg(r)?),
+                IR::Ge(t, l, r) => Op::Ge(reg(t)?, reg(l)?, reg(r)?),
+                IR::Neg(t, s) => Op::Neg(reg(t)?, reg(s)?),
+                IR::Not(t, s) => Op::Not(reg(t)?, reg(s)?),
+                IR::NewArray(t, items) => Op::NewArray(reg(t)?, items.iter().map(&mut reg).collect::<Result<_, _>>()?),
+                IR::IndexLoad(t, a, i) => Op::IndexLoad(reg(t)?, reg(a)?, reg(i)?),
+                IR::IndexStore(a, i, v) => Op::IndexStore(reg(a)?, reg(i)?, reg(v)?),
+                IR::Call(f, args, t) => {
+                    let k = konst(Value::Str(f.clone()));
+                    let callee = callee_names.iter().position(|c| *c == k).unwrap_or_else(|| { callee_names.push(k); callee_names.len() - 1 });
+                    Op::Call(callee as u32, reg(t)?, args.iter().map(&mut reg).collect::<Result<_, _>>()?)
+                }
+                IR::Return(t) => Op::Return(reg(t)?),
+                IR::IfFalse(t, target) => Op::IfFalse(reg(t)?, *target as u32),
+                IR::Goto(target) => Op::Goto(*target as u32),
+                IR::Label => Op::Nop,
+            };
+            ops.push(op);
+        }
+        out.push(Function { name: name.to_string(), params: params.len() as u16, regs: regs.len() as u16, code: ops });
+    }
+    Program::link(consts, callee_names, global_names, out)
+}
+
+// 全域變數名稱 (常數編號) 在全域變數表裡的位置，第一次出現時加到最後
+fn global_slot(global_names: &mut Vec<u32>, k: u32) -> u32 {
+    let slot = global_names.iter().position(|g| *g == k).unwrap_or_else(|| { global_names.push(k); global_names.len() - 1 });
+    slot as u32
+}
+
+impl Program {
+    // 把呼叫目標的名稱解析成函數編號或內建函數
+    fn link(consts: Vec<Value>, callee_names: Vec<u32>, global_names: Vec<u32>, functions: Vec<Function>) -> Result<Program, String> {
+        let mut callees = Vec::new();
+        for k in &callee_names {
+            let Some(Value::Str(name)) = consts.get(*k as usize) else { return Err(format!("呼叫目標 #{} 不是字串常數", k)) };
+            let callee = match functions.iter().position(|f| &f.name == name) {
+                Some(i) => Callee::Func(i),
+                None => match NATIVES.iter().find(|(n, ..)| n == name) {
+                    Some((_, _, f)) => Callee::Native(*f),
+                    None => return Err(format!("找不到函數 {}", name)),
+                },
+            };
+            callees.push(callee);
+        }
+        // 沒有賦值過的全域變數是 0，和 VM 一致
+        let globals = RefCell::new(vec![Value::Int(0); global_names.len()]);
+        Ok(Program { max_depth: DEFAULT_MAX_DEPTH, consts, callee_names, callees, global_names, globals, functions })
+    }
+
+    // ---------- 存檔與讀檔 ----------
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Vec::new();
+        w.extend_from_slice(MAGIC);
+        w.push(VERSION);
+        w.extend_from_slice(&(self.consts.len() as u32).to_le_bytes());
+        for c in &self.consts {
+            match c {
+                Value::Int(v) => { w.push(0); w.extend_from_slice(&v.to_le_bytes()); }
+                Value::Str(s) => {
+                    w.push(1);
+                    w.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                    w.extend_from_slice(s.as_bytes());
+                }
+                Value::Array(_) => unreachable!("常數池裡不會有陣列"),
+            }
+        }
+        w.extend_from_slice(&(self.callee_names.len() as u32).to_le_bytes());
+        for k in &self.callee_names { w.extend_from_slice(&k.to_le_bytes()); }
+        w.extend_from_slice(&(self.global_names.len() as u32).to_le_bytes());
+        for k in &self.global_names { w.extend_from_slice(&k.to_le_bytes()); }
+        w.extend_from_slice(&(self.functions.len() as u32).to_le_bytes());
+        for f in &self.functions {
+            let name = self.consts.iter().position(|c| matches!(c, Value::Str(s) if *s == f.name)).expect("函數名稱應該在常數池裡");
+            w.extend_from_slice(&(name as u32).to_le_bytes());
+            w.extend_from_slice(&f.params.to_le_bytes());
+            w.extend_from_slice(&f.regs.to_le_bytes());
+            w.extend_from_slice(&(f.code.len() as u32).to_le_bytes());
+            for op in &f.code { encode_op(&mut w, op); }
+        }
+        w
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Program, String> {
+        let mut r = Reader { bytes, pos: 0 };
+        if r.take(3)? != MAGIC { return Err("不是 p0 位元組碼檔案 (開頭不是 P0B)".to_string()); }
+        let version = r.u8()?;
+        if version != VERSION { return Err(format!("不支援的位元組碼版本 {} (目前是 {})", version, VERSION)); }
+        let mut consts = Vec::new();
+        for _ in 0..r.u32()? {
+            consts.push(match r.u8()? {
+                0 => Value::Int(r.u32()? as i32),
+                1 => Value::Str(r.string()?),
+                tag => return Err(format!("未知的常數種類 {}", tag)),
+            });
+        }
+        let callee_names = (0..r.u32()?).map(|_| r.u32()).collect::<Result<Vec<_>, _>>()?;
+        let global_names = (0..r.u32()?).map(|_| r.u32()).collect::<Result<Vec<_>, _>>()?;
+        if global_names.iter().any(|k| !matches!(consts.get(*k as usize), Some(Value::Str(_)))) {
+            return Err("全域變數名稱不是字串常數".to_string());
+        }
+        let mut functions = Vec::new();
+        for _ in 0..r.u32()? {
+            let name = match consts.get(r.u32()? as usize) {
+                Some(Value::Str(s)) => s.clone(),
+                _ => return Err("函數名稱不是字串常數".to_string()),
+            };
+            let (params, regs) = (r.u16()?, r.u16()?);
+            let count = r.u32()?;
+            let code = (0..count).map(|_| decode_op(&mut r)).collect::<Result<Vec<_>, _>>()?;
+            // 先檢查暫存器與跳躍目標，執行時就不必再檢查
+            for op in &code {
+                let (used, target) = op_operands(op);
+                if let Some(bad) = used.iter().find(|x| **x >= regs) { return Err(format!("函數 {} 使用了不存在的暫存器 r{}", name, bad)); }
+                if target.is_some_and(|t| t > count) { return Err(format!("函數 {} 的跳躍目標超出範圍", name)); }
+                if let Op::LoadK(_, k) = op {
+                    if *k as usize >= consts.len() { return Err(format!("函數 {} 使用了不存在的常數 #{}", name, k)); }
+                }
+                if let Op::Call(c, ..) = op {
+                    if *c as usize >= callee_names.len() { return Err(format!("函數 {} 使用了不存在的呼叫目標 #{}", name, c)); }
+                }
+                if let Op::LoadG(_, g) | Op::StoreG(g, _) = op {
+                    if *g as usize >= global_names.len() { return Err(format!("函數 {} 使用了不存在的全域變數 #{}", name, g)); }
+                }
+            }
+            if params > regs { return Err(format!("函數 {} 的參數比暫存器多", name)); }
+            functions.push(Function { name, params, regs, code });
+        }
+        if r.pos != bytes.len() { return Err("檔案結尾有多餘的資料".to_string()); }
+        Program::link(consts, callee_names, global_names, functions)
+    }
+
+    // ---------- 直譯器 ----------
+
+    pub fn run_main(&self) -> Result<Value, RuntimeError> {
+        // 和 VM::run 一樣先執行 __init__ 設定全域變數
+        if let Some(init) = self.functions.iter().position(|f| f.name == INIT) { self.run(init)?; }
+        let main = self.functions.iter().position(|f| f.name == "main")
+            .ok_or_else(|| RuntimeError::new("main", 0, "找不到函數 'main'"))?;
+        self.run(main)
+    }
+
+    // 在明確的框架堆疊上執行，p0 的遞迴不會用到 Rust 的堆疊；出錯時依照當下的堆疊補上呼叫鏈
+    fn run(&self, func: usize) -> Result<Value, RuntimeError> {
+        let mut stack = vec![self.new_frame(func, Vec::new())];
+        self.execute(&mut stack).map_err(|mut e| { e.backtrace = self.backtrace(&stack); e })
+    }
+
+    fn new_frame(&self, func: usize, args: Vec<Value>) -> Frame {
+        let f = &self.functions[func];
+        // 沒有賦值過的變數是 0，和 VM::run 的 locals 預設值一致
+        let mut regs: Vec<Value> = vec![Value::Int(0); f.regs as usize];
+        for (i, a) in args.into_iter().enumerate().take(f.params as usize) { regs[i] = a; }
+        Frame { func, pc: 0, regs }
+    }
+
+    // 呼叫鏈，最內層在前；格式和 VM::backtrace 相同
+    fn backtrace(&self, stack: &[Frame]) -> Vec<CallSite> {
+        stack.iter().rev().map(|f| (self.functions[f.func].name.clone(), f.pc, None)).collect()
+    }
+
+    fn execute(&self, stack: &mut Vec<Frame>) -> Result<Value, RuntimeError> {
+        loop {
+            let depth = stack.len();
+            let frame = stack.last_mut().unwrap();
+            match self.run_frame(frame)? {
+                Exit::Call(func, args) => {
+                    // 呼叫端的 pc 停在 CALL 上，等被呼叫的函數回傳時才前進 (和 VM::step 一樣)
+                    if depth >= self.max_depth {
+                        let msg = format!("呼叫深度超過上限 {}", self.max_depth);
+                        return Err(RuntimeError::new(&self.functions[frame.func].name, frame.pc, &msg));
+                    }
+                    stack.push(self.new_frame(func, args));
+                }
+                Exit::Return(v) => {
+                    stack.pop();
+                    let Some(caller) = stack.last_mut() else { return Ok(v) };
+                    if let Op::Call(_, d, _) = &self.functions[caller.func].code[caller.pc] { caller.regs[*d as usize] = v; }
+                    caller.pc += 1;
+                }
+            }
+        }
+    }
+
+    // 執行堆疊頂端的框架，直到它呼叫 p0 函數或回傳
+    fn run_frame(&self, frame: &mut Frame) -> Result<Exit, RuntimeError> {
+        let f = &self.functions[frame.func];
+        let (regs, pc) = (&mut frame.regs, &mut frame.pc);
+        let err = |pc: usize, msg: &str| RuntimeError::new(&f.name, pc, msg);
+        let template = |op: &Op| match op {
+            Op::Add(..) => IR::Add(String::new(), String::new(), String::new()),
+            Op::Sub(..) => IR::Sub(String::new(), String::new(), String::new()),
+            Op::Mul(..) => IR::Mul(String::new(), String::new(), String::new()),
+            Op::Div(..) => IR::Div(String::new(), String::new(), String::new()),
+            Op::Mod(..) => IR::Mod(String::new(), String::new(), String::new()),
+            Op::Lt(..) => IR::Lt(String::new(), String::new(), String::new()),
+            Op::Le(..) => IR::Le(String::new(), String::new(), String::new()),
+            Op::Gt(..) => IR::Gt(String::new(), String::new(), String::new()),
+            _ => IR::Ge(String::new(), String::new(), String::new()),
+        };
+
+        let code = &f.code;
+        while *pc < code.len() {
+            match &code[*pc] {
+                Op::LoadK(d, k) => regs[*d as usize] = self.consts[*k as usize].clone(),
+                Op::Move(d, s) => regs[*d as usize] = regs[*s as usize].clone(),
+                Op::LoadG(d, g) => regs[*d as usize] = self.globals.borrow()[*g as usize].clone(),
+                Op::StoreG(g, s) => self.globals.borrow_mut()[*g as usize] = regs[*s as usize].clone(),
+                op @ (Op::Add(d, a, b) | Op::Sub(d, a, b) | Op::Mul(d, a, b) | Op::Div(d, a, b) | Op::Mod(d, a, b)
+                | Op::Lt(d, a, b) | Op::Le(d, a, b) | Op::Gt(d, a, b) | Op::Ge(d, a, b)) => {
+                    // 整數是最常見的情況，直接算；其他型別 (字串、錯誤) 交給 binary_op
+                    let v = match (op, &regs[*a as usize], &regs[*b as usize]) {
+                        (Op::Add(..), Value::Int(x), Value::Int(y)) => Value::Int(x.wrapping_add(*y)),
+                        (Op::Sub(..), Value::Int(x), Value::Int(y)) => Value::Int(x.wrapping_sub(*y)),
+                        (Op::Mul(..), Value::Int(x), Value::Int(y)) => Value::Int(x.wrapping_mul(*y)),
+                        (Op::Lt(..), Value::Int(x), Value::Int(y)) => Value::Int((x < y) as i32),
+                        (Op::Le(..), Value::Int(x), Value::Int(y)) => Value::Int((x <= y) as i32),
+                        (Op::Gt(..), Value::Int(x), Value::Int(y)) => Value::Int((x > y) as i32),
+                        (Op::Ge(..), Value::Int(x), Value::Int(y)) => Value::Int((x >= y) as i32),
+                        (op, l, r) => binary_op(&template(op), l, r).map_err(|msg| err(*pc, &msg))?,
+                    };
+                    regs[*d as usize] = v;
+                }
+                Op::Eq(d, a, b) => regs[*d as usize] = Value::Int(values_equal(&regs[*a as usize], &regs[*b as usize]) as i32),
+                Op::Ne(d, a, b) => regs[*d as usize] = Value::Int(!values_equal(&regs[*a as usize], &regs[*b as usize]) as i32),
+                Op::Neg(d, s) => match &regs[*s as usize] {
+                    Value::Int(v) => regs[*d as usize] = Value::Int(v.wrapping_neg()),
+                    v => return Err(err(*pc, &format!("型別錯誤: 不能對{}使用 '-'", v.type_name()))),
+                },
+                Op::Not(d, s) => {
+                    let truthy = match &regs[*s as usize] { Value::Int(v) => *v != 0, _ => true };
+                    regs[*d as usize] = Value::Int(!truthy as i32);
+                }
+                Op::NewArray(d, items) => {
+                    let values = items.iter().map(|r| regs[*r as usize].clone()).collect();
+                    regs[*d as usize] = Value::Array(Rc::new(RefCell::new(values)));
+                }
+                Op::IndexLoad(d, a, i) => {
+                    let (items, idx) = array_slot(&f.name, *pc, &regs[*a as usize], &regs[*i as usize])?;
+                    let v = items.borrow()[idx].clone();
+                    regs[*d as usize] = v;
+                }
+                Op::IndexStore(a, i, v) => {
+                    let (items, idx) = array_slot(&f.name, *pc, &regs[*a as usize], &regs[*i as usize])?;
+                    items.borrow_mut()[idx] = regs[*v as usize].clone();
+                }
+                Op::Call(c, d, args) => {
+                    let call_args: Vec<Value> = args.iter().map(|r| regs[*r as usize].clone()).collect();
+                    match &self.callees[*c as usize] {
+                        Callee::Func(i) => return Ok(Exit::Call(*i, call_args)),
+                        Callee::Native(native) => regs[*d as usize] = native(&call_args).map_err(|msg| err(*pc, &msg))?,
+                    }
+                }
+                Op::Return(r) => return Ok(Exit::Return(std::mem::replace(&mut regs[*r as usize], Value::Int(0)))),
+                Op::IfFalse(r, target) => {
+                    if let Value::Int(0) = regs[*r as usize] { *pc = *target as usize; continue; }
+                }
+                Op::Goto(target) => { *pc = *target as usize; continue; }
+                Op::Nop => {}
+            }
+            *pc += 1;
+        }
+        // 走到函數結尾沒有 RETURN，回傳 0
+        Ok(Exit::Return(Value::Int(0)))
+    }
+}
+
+// 指令讀寫的暫存器與跳躍目標，載入時檢查用
+fn op_operands(op: &Op) -> (Vec<Reg>, Option<u32>) {
+    match op {
+        Op::LoadK(d, _) | Op::LoadG(d, _) | Op::StoreG(_, d) | Op::Return(d) => (vec![*d], None),
+        Op::Move(a, b) | Op::Neg(a, b) | Op::Not(a, b) => (vec![*a, *b], None),
+        Op::Add(a, b, c) | Op::Sub(a, b, c) | Op::Mul(a, b, c) | Op::Div(a, b, c) | Op::Mod(a, b, c)
+        | Op::Eq(a, b, c) | Op::Ne(a, b, c) | Op::Lt(a, b, c) | Op::Le(a, b, c) | Op::Gt(a, b, c) | Op::Ge(a, b, c)
+        | Op::IndexLoad(a, b, c) | Op::IndexStore(a, b, c) => (vec![*a, *b, *c], None),
+        Op::NewArray(d, items) | Op::Call(_, d, items) => (std::iter::once(*d).chain(items.iter().copied()).collect(), None),
+        Op::IfFalse(r, t) => (vec![*r], Some(*t)),
+        Op::Goto(t) => (vec![], Some(*t)),
+        Op::Nop => (vec![], None),
+    }
+}
+
+fn encode_op(w: &mut Vec<u8>, op: &Op) {
+    let reg = |w: &mut Vec<u8>, r: &Reg| w.extend_from_slice(&r.to_le_bytes());
+    let list = |w: &mut Vec<u8>, rs: &[Reg]| {
+        w.extend_from_slice(&(rs.len() as u16).to_le_bytes());
+        for r in rs { w.extend_from_slice(&r.to_le_bytes()); }
+    };
+    let three = |w: &mut Vec<u8>, code: u8, a: &Reg, b: &Reg, c: &Reg| { w.push(code); reg(w, a); reg(w, b); reg(w, c); };
+    match op {
+        Op::LoadK(d, k) => { w.push(0); reg(w, d); w.extend_from_slice(&k.to_le_bytes()); }
+        Op::Move(d, s) => { w.push(1); reg(w, d); reg(w, s); }
+        Op::Add(a, b, c) => three(w, 2, a, b, c),
+        Op::Sub(a, b, c) => three(w, 3, a, b, c),
+        Op::Mul(a, b, c) => three(w, 4, a, b, c),
+        Op::Div(a, b, c) => three(w, 5, a, b, c),
+        Op::Mod(a, b, c) => three(w, 6, a, b, c),
+        Op::Eq(a, b, c) => three(w, 7, a, b, c),
+        Op::Ne(a, b, c) => three(w, 8, a, b, c),
+        Op::Lt(a, b, c) => three(w, 9, a, b, c),
+        Op::Le(a, b, c) => three(w, 10, a, b, c),
+        Op::Gt(a, b, c) => three(w, 11, a, b, c),
+        Op::Ge(a, b, c) => three(w, 12, a, b, c),
+        Op::Neg(d, s) => { w.push(13); reg(w, d); reg(w, s); }
+        Op::Not(d, s) => { w.push(14); reg(w, d); reg(w, s); }
+        Op::NewArray(d, items) => { w.push(15); reg(w, d); list(w, items); }
+        Op::IndexLoad(a, b, c) => three(w, 16, a, b, c),
+        Op::IndexStore(a, b, c) => three(w, 17, a, b, c),
+        Op::Call(c, d, args) => { w.push(18); w.extend_from_slice(&c.to_le_bytes()); reg(w, d); list(w, args); }
+        Op::Return(r) => { w.push(19); reg(w, r); }
+        Op::IfFalse(r, t) => { w.push(20); reg(w, r); w.extend_from_slice(&t.to_le_bytes()); }
+        Op::Goto(t) => { w.push(21); w.extend_from_slice(&t.to_le_bytes()); }
+        Op::Nop => w.push(22),
+        Op::LoadG(d, g) => { w.push(23); reg(w, d); w.extend_from_slice(&g.to_le_bytes()); }
+        Op::StoreG(g, s) => { w.push(24); w.extend_from_slice(&g.to_le_bytes()); reg(w, s); }
+    }
+}
+
+fn decode_op(r: &mut Reader) -> Result<Op, String> {
+    let code = r.u8()?;
+    let op = match code {
+        0 => Op::LoadK(r.u16()?, r.u32()?),
+        1 => Op::Move(r.u16()?, r.u16()?),
+        2..=12 | 16 | 17 => {
+            let (a, b, c) = (r.u16()?, r.u16()?, r.u16()?);
+            match code {
+                2 => Op::Add(a, b, c),
+                3 => Op::Sub(a, b, c),
+                4 => Op::Mul(a, b, c),
+                5 => Op::Div(a, b, c),
+                6 => Op::Mod(a, b, c),
+                7 => Op::Eq(a, b, c),
+                8 => Op::Ne(a, b, c),
+                9 => Op::Lt(a, b, c),
+                10 => Op::Le(a, b, c),
+                11 => Op::Gt(a, b, c),
+                12 => Op::Ge(a, b, c),
+                16 => Op::IndexLoad(a, b, c),
+                _ => Op::IndexStore(a, b, c),
+            }
+        }
+        13 => Op::Neg(r.u16()?, r.u16()?),
+        14 => Op::Not(r.u16()?, r.u16()?),
+        15 => Op::NewArray(r.u16()?, r.list()?),
+        18 => Op::Call(r.u32()?, r.u16()?, r.list()?),
+        19 => Op::Return(r.u16()?),
+        20 => Op::IfFalse(r.u16()?, r.u32()?),
+        21 => Op::Goto(r.u32()?),
+        22 => Op::Nop,
+        23 => Op::LoadG(r.u16()?, r.u32()?),
+        24 => Op::StoreG(r.u32()?, r.u16()?),
+        _ => return Err(format!("未知的 opcode {} (位移 {})", code, r.pos - 1)),
+    };
+    Ok(op)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        let end = self.pos + n;
+        let slice = self.bytes.get(self.pos..end).ok_or("檔案提早結束")?;
+        self.pos = end;
+        Ok(slice)
+    }
+    fn u8(&mut self) -> Result<u8, String> { Ok(self.take(1)?[0]) }
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "字串不是合法的 UTF-8".to_string())
+    }
+    fn list(&mut self) -> Result<Vec<Reg>, String> {
+        let n = self.u16()?;
+        (0..n).map(|_| self.u16()).collect()
+    }
+}
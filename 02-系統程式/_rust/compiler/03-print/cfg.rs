@@ -0,0 +1,305 @@
+// ==========================================================
+// 控制流程圖 (CFG)、支配者 (dominator) 與 SSA
+//
+// IR 是一串以絕對位置跳躍的指令，這裡把它切成基本區塊並連成 CFG，
+// 用 Cooper-Harvey-Kennedy 的迭代法算出每個區塊的直接支配者 (idom)，
+// 再依支配邊界 (dominance frontier) 插入 phi 並沿著支配樹重新命名，得到 SSA。
+//
+// SSA 裡變數與暫存值一視同仁：STORE_VAR 是對變數的定義，LOAD_VAR 是使用。
+// 每次定義產生一個新版本 name#k；name#0 代表進入函數時的值 (參數，或尚未賦值的變數)。
+// --emit cfg 會把每個函數輸出成一個 Graphviz .dot 檔。
+// ==========================================================
+
+use super::*;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Write;
+
+pub struct Block {
+    pub start: usize, // 第一個指令的位置
+    pub end: usize,   // 最後一個指令的下一個位置
+    pub succs: Vec<usize>,
+    pub preds: Vec<usize>,
+}
+
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    pub rpo: Vec<usize>,          // 從入口可到達的區塊，依反向後序排列
+    pub idom: Vec<Option<usize>>, // 直接支配者；入口是自己，走不到的區塊是 None
+}
+
+pub struct Phi {
+    pub var: String,
+    pub dest: String,
+    pub args: Vec<(usize, String)>, // (前驅區塊, 從該區塊流入的版本)
+}
+
+pub struct SsaBlock {
+    pub phis: Vec<Phi>,
+    pub code: Vec<IR>, // IFFALSE / GOTO 的目標改成區塊編號
+}
+
+impl Cfg {
+    // 區塊的開頭：跳躍目標、跳躍或回傳的下一行。
+    // B0 固定是空的入口區塊 (IR 第 0 行可能是迴圈的跳躍目標，入口不能有前驅)，
+    // 最後固定放一個空的結尾區塊，代表跑到函數結尾時的隱含 return 0。
+    pub fn build(code: &[IR]) -> Self {
+        let mut leaders = BTreeSet::from([0, code.len()]);
+        for (ip, ir) in code.iter().enumerate() {
+            match ir {
+                IR::IfFalse(_, target) | IR::Goto(target) => { leaders.insert(*target); leaders.insert(ip + 1); }
+                IR::Return(_) => { leaders.insert(ip + 1); }
+                _ => {}
+            }
+        }
+        let leaders: Vec<usize> = leaders.into_iter().collect();
+        let block_of = |ip: usize| leaders.binary_search(&ip).unwrap() + 1;
+        let mut blocks = vec![Block { start: 0, end: 0, succs: vec![1], preds: Vec::new() }];
+        for (i, &start) in leaders.iter().enumerate() {
+            let end = leaders.get(i + 1).copied().unwrap_or(start);
+            blocks.push(Block { start, end, succs: Vec::new(), preds: Vec::new() });
+        }
+
+        for b in 1..blocks.len() - 1 {
+            let end = blocks[b].end;
+            // IFFALSE 的兩個後繼：先是條件成立 (往下走)，再是條件不成立 (跳躍)
+            blocks[b].succs = match &code[end - 1] {
+                IR::Goto(target) => vec![block_of(*target)],
+                IR::IfFalse(_, target) => vec![block_of(end), block_of(*target)],
+                IR::Return(_) => vec![],
+                _ => vec![block_of(end)],
+            };
+        }
+        for b in 0..blocks.len() {
+            for s in blocks[b].succs.clone() { blocks[s].preds.push(b); }
+        }
+
+        let rpo = reverse_postorder(&blocks);
+        let idom = dominators(&blocks, &rpo);
+        Cfg { blocks, rpo, idom }
+    }
+
+    // 支配邊界：b 支配某個前驅、卻不嚴格支配的區塊
+    fn frontiers(&self) -> Vec<HashSet<usize>> {
+        let mut df = vec![HashSet::new(); self.blocks.len()];
+        for &b in &self.rpo {
+            let preds: Vec<usize> = self.blocks[b].preds.iter().copied().filter(|p| self.idom[*p].is_some()).collect();
+            if preds.len() < 2 { continue; }
+            for p in preds {
+                let mut runner = p;
+                while Some(runner) != self.idom[b] {
+                    df[runner].insert(b);
+                    runner = self.idom[runner].unwrap();
+                }
+            }
+        }
+        df
+    }
+
+    // 支配樹的子節點
+    fn dom_children(&self) -> Vec<Vec<usize>> {
+        let mut children = vec![Vec::new(); self.blocks.len()];
+        for &b in self.rpo.iter().skip(1) { children[self.idom[b].unwrap()].push(b); }
+        children
+    }
+
+    pub fn to_ssa(&self, params: &[String], code: &[IR]) -> Vec<SsaBlock> {
+        let mut ssa: Vec<SsaBlock> = self.blocks.iter().map(|blk| SsaBlock {
+            phis: Vec::new(),
+            code: code[blk.start..blk.end].iter().map(|ir| match ir {
+                IR::IfFalse(t, target) => IR::IfFalse(t.clone(), self.block_at(*target)),
+                IR::Goto(target) => IR::Goto(self.block_at(*target)),
+                ir => ir.clone(),
+            }).collect(),
+        }).collect();
+
+        // 半修剪 (semi-pruned) SSA：只有跨區塊使用的名稱才需要 phi
+        let mut globals: BTreeSet<String> = BTreeSet::new();
+        let mut def_blocks: HashMap<String, Vec<usize>> = HashMap::new();
+        for p in params { def_blocks.entry(p.clone()).or_default().push(0); }
+        for &b in &self.rpo {
+            let mut defined: HashSet<String> = HashSet::new();
+            for ir in ssa[b].code.iter_mut() {
+                let (uses, def) = operands(ir);
+                for u in uses {
+                    if !defined.contains(u.as_str()) { globals.insert(u.clone()); }
+                }
+                if let Some(d) = def {
+                    defined.insert(d.clone());
+                    def_blocks.entry(d.clone()).or_default().push(b);
+                }
+            }
+        }
+
+        let df = self.frontiers();
+        for var in &globals {
+            let mut has_phi: HashSet<usize> = HashSet::new();
+            let mut work = def_blocks.get(var).cloned().unwrap_or_default();
+            while let Some(b) = work.pop() {
+                for &d in &df[b] {
+                    if has_phi.insert(d) {
+                        let args = self.blocks[d].preds.iter().map(|p| (*p, String::new())).collect();
+                        ssa[d].phis.push(Phi { var: var.clone(), dest: String::new(), args });
+                        work.push(d);
+                    }
+                }
+            }
+        }
+
+        let mut renamer = Renamer { counters: HashMap::new(), stacks: HashMap::new() };
+        for p in params { renamer.stacks.insert(p.clone(), vec![format!("{}#0", p)]); }
+        renamer.rename(self, &self.dom_children(), &mut ssa, 0);
+        ssa
+    }
+
+    fn block_at(&self, ip: usize) -> usize {
+        self.blocks.iter().skip(1).position(|b| b.start == ip).unwrap() + 1
+    }
+}
+
+fn reverse_postorder(blocks: &[Block]) -> Vec<usize> {
+    fn visit(blocks: &[Block], b: usize, seen: &mut Vec<bool>, order: &mut Vec<usize>) {
+        seen[b] = true;
+        for &s in &blocks[b].succs {
+            if !seen[s] { visit(blocks, s, seen, order); }
+        }
+        order.push(b);
+    }
+    let mut seen = vec![false; blocks.len()];
+    let mut order = Vec::new();
+    visit(blocks, 0, &mut seen, &mut order);
+    order.reverse();
+    order
+}
+
+// Cooper, Harvey, Kennedy: "A Simple, Fast Dominance Algorithm"
+fn dominators(blocks: &[Block], rpo: &[usize]) -> Vec<Option<usize>> {
+    let mut order = vec![usize::MAX; blocks.len()];
+    for (i, &b) in rpo.iter().enumerate() { order[b] = i; }
+    let mut idom: Vec<Option<usize>> = vec![None; blocks.len()];
+    idom[0] = Some(0);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &b in rpo.iter().skip(1) {
+            let mut new_idom: Option<usize> = None;
+            for &p in &blocks[b].preds {
+                if idom[p].is_none() { continue; }
+                new_idom = Some(match new_idom {
+                    None => p,
+                    Some(mut other) => {
+                        let mut p = p;
+                        while p != other {
+                            while order[p] > order[other] { p = idom[p].unwrap(); }
+                            while order[other] > order[p] { other = idom[other].unwrap(); }
+                        }
+                        p
+                    }
+                });
+            }
+            if idom[b] != new_idom { idom[b] = new_idom; changed = true; }
+        }
+    }
+    idom
+}
+
+// 指令讀取的名稱與定義的名稱 (LOAD_VAR 讀變數、STORE_VAR 定義變數)
+fn operands(ir: &mut IR) -> (Vec<&mut String>, Option<&mut String>) {
+    match ir {
+        IR::LoadConst(t, _) | IR::LoadStr(t, _) | IR::LoadGlobal(t, _) => (vec![], Some(t)),
+        IR::LoadVar(t, n) | IR::StoreVar(t, n) | IR::Neg(t, n) | IR::Not(t, n) => (vec![n], Some(t)),
+        IR::Add(t, l, r) | IR::Sub(t, l, r) | IR::Mul(t, l, r) | IR::Div(t, l, r) | IR::Mod(t, l, r)
+        | IR::Eq(t, l, r) | IR::Ne(t, l, r) | IR::Lt(t, l, r) | IR::Le(t, l, r) | IR::Gt(t, l, r) | IR::Ge(t, l, r)
+        | IR::IndexLoad(t, l, r) => (vec![l, r], Some(t)),
+        IR::IndexStore(a, i, t) => (vec![a, i, t], None),
+        IR::NewArray(t, items) | IR::Call(_, items, t) => (items.iter_mut().collect(), Some(t)),
+        IR::Return(t) | IR::IfFalse(t, _) | IR::StoreGlobal(_, t) => (vec![t], None),
+        IR::Goto(_) | IR::Label => (vec![], None),
+    }
+}
+
+struct Renamer {
+    counters: HashMap<String, usize>,
+    stacks: HashMap<String, Vec<String>>,
+}
+
+impl Renamer {
+    fn fresh(&mut self, var: &str, pushed: &mut Vec<String>) -> String {
+        let n = self.counters.entry(var.to_string()).or_insert(0);
+        *n += 1;
+        let name = format!("{}#{}", var, n);
+        self.stacks.entry(var.to_string()).or_default().push(name.clone());
+        pushed.push(var.to_string());
+        name
+    }
+
+    fn current(&self, var: &str) -> String {
+        self.stacks.get(var).and_then(|s| s.last().cloned()).unwrap_or_else(|| format!("{}#0", var))
+    }
+
+    fn rename(&mut self, cfg: &Cfg, children: &[Vec<usize>], ssa: &mut [SsaBlock], b: usize) {
+        let mut pushed = Vec::new();
+        for i in 0..ssa[b].phis.len() {
+            let var = ssa[b].phis[i].var.clone();
+            ssa[b].phis[i].dest = self.fresh(&var, &mut pushed);
+        }
+        for ir in ssa[b].code.iter_mut() {
+            let (uses, def) = operands(ir);
+            for u in uses { *u = self.current(u); }
+            if let Some(d) = def { *d = self.fresh(&d.clone(), &mut pushed); }
+        }
+        for &s in &cfg.blocks[b].succs {
+            for phi in ssa[s].phis.iter_mut() {
+                for (p, arg) in phi.args.iter_mut() {
+                    if *p == b { *arg = self.current(&phi.var); }
+                }
+            }
+        }
+        for &c in &children[b] { self.rename(cfg, children, ssa, c); }
+        for var in pushed { self.stacks.get_mut(&var).unwrap().pop(); }
+    }
+}
+
+// 一個函數的 Graphviz 圖：方塊是基本區塊 (SSA 形式)，實線是控制流程，灰色虛線是支配樹
+pub fn to_dot(name: &str, params: &[String], code: &[IR]) -> String {
+    let cfg = Cfg::build(code);
+    let ssa = cfg.to_ssa(params, code);
+    let esc = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut out = String::new();
+    writeln!(out, "digraph \"{}\" {{", esc(name)).unwrap();
+    writeln!(out, "  node [shape=box, fontname=\"monospace\"];").unwrap();
+    for &b in &cfg.rpo {
+        let blk = &cfg.blocks[b];
+        let mut label = if b == 0 {
+            format!("B0 (入口{})\\l", params.iter().map(|p| format!(" {}#0", p)).collect::<String>())
+        } else if blk.start == code.len() {
+            format!("B{} (結尾，回傳 0)\\l", b)
+        } else {
+            format!("B{}  [IR {}..{}]\\l", b, blk.start, blk.end)
+        };
+        for phi in &ssa[b].phis {
+            let args: Vec<String> = phi.args.iter().map(|(p, a)| format!("B{}: {}", p, a)).collect();
+            label.push_str(&esc(&format!("{} = phi({})", phi.dest, args.join(", "))));
+            label.push_str("\\l");
+        }
+        for ir in &ssa[b].code {
+            let line = match ir {
+                IR::IfFalse(t, target) => format!("IFFALSE {} B{}", t, target),
+                IR::Goto(target) => format!("GOTO B{}", target),
+                ir => ir.to_string(),
+            };
+            label.push_str(&esc(&line));
+            label.push_str("\\l");
+        }
+        writeln!(out, "  B{} [label=\"{}\"];", b, label).unwrap();
+        let conditional = matches!(code.get(blk.end.wrapping_sub(1)), Some(IR::IfFalse(..))) && blk.end > blk.start;
+        for (i, s) in blk.succs.iter().enumerate() {
+            let edge = if conditional { if i == 0 { " [label=\"真\"]" } else { " [label=\"假\"]" } } else { "" };
+            writeln!(out, "  B{} -> B{}{};", b, s, edge).unwrap();
+        }
+    }
+    for &b in cfg.rpo.iter().skip(1) {
+        writeln!(out, "  B{} -> B{} [style=dashed, color=gray, constraint=false];", cfg.idom[b].unwrap(), b).unwrap();
+    }
+    out.push_str("}\n");
+    out
+}
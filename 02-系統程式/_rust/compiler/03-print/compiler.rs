@@ -1,104 +1,281 @@
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::fmt;
 use std::fs;
 use std::path::Path;
 use std::process;
+use std::rc::Rc;
+
+mod bytecode;
+mod cfg;
+mod debugger;
+mod irtext;
+mod modules;
+mod opt;
+mod pretty;
+mod profile;
+mod repl;
+mod riscv;
+mod x86;
 
 // ==========================================================
-// 1. 定義 Token 與 AST
+// 1. 定義 Token、AST 與錯誤訊息
 // ==========================================================
 
 #[derive(Debug, Clone, PartialEq)]
 enum Token {
     Fn, Let, If, Else, Return,
+    While, For, Break, Continue,
+    Import, Export, Const,
     Ident(String),
     Int(i32),
     Str(String), // 新增：字串 Token
-    Assign, Plus, Minus, Mul, Div, Eq,
-    LParen, RParen, LBrace, RBrace, Semi, Comma,
+    Assign, Plus, Minus, Mul, Div, Mod, Eq, Ne, Lt, Le, Gt, Ge,
+    Not, And, Or,
+    LParen, RParen, LBrace, RBrace, LBracket, RBracket, Semi, Comma,
     EOF,
 }
 
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Token::Fn => "'fn'", Token::Let => "'let'", Token::If => "'if'", Token::Else => "'else'",
+            Token::Return => "'return'", Token::While => "'while'", Token::For => "'for'",
+            Token::Break => "'break'", Token::Continue => "'continue'",
+            Token::Import => "'import'", Token::Export => "'export'", Token::Const => "'const'",
+            Token::Ident(n) => return write!(f, "名稱 '{}'", n),
+            Token::Int(v) => return write!(f, "整數 {}", v),
+            Token::Str(s) => return write!(f, "字串 '{}'", s),
+            Token::Assign => "'='", Token::Plus => "'+'", Token::Minus => "'-'", Token::Mul => "'*'",
+            Token::Div => "'/'", Token::Mod => "'%'", Token::Eq => "'=='", Token::Ne => "'!='",
+            Token::Lt => "'<'", Token::Le => "'<='", Token::Gt => "'>'", Token::Ge => "'>='",
+            Token::Not => "'!'", Token::And => "'&&'", Token::Or => "'||'",
+            Token::LParen => "'('", Token::RParen => "')'", Token::LBrace => "'{'", Token::RBrace => "'}'",
+            Token::LBracket => "'['", Token::RBracket => "']'",
+            Token::Semi => "';'", Token::Comma => "','",
+            Token::EOF => "檔案結尾",
+        };
+        f.write_str(s)
+    }
+}
+
+// 原始碼位置：line/col 從 1 起算，len 是要標示的字元數
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Span {
+    line: usize,
+    col: usize,
+    len: usize,
+}
+
+impl Span {
+    // 延伸到 end 的結尾；跨行時只標示起始那一行
+    fn to(self, end: Span) -> Span {
+        if end.line == self.line && end.col + end.len > self.col {
+            Span { len: end.col + end.len - self.col, ..self }
+        } else { self }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Expr {
+    kind: ExprKind,
+    span: Span,
+}
+
 #[derive(Debug, Clone)]
-enum Expr {
+enum ExprKind {
     Number(i32),
     Variable(String),
     Str(String), // 新增：字串表達式
+    Array(Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    UnaryOp(Token, Box<Expr>),
     BinaryOp(Box<Expr>, Token, Box<Expr>),
     Call(String, Vec<Expr>),
 }
 
 #[derive(Debug, Clone)]
-enum Stmt {
+struct Stmt {
+    kind: StmtKind,
+    span: Span,
+}
+
+#[derive(Debug, Clone)]
+enum StmtKind {
     VarDecl(String, Expr),
+    ConstDecl(String, Expr), // 只出現在最外層
+    Assign(String, Expr),
+    IndexAssign(Expr, Expr, Expr), // a[i] = v
     If(Expr, Vec<Stmt>, Option<Vec<Stmt>>),
+    While(Expr, Vec<Stmt>),
+    For(Option<Box<Stmt>>, Option<Expr>, Option<Box<Stmt>>, Vec<Stmt>),
+    Break,
+    Continue,
     Return(Expr),
     FuncDecl(String, Vec<String>, Vec<Stmt>),
     ExprStmt(Expr),
 }
 
+// 編譯期錯誤：訊息 + 位置，輸出時仿照 rustc 附上原始碼與 ^ 標示
+#[derive(Debug, Clone)]
+struct Diagnostic {
+    msg: String,
+    span: Span,
+}
+
+impl Diagnostic {
+    fn new(msg: impl Into<String>, span: Span) -> Self {
+        Self { msg: msg.into(), span }
+    }
+
+    fn render(&self, path: &str, source: &str) -> String {
+        let Span { line, col, len } = self.span;
+        let text = source.lines().nth(line.saturating_sub(1)).unwrap_or("");
+        // caret 前面保留 tab，其他字元換成空白，這樣才會對齊
+        let pad: String = text.chars().take(col.saturating_sub(1)).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        let gutter = " ".repeat(line.to_string().len());
+        format!(
+            "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+            self.msg, gutter, path, line, col, gutter, line, text, gutter, pad, "^".repeat(len.max(1))
+        )
+    }
+}
+
 // ==========================================================
-// 2. Lexer (支援字串)
+// 2. Lexer (支援字串與註解，記錄行列位置)
 // ==========================================================
 
+// // 與 /* */ 註解：剖析時跳過，另外保留下來給 fmt 放回原位
+#[derive(Debug, Clone)]
+struct Comment {
+    span: Span,
+    text: String,
+    trailing: bool, // 同一行前面還有程式碼
+}
+
 struct Lexer {
     input: Vec<char>,
     pos: usize,
+    line: usize,
+    col: usize,
+    errors: Vec<Diagnostic>,
+    comments: Vec<Comment>,
+    last_line: usize, // 上一個 token 結尾所在的行
 }
 
 impl Lexer {
     fn new(input: &str) -> Self {
-        Self { input: input.chars().collect(), pos: 0 }
+        Self { input: input.chars().collect(), pos: 0, line: 1, col: 1, errors: Vec::new(), comments: Vec::new(), last_line: 0 }
     }
 
-    fn next_token(&mut self) -> Token {
-        self.skip_whitespace();
-        if self.pos >= self.input.len() { return Token::EOF; }
+    fn next_token(&mut self) -> (Token, Span) {
+        loop {
+            self.skip_whitespace();
+            let (line, col, start) = (self.line, self.col, self.pos);
+            if self.pos >= self.input.len() { return (Token::EOF, Span { line, col, len: 1 }); }
+            if self.peek() == '/' && matches!(self.input.get(self.pos + 1), Some('/' | '*')) {
+                self.scan_comment();
+                continue;
+            }
+            // 無法辨識的字元已記錄在 errors，繼續往下掃描
+            if let Some(tok) = self.scan_token() {
+                self.last_line = self.line;
+                return (tok, Span { line, col, len: self.pos - start });
+            }
+        }
+    }
 
-        let ch = self.input[self.pos];
+    fn scan_comment(&mut self) {
+        let (line, col, start) = (self.line, self.col, self.pos);
+        self.advance(); // /
+        if self.advance() == '/' {
+            while self.pos < self.input.len() && self.input[self.pos] != '\n' { self.advance(); }
+        } else {
+            loop {
+                if self.pos >= self.input.len() {
+                    self.errors.push(Diagnostic::new("註解缺少結尾的 */", Span { line, col, len: 2 }));
+                    break;
+                }
+                if self.advance() == '*' && self.peek() == '/' { self.advance(); break; }
+            }
+        }
+        let text: String = self.input[start..self.pos].iter().collect();
+        let span = Span { line, col, len: self.pos - start };
+        self.comments.push(Comment { span, text: text.trim_end().to_string(), trailing: self.last_line == line });
+    }
 
-        // 處理字串 '...'
-        if ch == '\'' {
-            self.pos += 1;
+    fn scan_token(&mut self) -> Option<Token> {
+        let (line, col) = (self.line, self.col);
+        let ch = self.advance();
+
+        // 處理字串 '...' 或 "..."
+        if ch == '\'' || ch == '"' {
             let start = self.pos;
-            while self.pos < self.input.len() && self.input[self.pos] != '\'' {
-                self.pos += 1;
+            while self.pos < self.input.len() && self.input[self.pos] != ch {
+                self.advance();
             }
             let s: String = self.input[start..self.pos].iter().collect();
-            self.pos += 1; // 跳過結尾引號
-            return Token::Str(s);
+            if self.pos < self.input.len() {
+                self.advance(); // 跳過結尾引號
+            } else {
+                self.errors.push(Diagnostic::new("字串缺少結尾的引號", Span { line, col, len: 1 }));
+            }
+            return Some(Token::Str(s));
         }
 
         if ch.is_alphabetic() {
-            let start = self.pos;
+            let start = self.pos - 1;
             while self.pos < self.input.len() && (self.input[self.pos].is_alphanumeric() || self.input[self.pos] == '_') {
-                self.pos += 1;
+                self.advance();
             }
             let s: String = self.input[start..self.pos].iter().collect();
-            return match s.as_str() {
+            return Some(match s.as_str() {
                 "fn" => Token::Fn, "let" => Token::Let, "if" => Token::If,
                 "else" => Token::Else, "return" => Token::Return,
+                "while" => Token::While, "for" => Token::For,
+                "break" => Token::Break, "continue" => Token::Continue,
+                "import" => Token::Import, "export" => Token::Export, "const" => Token::Const,
                 _ => Token::Ident(s),
-            };
+            });
         }
 
-        if ch.is_digit(10) {
-            let start = self.pos;
-            while self.pos < self.input.len() && self.input[self.pos].is_digit(10) {
-                self.pos += 1;
+        if ch.is_ascii_digit() {
+            let start = self.pos - 1;
+            while self.pos < self.input.len() && self.input[self.pos].is_ascii_digit() {
+                self.advance();
             }
             let s: String = self.input[start..self.pos].iter().collect();
-            return Token::Int(s.parse().unwrap());
+            let v = s.parse().unwrap_or_else(|_| {
+                self.errors.push(Diagnostic::new(format!("整數 {} 超出範圍", s), Span { line, col, len: s.len() }));
+                0
+            });
+            return Some(Token::Int(v));
         }
 
-        self.pos += 1;
-        match ch {
-            '=' => if self.peek() == '=' { self.pos += 1; Token::Eq } else { Token::Assign },
-            '+' => Token::Plus, '-' => Token::Minus, '*' => Token::Mul, '/' => Token::Div,
+        Some(match ch {
+            '=' => if self.peek() == '=' { self.advance(); Token::Eq } else { Token::Assign },
+            '!' => if self.peek() == '=' { self.advance(); Token::Ne } else { Token::Not },
+            '<' => if self.peek() == '=' { self.advance(); Token::Le } else { Token::Lt },
+            '>' => if self.peek() == '=' { self.advance(); Token::Ge } else { Token::Gt },
+            '&' if self.peek() == '&' => { self.advance(); Token::And }
+            '|' if self.peek() == '|' => { self.advance(); Token::Or }
+            '+' => Token::Plus, '-' => Token::Minus, '*' => Token::Mul, '/' => Token::Div, '%' => Token::Mod,
             '(' => Token::LParen, ')' => Token::RParen, '{' => Token::LBrace, '}' => Token::RBrace,
+            '[' => Token::LBracket, ']' => Token::RBracket,
             ';' => Token::Semi, ',' => Token::Comma,
-            _ => panic!("未知的字元: {}", ch),
-        }
+            _ => {
+                self.errors.push(Diagnostic::new(format!("未知的字元: {}", ch), Span { line, col, len: 1 }));
+                return None;
+            }
+        })
+    }
+
+    fn advance(&mut self) -> char {
+        let ch = self.input[self.pos];
+        self.pos += 1;
+        if ch == '\n' { self.line += 1; self.col = 1; } else { self.col += 1; }
+        ch
     }
 
     fn peek(&self) -> char {
@@ -106,152 +283,684 @@ impl Lexer {
     }
 
     fn skip_whitespace(&mut self) {
-        while self.pos < self.input.len() && self.input[self.pos].is_whitespace() { self.pos += 1; }
+        while self.pos < self.input.len() && self.input[self.pos].is_whitespace() { self.advance(); }
     }
 }
 
 // ==========================================================
-// 3. Parser
+// 3. Parser (遇到錯誤時在 ; 或 } 重新同步，一次回報多個錯誤)
 // ==========================================================
 
+// 單元運算子 (- 與 !) 綁得比所有二元運算子都緊
+const UNARY_PREC: i32 = 7;
+
+// 二元運算子的優先順序，數字大的先結合 (fmt 也用來決定哪裡要加括號)
+fn precedence(tok: &Token) -> Option<i32> {
+    match tok {
+        Token::Or => Some(1),
+        Token::And => Some(2),
+        Token::Eq | Token::Ne => Some(3),
+        Token::Lt | Token::Le | Token::Gt | Token::Ge => Some(4),
+        Token::Plus | Token::Minus => Some(5),
+        Token::Mul | Token::Div | Token::Mod => Some(6),
+        _ => None,
+    }
+}
+
+type PResult<T> = Result<T, Diagnostic>;
+
 struct Parser {
     lexer: Lexer,
     cur_tok: Token,
+    cur_span: Span,
+    prev_span: Span, // 上一個 token 的位置，用來決定節點的結尾
+    loop_depth: usize,
+    errors: Vec<Diagnostic>,
+    imports: Vec<(String, Span)>, // import 的模組路徑與位置
+    exports: Vec<String>,         // export fn 的函數名稱
+    block_ends: Vec<Span>,        // 每個區塊結尾 } 的位置，依剖析完的順序 (fmt 用來放置註解)
 }
 
 impl Parser {
     fn new(mut lexer: Lexer) -> Self {
-        let cur_tok = lexer.next_token();
-        Self { lexer, cur_tok }
+        let (cur_tok, cur_span) = lexer.next_token();
+        Self {
+            lexer, cur_tok, cur_span, prev_span: cur_span, loop_depth: 0, errors: Vec::new(),
+            imports: Vec::new(), exports: Vec::new(), block_ends: Vec::new(),
+        }
+    }
+
+    fn next(&mut self) {
+        self.prev_span = self.cur_span;
+        let (tok, span) = self.lexer.next_token();
+        self.cur_tok = tok;
+        self.cur_span = span;
+    }
+
+    fn error_here(&self, expected: &str) -> Diagnostic {
+        Diagnostic::new(format!("預期 {}，但遇到 {}", expected, self.cur_tok), self.cur_span)
+    }
+
+    fn expect(&mut self, tok: Token) -> PResult<()> {
+        if self.cur_tok == tok { self.next(); Ok(()) } else { Err(self.error_here(&tok.to_string())) }
+    }
+
+    fn expect_ident(&mut self, what: &str) -> PResult<String> {
+        if let Token::Ident(n) = &self.cur_tok {
+            let n = n.clone();
+            self.next();
+            Ok(n)
+        } else { Err(self.error_here(what)) }
     }
 
-    fn next(&mut self) { self.cur_tok = self.lexer.next_token(); }
+    // 跳到下一個 ; 或完整的 {...} (吃掉)，或停在外層的 } / fn，讓後面的敘述可以繼續剖析
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.cur_tok {
+                Token::Semi if depth == 0 => { self.next(); return; }
+                Token::LBrace => depth += 1,
+                Token::RBrace if depth == 0 => return,
+                Token::RBrace => {
+                    depth -= 1;
+                    if depth == 0 { self.next(); return; }
+                }
+                Token::Fn | Token::EOF => return,
+                _ => {}
+            }
+            self.next();
+        }
+    }
+
+    fn parse_program(&mut self) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+        let mut stmts = Vec::new();
+        while self.cur_tok != Token::EOF {
+            let result = match self.cur_tok {
+                Token::Fn => self.parse_function(),
+                Token::Export => {
+                    self.next();
+                    self.parse_function().inspect(|f| {
+                        if let StmtKind::FuncDecl(name, ..) = &f.kind { self.exports.push(name.clone()); }
+                    })
+                }
+                Token::Import => match self.parse_import() {
+                    Ok(()) => continue,
+                    Err(e) => Err(e),
+                },
+                // 全域變數與常數
+                Token::Let | Token::Const => self.parse_global(),
+                _ => {
+                    self.errors.push(self.error_here("'fn'、'let'、'const'、'export' 或 'import'"));
+                    self.skip_to_fn();
+                    continue;
+                }
+            };
+            match result {
+                Ok(f) => stmts.push(f),
+                Err(e) => { self.errors.push(e); self.next(); self.skip_to_fn(); }
+            }
+        }
+        let mut errors = std::mem::take(&mut self.lexer.errors);
+        errors.append(&mut self.errors);
+        errors.sort_by_key(|e| (e.span.line, e.span.col));
+        if errors.is_empty() { Ok(stmts) } else { Err(errors) }
+    }
 
-    fn parse_program(&mut self) -> Vec<Stmt> {
+    // REPL 輸入的一串敘述 (不在任何函數裡)
+    fn parse_stmts(&mut self) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
         let mut stmts = Vec::new();
         while self.cur_tok != Token::EOF {
-            if self.cur_tok == Token::Fn { stmts.push(self.parse_function()); }
-            else { self.next(); }
+            match self.parse_stmt() {
+                Ok(s) => stmts.push(s),
+                Err(e) => {
+                    self.errors.push(e);
+                    self.synchronize();
+                    // 外層多出來的 } 或 fn 不會被 synchronize 吃掉，跳過免得卡住
+                    if matches!(self.cur_tok, Token::RBrace | Token::Fn) { self.next(); }
+                }
+            }
         }
-        stmts
+        let mut errors = std::mem::take(&mut self.lexer.errors);
+        errors.append(&mut self.errors);
+        errors.sort_by_key(|e| (e.span.line, e.span.col));
+        if errors.is_empty() { Ok(stmts) } else { Err(errors) }
+    }
+
+    fn skip_to_fn(&mut self) {
+        while !matches!(self.cur_tok, Token::Fn | Token::Export | Token::Import | Token::Const | Token::EOF) { self.next(); }
     }
 
-    fn parse_function(&mut self) -> Stmt {
-        self.next(); // fn
-        let name = if let Token::Ident(n) = &self.cur_tok { n.clone() } else { panic!("預期函數名稱") };
-        self.next(); self.next(); // (
+    // let name = expr; 或 const NAME = expr;
+    fn parse_global(&mut self) -> PResult<Stmt> {
+        let start = self.cur_span;
+        let is_const = self.cur_tok == Token::Const;
+        self.next();
+        let name = self.expect_ident(if is_const { "常數名稱" } else { "變數名稱" })?;
+        self.expect(Token::Assign)?;
+        let expr = self.parse_expr(0)?;
+        self.expect(Token::Semi)?;
+        let kind = if is_const { StmtKind::ConstDecl(name, expr) } else { StmtKind::VarDecl(name, expr) };
+        Ok(Stmt { kind, span: start.to(self.prev_span) })
+    }
+
+    // import 'path';
+    fn parse_import(&mut self) -> PResult<()> {
+        self.next(); // import
+        let span = self.cur_span;
+        let Token::Str(path) = self.cur_tok.clone() else { return Err(self.error_here("模組路徑字串")) };
+        self.next();
+        self.expect(Token::Semi)?;
+        self.imports.push((path, span));
+        Ok(())
+    }
+
+    fn parse_function(&mut self) -> PResult<Stmt> {
+        let start = self.cur_span;
+        self.expect(Token::Fn)?;
+        let name = self.expect_ident("函數名稱")?;
+        self.expect(Token::LParen)?;
         let mut params = Vec::new();
         while self.cur_tok != Token::RParen {
-            if let Token::Ident(p) = &self.cur_tok { params.push(p.clone()); }
-            self.next();
-            if self.cur_tok == Token::Comma { self.next(); }
+            params.push(self.expect_ident("參數名稱")?);
+            if self.cur_tok == Token::Comma { self.next(); } else if self.cur_tok != Token::RParen { return Err(self.error_here("',' 或 ')'")); }
         }
         self.next(); // )
-        Stmt::FuncDecl(name, params, self.parse_block())
+        let span = start.to(self.prev_span);
+        Ok(Stmt { kind: StmtKind::FuncDecl(name, params, self.parse_block()?), span })
     }
 
-    fn parse_block(&mut self) -> Vec<Stmt> {
-        self.next(); // {
+    fn parse_block(&mut self) -> PResult<Vec<Stmt>> {
+        self.expect(Token::LBrace)?;
         let mut stmts = Vec::new();
-        while self.cur_tok != Token::RBrace && self.cur_tok != Token::EOF { stmts.push(self.parse_stmt()); }
-        self.next(); // }
-        stmts
+        while self.cur_tok != Token::RBrace && self.cur_tok != Token::EOF {
+            match self.parse_stmt() {
+                Ok(s) => stmts.push(s),
+                Err(e) => { self.errors.push(e); self.synchronize(); }
+            }
+        }
+        self.expect(Token::RBrace)?;
+        self.block_ends.push(self.prev_span);
+        Ok(stmts)
     }
 
-    fn parse_stmt(&mut self) -> Stmt {
-        match &self.cur_tok {
-            Token::Let => {
-                self.next();
-                let name = if let Token::Ident(n) = &self.cur_tok { n.clone() } else { panic!("預期變數名") };
-                self.next(); self.next(); // =
-                let expr = self.parse_expr(0);
-                if self.cur_tok == Token::Semi { self.next(); }
-                Stmt::VarDecl(name, expr)
-            }
+    // let / 指定 / 表達式，不吃結尾的分號 (for 的 init 與 step 也用這個)
+    fn parse_simple_stmt(&mut self) -> PResult<Stmt> {
+        let start = self.cur_span;
+        if self.cur_tok == Token::Let {
+            self.next();
+            let name = self.expect_ident("變數名稱")?;
+            self.expect(Token::Assign)?;
+            let expr = self.parse_expr(0)?;
+            return Ok(Stmt { kind: StmtKind::VarDecl(name, expr), span: start.to(self.prev_span) });
+        }
+        let expr = self.parse_expr(0)?;
+        if self.cur_tok == Token::Assign {
+            self.next(); // =
+            let value = self.parse_expr(0)?;
+            let kind = match expr.kind {
+                ExprKind::Variable(name) => StmtKind::Assign(name, value),
+                ExprKind::Index(base, index) => StmtKind::IndexAssign(*base, *index, value),
+                _ => return Err(Diagnostic::new("無效的指定目標", expr.span)),
+            };
+            return Ok(Stmt { kind, span: start.to(self.prev_span) });
+        }
+        Ok(Stmt { kind: StmtKind::ExprStmt(expr), span: start.to(self.prev_span) })
+    }
+
+    fn parse_stmt(&mut self) -> PResult<Stmt> {
+        let start = self.cur_span;
+        let kind = match &self.cur_tok {
             Token::If => {
-                self.next(); self.next(); // if (
-                let cond = self.parse_expr(0);
-                self.next(); // )
-                let then_part = self.parse_block();
+                self.next();
+                self.expect(Token::LParen)?;
+                let cond = self.parse_expr(0)?;
+                self.expect(Token::RParen)?;
+                let then_part = self.parse_block()?;
                 let mut else_part = None;
-                if self.cur_tok == Token::Else { self.next(); else_part = Some(self.parse_block()); }
-                Stmt::If(cond, then_part, else_part)
+                if self.cur_tok == Token::Else { self.next(); else_part = Some(self.parse_block()?); }
+                StmtKind::If(cond, then_part, else_part)
+            }
+            Token::While => {
+                self.next();
+                self.expect(Token::LParen)?;
+                let cond = self.parse_expr(0)?;
+                self.expect(Token::RParen)?;
+                StmtKind::While(cond, self.parse_loop_body()?)
+            }
+            Token::For => {
+                self.next();
+                self.expect(Token::LParen)?;
+                let init = if self.cur_tok == Token::Semi { None } else { Some(Box::new(self.parse_simple_stmt()?)) };
+                self.expect(Token::Semi)?;
+                let cond = if self.cur_tok == Token::Semi { None } else { Some(self.parse_expr(0)?) };
+                self.expect(Token::Semi)?;
+                let step = if self.cur_tok == Token::RParen { None } else { Some(Box::new(self.parse_simple_stmt()?)) };
+                self.expect(Token::RParen)?;
+                StmtKind::For(init, cond, step, self.parse_loop_body()?)
+            }
+            Token::Break | Token::Continue => {
+                let kind = if self.cur_tok == Token::Break { StmtKind::Break } else { StmtKind::Continue };
+                if self.loop_depth == 0 {
+                    return Err(Diagnostic::new(format!("{} 不在迴圈內", self.cur_tok), self.cur_span));
+                }
+                self.next();
+                self.expect(Token::Semi)?;
+                kind
             }
             Token::Return => {
                 self.next();
-                let expr = self.parse_expr(0);
-                if self.cur_tok == Token::Semi { self.next(); }
-                Stmt::Return(expr)
+                let expr = self.parse_expr(0)?;
+                self.expect(Token::Semi)?;
+                StmtKind::Return(expr)
             }
             _ => {
-                let expr = self.parse_expr(0);
-                if self.cur_tok == Token::Semi { self.next(); }
-                Stmt::ExprStmt(expr)
+                let stmt = self.parse_simple_stmt()?;
+                self.expect(Token::Semi)?;
+                return Ok(stmt);
             }
-        }
+        };
+        Ok(Stmt { kind, span: start.to(self.prev_span) })
     }
 
-    fn parse_expr(&mut self, prec: i32) -> Expr {
-        let mut left = match &self.cur_tok {
-            Token::Int(v) => { let e = Expr::Number(*v); self.next(); e }
-            Token::Str(s) => { let e = Expr::Str(s.clone()); self.next(); e } // 支援字串表達式
+    fn parse_loop_body(&mut self) -> PResult<Vec<Stmt>> {
+        self.loop_depth += 1;
+        let body = self.parse_block();
+        self.loop_depth -= 1;
+        body
+    }
+
+    fn parse_expr(&mut self, prec: i32) -> PResult<Expr> {
+        let start = self.cur_span;
+        let kind = match &self.cur_tok {
+            Token::Int(v) => { let e = ExprKind::Number(*v); self.next(); e }
+            Token::Str(s) => { let e = ExprKind::Str(s.clone()); self.next(); e } // 支援字串表達式
             Token::Ident(name) => {
                 let n = name.clone();
                 self.next();
                 if self.cur_tok == Token::LParen {
                     self.next();
-                    let mut args = Vec::new();
-                    while self.cur_tok != Token::RParen {
-                        args.push(self.parse_expr(0));
-                        if self.cur_tok == Token::Comma { self.next(); }
-                    }
-                    self.next();
-                    Expr::Call(n, args)
-                } else { Expr::Variable(n) }
+                    ExprKind::Call(n, self.parse_list(Token::RParen)?)
+                } else { ExprKind::Variable(n) }
+            }
+            Token::LParen => {
+                self.next();
+                let e = self.parse_expr(0)?;
+                self.expect(Token::RParen)?;
+                e.kind
             }
-            _ => panic!("無效的表達式: {:?}", self.cur_tok),
+            Token::LBracket => {
+                self.next();
+                ExprKind::Array(self.parse_list(Token::RBracket)?)
+            }
+            Token::Minus | Token::Not => {
+                let op = self.cur_tok.clone();
+                self.next();
+                ExprKind::UnaryOp(op, Box::new(self.parse_expr(UNARY_PREC)?))
+            }
+            _ => return Err(self.error_here("表達式")),
         };
+        let mut left = Expr { kind, span: start.to(self.prev_span) };
+
+        // 後置的索引 a[i][j]
+        while self.cur_tok == Token::LBracket {
+            self.next();
+            let index = self.parse_expr(0)?;
+            self.expect(Token::RBracket)?;
+            left = Expr { kind: ExprKind::Index(Box::new(left), Box::new(index)), span: start.to(self.prev_span) };
+        }
 
-        while let Some(p) = self.get_prec(&self.cur_tok) {
+        while let Some(p) = precedence(&self.cur_tok) {
             if p < prec { break; }
             let op = self.cur_tok.clone();
             self.next();
-            let right = self.parse_expr(p + 1);
-            left = Expr::BinaryOp(Box::new(left), op, Box::new(right));
+            let right = self.parse_expr(p + 1)?;
+            let span = left.span.to(right.span);
+            left = Expr { kind: ExprKind::BinaryOp(Box::new(left), op, Box::new(right)), span };
         }
-        left
+        Ok(left)
     }
 
-    fn get_prec(&self, tok: &Token) -> Option<i32> {
-        match tok {
-            Token::Eq => Some(1),
-            Token::Plus | Token::Minus => Some(2),
-            Token::Mul | Token::Div => Some(3),
-            _ => None,
+    // 以逗號分隔的表達式串列，直到 close 為止 (呼叫參數與陣列常數共用)
+    fn parse_list(&mut self, close: Token) -> PResult<Vec<Expr>> {
+        let mut items = Vec::new();
+        while self.cur_tok != close {
+            items.push(self.parse_expr(0)?);
+            if self.cur_tok == Token::Comma { self.next(); } else if self.cur_tok != close { return Err(self.error_here(&format!("',' 或 {}", close))); }
         }
+        self.next();
+        Ok(items)
     }
 }
 
 // ==========================================================
-// 4. IR 與 VM (支援多參數與字串值)
+// 4. 語意分析 (名稱解析、參數個數、重複定義、區塊作用域)
 // ==========================================================
 
+struct Resolver {
+    funcs: HashMap<String, (Option<usize>, Option<Span>)>, // 參數個數、定義位置 (內建函數沒有位置)
+    scopes: Vec<Vec<String>>, // scopes[0] 是全域變數
+    consts: Vec<String>,
+    errors: Vec<Diagnostic>,
+    require_main: bool,
+}
+
+impl Resolver {
+    fn new() -> Self {
+        let funcs = NATIVES.iter().map(|(n, arity, _)| (n.to_string(), (*arity, None))).collect();
+        Self { funcs, scopes: vec![Vec::new()], consts: Vec::new(), errors: Vec::new(), require_main: true }
+    }
+
+    // 已經在別處定義的函數 (名稱、參數個數) 也可以呼叫：REPL 先前的輸入或 import 的模組。
+    // 這些函數不能再定義一次，呼叫端要先檢查
+    fn with_functions(known: Vec<(String, usize)>, require_main: bool) -> Self {
+        let mut resolver = Self::new();
+        for (name, arity) in known { resolver.funcs.insert(name, (Some(arity), None)); }
+        resolver.require_main = require_main;
+        resolver
+    }
+
+    // REPL 用 :load 載入的全域變數 (名稱、是不是常數)，函數裡都看得到
+    fn with_globals(mut self, globals: &[(String, bool)]) -> Self {
+        for (name, is_const) in globals {
+            self.scopes[0].push(name.clone());
+            if *is_const { self.consts.push(name.clone()); }
+        }
+        self
+    }
+
+    // 在任何程式碼執行之前，一次找出所有語意錯誤
+    fn resolve_program(mut self, program: &[Stmt]) -> Result<(), Vec<Diagnostic>> {
+        for stmt in program {
+            if let StmtKind::FuncDecl(name, params, _) = &stmt.kind {
+                match self.funcs.get(name) {
+                    Some((_, None)) => {
+                        self.errors.push(Diagnostic::new(format!("函數 '{}' 與內建函數同名", name), stmt.span));
+                    }
+                    Some((_, Some(first))) => {
+                        let msg = format!("函數 '{}' 重複定義 (第一次定義在第 {} 行)", name, first.line);
+                        self.errors.push(Diagnostic::new(msg, stmt.span));
+                    }
+                    None => { self.funcs.insert(name.clone(), (Some(params.len()), Some(stmt.span))); }
+                }
+            }
+        }
+        if self.require_main && !self.funcs.contains_key("main") {
+            self.errors.push(Diagnostic::new("找不到 main 函數", Span { line: 1, col: 1, len: 1 }));
+        }
+
+        // 全域變數的初始值只能用到前面宣告過的全域變數，函數裡則全部都看得到
+        for stmt in program {
+            if let StmtKind::VarDecl(name, expr) | StmtKind::ConstDecl(name, expr) = &stmt.kind {
+                self.resolve_expr(expr);
+                if self.scopes[0].contains(name) {
+                    self.errors.push(Diagnostic::new(format!("全域變數 '{}' 重複定義", name), stmt.span));
+                    continue;
+                }
+                self.scopes[0].push(name.clone());
+                if matches!(stmt.kind, StmtKind::ConstDecl(..)) { self.consts.push(name.clone()); }
+            }
+        }
+
+        for stmt in program {
+            if let StmtKind::FuncDecl(_, params, body) = &stmt.kind {
+                let mut scope: Vec<String> = Vec::new();
+                for p in params {
+                    if scope.contains(p) {
+                        self.errors.push(Diagnostic::new(format!("參數 '{}' 重複", p), stmt.span));
+                    }
+                    scope.push(p.clone());
+                }
+                self.scopes.push(scope);
+                self.resolve_block(body);
+                self.scopes.pop();
+            }
+        }
+
+        let mut errors = self.errors;
+        errors.sort_by_key(|e| (e.span.line, e.span.col));
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    fn resolve_block(&mut self, stmts: &[Stmt]) {
+        self.scopes.push(Vec::new());
+        for s in stmts { self.resolve_stmt(s); }
+        self.scopes.pop();
+    }
+
+    fn resolve_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::VarDecl(name, expr) => {
+                self.resolve_expr(expr);
+                self.scopes.last_mut().unwrap().push(name.clone());
+            }
+            StmtKind::Assign(name, expr) => {
+                self.resolve_expr(expr);
+                if !self.is_defined(name) {
+                    self.errors.push(Diagnostic::new(format!("指定給未定義的變數 '{}'", name), stmt.span));
+                } else if self.is_const(name) {
+                    self.errors.push(Diagnostic::new(format!("不能指定給常數 '{}'", name), stmt.span));
+                }
+            }
+            StmtKind::IndexAssign(base, index, value) => {
+                self.resolve_expr(base);
+                self.resolve_expr(index);
+                self.resolve_expr(value);
+            }
+            StmtKind::If(cond, then_part, else_part) => {
+                self.resolve_expr(cond);
+                self.resolve_block(then_part);
+                if let Some(else_stmts) = else_part { self.resolve_block(else_stmts); }
+            }
+            StmtKind::While(cond, body) => {
+                self.resolve_expr(cond);
+                self.resolve_block(body);
+            }
+            StmtKind::For(init, cond, step, body) => {
+                // init 宣告的變數只在整個 for 之內可見
+                self.scopes.push(Vec::new());
+                if let Some(init) = init { self.resolve_stmt(init); }
+                if let Some(cond) = cond { self.resolve_expr(cond); }
+                self.resolve_block(body);
+                if let Some(step) = step { self.resolve_stmt(step); }
+                self.scopes.pop();
+            }
+            StmtKind::Return(expr) | StmtKind::ExprStmt(expr) => self.resolve_expr(expr),
+            StmtKind::Break | StmtKind::Continue | StmtKind::FuncDecl(..) | StmtKind::ConstDecl(..) => {}
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Number(_) | ExprKind::Str(_) => {}
+            ExprKind::Variable(name) => {
+                if !self.is_defined(name) {
+                    self.errors.push(Diagnostic::new(format!("未定義的變數 '{}'", name), expr.span));
+                }
+            }
+            ExprKind::Array(items) => {
+                for item in items { self.resolve_expr(item); }
+            }
+            ExprKind::UnaryOp(_, e) => self.resolve_expr(e),
+            ExprKind::BinaryOp(l, _, r) | ExprKind::Index(l, r) => {
+                self.resolve_expr(l);
+                self.resolve_expr(r);
+            }
+            ExprKind::Call(name, args) => {
+                for a in args { self.resolve_expr(a); }
+                match self.funcs.get(name) {
+                    None => self.errors.push(Diagnostic::new(format!("未定義的函數 '{}'", name), expr.span)),
+                    Some((Some(arity), _)) if *arity != args.len() => {
+                        let msg = format!("函數 '{}' 需要 {} 個參數，但傳入了 {} 個", name, arity, args.len());
+                        self.errors.push(Diagnostic::new(msg, expr.span));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.iter().any(|n| n == name))
+    }
+
+    // 沒有被區域變數遮蔽的常數
+    fn is_const(&self, name: &str) -> bool {
+        self.consts.iter().any(|n| n == name) && !self.scopes[1..].iter().any(|scope| scope.iter().any(|n| n == name))
+    }
+}
+
+// 區塊作用域：let 會遮蔽 (shadow) 外層同名變數，指定則寫回最近的那一個。
+// VM 的 locals 是每次呼叫一張扁平的表，所以在產生 IR 之前把遮蔽外層的 let 改名成 name.N。
+// 全域變數是最外層，遮蔽全域變數的參數也要改名，IR 裡沒改名的全域變數名稱才一定是全域變數。
+struct ScopeRenamer {
+    scopes: Vec<Vec<(String, String)>>, // (原始名稱, IR 中的名稱)
+    counts: HashMap<String, usize>,
+}
+
+impl ScopeRenamer {
+    fn rename_function(globals: &[String], params: &mut [String], body: &mut [Stmt]) {
+        let globals = globals.iter().map(|g| (g.clone(), g.clone())).collect();
+        let mut renamer = Self { scopes: vec![globals, Vec::new()], counts: HashMap::new() };
+        for p in params { renamer.declare(p); }
+        renamer.block(body);
+    }
+
+    // 在目前的作用域宣告變數，遮蔽外層同名變數時改名
+    fn declare(&mut self, name: &mut String) {
+        let ir_name = if self.lookup(name).is_some() {
+            let n = self.counts.entry(name.clone()).or_insert(0);
+            *n += 1;
+            format!("{}.{}", name, n)
+        } else { name.clone() };
+        self.scopes.last_mut().unwrap().push((name.clone(), ir_name.clone()));
+        *name = ir_name;
+    }
+
+    fn block(&mut self, stmts: &mut [Stmt]) {
+        self.scopes.push(Vec::new());
+        for s in stmts { self.stmt(s); }
+        self.scopes.pop();
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        match &mut stmt.kind {
+            StmtKind::VarDecl(name, expr) => {
+                self.expr(expr);
+                self.declare(name);
+            }
+            StmtKind::Assign(name, expr) => {
+                self.expr(expr);
+                if let Some(ir_name) = self.lookup(name) { *name = ir_name; }
+            }
+            StmtKind::IndexAssign(base, index, value) => {
+                self.expr(base);
+                self.expr(index);
+                self.expr(value);
+            }
+            StmtKind::If(cond, then_part, else_part) => {
+                self.expr(cond);
+                self.block(then_part);
+                if let Some(else_stmts) = else_part { self.block(else_stmts); }
+            }
+            StmtKind::While(cond, body) => {
+                self.expr(cond);
+                self.block(body);
+            }
+            StmtKind::For(init, cond, step, body) => {
+                self.scopes.push(Vec::new());
+                if let Some(init) = init { self.stmt(init); }
+                if let Some(cond) = cond { self.expr(cond); }
+                self.block(body);
+                if let Some(step) = step { self.stmt(step); }
+                self.scopes.pop();
+            }
+            StmtKind::Return(expr) | StmtKind::ExprStmt(expr) => self.expr(expr),
+            StmtKind::Break | StmtKind::Continue | StmtKind::FuncDecl(..) | StmtKind::ConstDecl(..) => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) {
+        match &mut expr.kind {
+            ExprKind::Number(_) | ExprKind::Str(_) => {}
+            ExprKind::Variable(name) => {
+                if let Some(ir_name) = self.lookup(name) { *name = ir_name; }
+            }
+            ExprKind::UnaryOp(_, e) => self.expr(e),
+            ExprKind::BinaryOp(l, _, r) | ExprKind::Index(l, r) => {
+                self.expr(l);
+                self.expr(r);
+            }
+            ExprKind::Call(_, items) | ExprKind::Array(items) => {
+                for a in items { self.expr(a); }
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<String> {
+        self.scopes.iter().rev().flat_map(|scope| scope.iter().rev()).find(|(n, _)| n == name).map(|(_, ir)| ir.clone())
+    }
+}
+
+// ==========================================================
+// 5. IR 與 VM (支援多參數與字串值)
+// ==========================================================
+
+type ArrayRef = Rc<RefCell<Vec<Value>>>;
+
 #[derive(Debug, Clone)]
 enum Value {
     Int(i32),
     Str(String),
+    Array(ArrayRef), // 陣列是參考語意，push 與 a[i] = v 會影響所有持有者
 }
 
-#[derive(Debug, Clone)]
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "整數",
+            Value::Str(_) => "字串",
+            Value::Array(_) => "陣列",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Array(items) => {
+                let items: Vec<String> = items.borrow().iter().map(|v| v.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 enum IR {
     LoadConst(String, i32),
     LoadStr(String, String), // 新增
     LoadVar(String, String),
     StoreVar(String, String),
+    LoadGlobal(String, String),  // t = 全域變數
+    StoreGlobal(String, String), // 全域變數 = t
     Add(String, String, String),
     Sub(String, String, String),
     Mul(String, String, String),
+    Div(String, String, String),
+    Mod(String, String, String),
     Eq(String, String, String),
+    Ne(String, String, String),
+    Lt(String, String, String),
+    Le(String, String, String),
+    Gt(String, String, String),
+    Ge(String, String, String),
+    Neg(String, String),
+    Not(String, String),
+    NewArray(String, Vec<String>),
+    IndexLoad(String, String, String),  // t = a[i]
+    IndexStore(String, String, String), // a[i] = t
     Call(String, Vec<String>, String),
     Return(String),
     IfFalse(String, usize),
@@ -259,43 +968,331 @@ enum IR {
     Label,
 }
 
+// IR 的文字格式，dump_ir 與 --emit cfg 共用
+impl fmt::Display for IR {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IR::LoadConst(t, v) => write!(f, "LOAD_CONST {} {}", t, v),
+            IR::LoadStr(t, s) => write!(f, "LOAD_STR {} \"{}\"", t, irtext::escape(s)),
+            IR::LoadVar(t, n) => write!(f, "LOAD_VAR {} {}", t, n),
+            IR::StoreVar(n, t) => write!(f, "STORE_VAR {} {}", n, t),
+            IR::LoadGlobal(t, g) => write!(f, "LOAD_GLOBAL {} {}", t, g),
+            IR::StoreGlobal(g, t) => write!(f, "STORE_GLOBAL {} {}", g, t),
+            IR::Add(t, l, r) => write!(f, "ADD {} {} {}", t, l, r),
+            IR::Sub(t, l, r) => write!(f, "SUB {} {} {}", t, l, r),
+            IR::Mul(t, l, r) => write!(f, "MUL {} {} {}", t, l, r),
+            IR::Div(t, l, r) => write!(f, "DIV {} {} {}", t, l, r),
+            IR::Mod(t, l, r) => write!(f, "MOD {} {} {}", t, l, r),
+            IR::Eq(t, l, r) => write!(f, "EQ {} {} {}", t, l, r),
+            IR::Ne(t, l, r) => write!(f, "NE {} {} {}", t, l, r),
+            IR::Lt(t, l, r) => write!(f, "LT {} {} {}", t, l, r),
+            IR::Le(t, l, r) => write!(f, "LE {} {} {}", t, l, r),
+            IR::Gt(t, l, r) => write!(f, "GT {} {} {}", t, l, r),
+            IR::Ge(t, l, r) => write!(f, "GE {} {} {}", t, l, r),
+            IR::Neg(t, s) => write!(f, "NEG {} {}", t, s),
+            IR::Not(t, s) => write!(f, "NOT {} {}", t, s),
+            IR::NewArray(t, items) => write!(f, "NEW_ARRAY {} {}", t, items.join(" ")),
+            IR::IndexLoad(t, a, i) => write!(f, "INDEX_LOAD {} {} {}", t, a, i),
+            IR::IndexStore(a, i, t) => write!(f, "INDEX_STORE {} {} {}", a, i, t),
+            IR::Call(func, args, t) => write!(f, "CALL {} {} {}", func, t, args.join(" ")),
+            IR::Return(t) => write!(f, "RETURN {}", t),
+            IR::IfFalse(t, target) => write!(f, "IFFALSE {} {}", t, target),
+            IR::Goto(target) => write!(f, "GOTO {}", target),
+            IR::Label => write!(f, "LABEL"),
+        }
+    }
+}
+
+impl IR {
+    // 指令名稱，與文字格式相同
+    fn opcode(&self) -> &'static str {
+        match self {
+            IR::LoadConst(..) => "LOAD_CONST",
+            IR::LoadStr(..) => "LOAD_STR",
+            IR::LoadVar(..) => "LOAD_VAR",
+            IR::StoreVar(..) => "STORE_VAR",
+            IR::LoadGlobal(..) => "LOAD_GLOBAL",
+            IR::StoreGlobal(..) => "STORE_GLOBAL",
+            IR::Add(..) => "ADD",
+            IR::Sub(..) => "SUB",
+            IR::Mul(..) => "MUL",
+            IR::Div(..) => "DIV",
+            IR::Mod(..) => "MOD",
+            IR::Eq(..) => "EQ",
+            IR::Ne(..) => "NE",
+            IR::Lt(..) => "LT",
+            IR::Le(..) => "LE",
+            IR::Gt(..) => "GT",
+            IR::Ge(..) => "GE",
+            IR::Neg(..) => "NEG",
+            IR::Not(..) => "NOT",
+            IR::NewArray(..) => "NEW_ARRAY",
+            IR::IndexLoad(..) => "INDEX_LOAD",
+            IR::IndexStore(..) => "INDEX_STORE",
+            IR::Call(..) => "CALL",
+            IR::Return(..) => "RETURN",
+            IR::IfFalse(..) => "IFFALSE",
+            IR::Goto(..) => "GOTO",
+            IR::Label => "LABEL",
+        }
+    }
+}
+
+// 迴圈中尚未回填目標的 break / continue 位置
+struct LoopCtx {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+// 呼叫鏈的一層：(函數, IR 位置, 原始碼位置 (檔案, 行號))
+type CallSite = (String, usize, Option<(String, usize)>);
+
+// 執行期錯誤：記錄發生在哪個函數的第幾個 IR 指令，VM 另外補上當時的呼叫鏈
+#[derive(Debug)]
+struct RuntimeError {
+    func: String,
+    ip: usize,
+    msg: String,
+    backtrace: Vec<CallSite>,
+}
+
+impl RuntimeError {
+    fn new(func: &str, ip: usize, msg: &str) -> Self {
+        Self { func: func.to_string(), ip, msg: msg.to_string(), backtrace: Vec::new() }
+    }
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "執行期錯誤: {} (於函數 {} 的 IR #{})", self.msg, self.func, self.ip)?;
+        if self.backtrace.is_empty() { return Ok(()); }
+        write!(f, "\n呼叫堆疊 (最內層在前):")?;
+        // 無窮遞迴時堆疊很深，只印頭尾
+        let n = self.backtrace.len();
+        for (i, (func, ip, line)) in self.backtrace.iter().enumerate() {
+            if n > 20 && i == 10 { write!(f, "\n  ... 省略 {} 層 ...", n - 15)?; }
+            if n > 20 && (10..n - 5).contains(&i) { continue; }
+            write!(f, "\n  #{} {} (IR #{}", i, func, ip)?;
+            if let Some((file, line)) = line { write!(f, ", {} 第 {} 行", file, line)?; }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+// == 的語意：整數與字串比內容，陣列比是否為同一個，不同型別一律不相等
+fn values_equal(l: &Value, r: &Value) -> bool {
+    match (l, r) {
+        (Value::Int(lv), Value::Int(rv)) => lv == rv,
+        (Value::Str(lv), Value::Str(rv)) => lv == rv,
+        (Value::Array(lv), Value::Array(rv)) => Rc::ptr_eq(lv, rv),
+        _ => false,
+    }
+}
+
+// 檢查 a[i] 的 a 是陣列且 i 在範圍內，回傳陣列與索引
+fn array_slot(func: &str, ip: usize, arr: &Value, idx: &Value) -> Result<(ArrayRef, usize), RuntimeError> {
+    let items = match arr {
+        Value::Array(items) => items.clone(),
+        _ => return Err(RuntimeError::new(func, ip, "只能對陣列使用索引")),
+    };
+    let i = match idx {
+        Value::Int(i) => *i,
+        _ => return Err(RuntimeError::new(func, ip, "陣列索引必須是整數")),
+    };
+    let len = items.borrow().len();
+    if i < 0 || i as usize >= len {
+        return Err(RuntimeError::new(func, ip, &format!("索引 {} 超出範圍 (陣列長度 {})", i, len)));
+    }
+    Ok((items, i as usize))
+}
+
+// 二元算術與比較：整數之間全部支援，字串之間支援 + (串接) 與大小比較
+fn binary_op(ir: &IR, l: &Value, r: &Value) -> Result<Value, String> {
+    let bool_val = |b: bool| Value::Int(if b { 1 } else { 0 });
+    match (ir, l, r) {
+        (IR::Add(..), Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_add(*b))),
+        (IR::Sub(..), Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_sub(*b))),
+        (IR::Mul(..), Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_mul(*b))),
+        (IR::Div(..) | IR::Mod(..), Value::Int(_), Value::Int(0)) => Err("除以零".to_string()),
+        (IR::Div(..), Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_div(*b))),
+        (IR::Mod(..), Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_rem(*b))),
+        (IR::Lt(..), Value::Int(a), Value::Int(b)) => Ok(bool_val(a < b)),
+        (IR::Le(..), Value::Int(a), Value::Int(b)) => Ok(bool_val(a <= b)),
+        (IR::Gt(..), Value::Int(a), Value::Int(b)) => Ok(bool_val(a > b)),
+        (IR::Ge(..), Value::Int(a), Value::Int(b)) => Ok(bool_val(a >= b)),
+        (IR::Add(..), Value::Str(a), Value::Str(b)) => Ok(Value::Str(format!("{}{}", a, b))),
+        (IR::Lt(..), Value::Str(a), Value::Str(b)) => Ok(bool_val(a < b)),
+        (IR::Le(..), Value::Str(a), Value::Str(b)) => Ok(bool_val(a <= b)),
+        (IR::Gt(..), Value::Str(a), Value::Str(b)) => Ok(bool_val(a > b)),
+        (IR::Ge(..), Value::Str(a), Value::Str(b)) => Ok(bool_val(a >= b)),
+        _ => {
+            let op = match ir {
+                IR::Add(..) => "+", IR::Sub(..) => "-", IR::Mul(..) => "*", IR::Div(..) => "/", IR::Mod(..) => "%",
+                IR::Lt(..) => "<", IR::Le(..) => "<=", IR::Gt(..) => ">", _ => ">=",
+            };
+            Err(format!("型別錯誤: 不能對{}與{}使用 '{}'", l.type_name(), r.type_name(), op))
+        }
+    }
+}
+
+// 預設的最大呼叫深度，可用 --max-depth 調整
+const DEFAULT_MAX_DEPTH: usize = 10000;
+
+// 設定全域變數初始值的函數，VM::run 在 main 之前執行
+const INIT: &str = "__init__";
+
 struct VM {
     functions: HashMap<String, (Vec<String>, Vec<IR>)>,
+    natives: HashMap<&'static str, NativeFn>,
+    // 每個 IR 指令對應的原始碼行號；從 .ir 載入或最佳化過的函數沒有
+    lines: HashMap<String, Vec<usize>>,
+    // 有行號的函數是從哪個檔案編譯的；import 的模組行號各自從 1 開始
+    files: HashMap<String, String>,
+    max_depth: usize,
+    globals: RefCell<HashMap<String, Value>>,
+    // 編譯過的程式 (所有模組) 宣告的全域變數
+    global_names: HashSet<String>,
+    // 產生 IR 時的行號表與目前所在的敘述行號
+    line_table: RefCell<Vec<usize>>,
+    cur_line: Cell<usize>,
+}
+
+// 呼叫堆疊上的一層：呼叫 p0 函數時推入，RETURN 時彈出
+struct Frame<'a> {
+    func: &'a str,
+    code: &'a [IR],
+    ip: usize,
+    locals: HashMap<String, Value>,
+    temps: HashMap<String, Value>,
 }
 
 impl VM {
-    fn new() -> Self { Self { functions: HashMap::new() } }
+    fn new() -> Self {
+        let natives = NATIVES.iter().map(|(name, _, f)| (*name, *f)).collect();
+        Self {
+            functions: HashMap::new(), natives, lines: HashMap::new(), files: HashMap::new(), max_depth: DEFAULT_MAX_DEPTH,
+            globals: RefCell::new(HashMap::new()), global_names: HashSet::new(),
+            line_table: RefCell::new(Vec::new()), cur_line: Cell::new(0),
+        }
+    }
 
-    fn compile(&mut self, stmts: Vec<Stmt>) {
+    // file 是原始碼的路徑 (REPL 是 <repl>)，執行期錯誤與除錯器用它顯示位置
+    fn compile(&mut self, stmts: Vec<Stmt>, file: &str) {
+        // 先前編譯的全域變數要留著：REPL 之後定義的函數還會用到 (能不能用由 Resolver 檢查)
+        self.global_names.extend(stmts.iter().filter_map(|s| match &s.kind {
+            StmtKind::VarDecl(name, _) | StmtKind::ConstDecl(name, _) => Some(name.clone()),
+            _ => None,
+        }));
+        let globals: Vec<String> = self.global_names.iter().cloned().collect();
+        let mut inits = Vec::new();
         for stmt in stmts {
-            if let Stmt::FuncDecl(name, params, body) = stmt {
-                let mut irs = Vec::new();
-                let mut t_idx = 0;
-                let mut l_idx = 0;
-                for s in body { self.gen_stmt(&s, &mut irs, &mut t_idx, &mut l_idx); }
-                self.functions.insert(name, (params, irs));
+            match stmt.kind {
+                StmtKind::FuncDecl(name, mut params, mut body) => {
+                    ScopeRenamer::rename_function(&globals, &mut params, &mut body);
+                    let mut irs = Vec::new();
+                    let mut t_idx = 0;
+                    let mut l_idx = 0;
+                    let mut loops = Vec::new();
+                    for s in body { self.gen_stmt(&s, &mut irs, &mut t_idx, &mut l_idx, &mut loops); }
+                    self.lines.insert(name.clone(), self.line_table.take());
+                    self.files.insert(name.clone(), file.to_string());
+                    self.functions.insert(name, (params, irs));
+                }
+                _ => inits.push(stmt),
             }
         }
+        if !inits.is_empty() { self.compile_init(inits, file); }
+    }
+
+    // 全域變數的初始化依宣告順序接在 __init__ 後面，先編譯的模組先初始化
+    fn compile_init(&mut self, stmts: Vec<Stmt>, file: &str) {
+        let (params, mut irs) = self.functions.remove(INIT).unwrap_or_default();
+        // 暫存值接著原本的編號；原本的行號表對不上 (例如從快取載入) 或屬於別的檔案就不記行號
+        let mut t_idx = irs.iter().filter_map(|ir| opt::def(ir)?.strip_prefix('t')?.parse::<i32>().ok()).max().map_or(0, |n| n + 1);
+        let old_lines = self.lines.remove(INIT);
+        let keep_lines = irs.is_empty()
+            || (self.files.get(INIT).is_some_and(|f| f == file) && old_lines.as_ref().is_some_and(|lines| lines.len() == irs.len()));
+        *self.line_table.borrow_mut() = old_lines.unwrap_or_default();
+        let mut l_idx = 0;
+        for s in stmts { self.gen_stmt(&s, &mut irs, &mut t_idx, &mut l_idx, &mut Vec::new()); }
+        let table = self.line_table.take();
+        if keep_lines {
+            self.lines.insert(INIT.to_string(), table);
+            self.files.insert(INIT.to_string(), file.to_string());
+        }
+        self.functions.insert(INIT.to_string(), (params, irs));
     }
 
     fn gen_expr(&self, expr: &Expr, irs: &mut Vec<IR>, t_idx: &mut i32, l_idx: &mut i32) -> String {
-        match expr {
-            Expr::Number(v) => {
+        match &expr.kind {
+            ExprKind::Number(v) => {
                 let t = format!("t{}", t_idx); *t_idx += 1;
                 irs.push(IR::LoadConst(t.clone(), *v));
                 t
             }
-            Expr::Str(s) => {
+            ExprKind::Str(s) => {
                 let t = format!("t{}", t_idx); *t_idx += 1;
                 irs.push(IR::LoadStr(t.clone(), s.clone()));
                 t
             }
-            Expr::Variable(n) => {
+            ExprKind::Variable(n) => {
+                let t = format!("t{}", t_idx); *t_idx += 1;
+                if self.global_names.contains(n) { irs.push(IR::LoadGlobal(t.clone(), n.clone())); }
+                else { irs.push(IR::LoadVar(t.clone(), n.clone())); }
+                t
+            }
+            ExprKind::Array(items) => {
+                let item_temps: Vec<String> = items.iter().map(|e| self.gen_expr(e, irs, t_idx, l_idx)).collect();
+                let t = format!("t{}", t_idx); *t_idx += 1;
+                irs.push(IR::NewArray(t.clone(), item_temps));
+                t
+            }
+            ExprKind::Index(base, index) => {
+                let bt = self.gen_expr(base, irs, t_idx, l_idx);
+                let it = self.gen_expr(index, irs, t_idx, l_idx);
                 let t = format!("t{}", t_idx); *t_idx += 1;
-                irs.push(IR::LoadVar(t.clone(), n.clone()));
+                irs.push(IR::IndexLoad(t.clone(), bt, it));
+                t
+            }
+            ExprKind::UnaryOp(op, e) => {
+                let et = self.gen_expr(e, irs, t_idx, l_idx);
+                let t = format!("t{}", t_idx); *t_idx += 1;
+                match op {
+                    Token::Minus => irs.push(IR::Neg(t.clone(), et)),
+                    Token::Not => irs.push(IR::Not(t.clone(), et)),
+                    _ => {}
+                }
+                t
+            }
+            ExprKind::BinaryOp(l, op, r) if matches!(op, Token::And | Token::Or) => {
+                // 短路求值：只有必要時才計算右邊，結果正規化為 0/1
+                let t = format!("t{}", t_idx); *t_idx += 1;
+                let lt = self.gen_expr(l, irs, t_idx, l_idx);
+                let l_false = irs.len();
+                irs.push(IR::IfFalse(lt.clone(), 0));
+                let mut to_end = Vec::new();
+                if *op == Token::Or {
+                    irs.push(IR::LoadConst(t.clone(), 1));
+                    to_end.push(irs.len());
+                    irs.push(IR::Goto(0));
+                }
+                let r_start = irs.len();
+                let rt = self.gen_expr(r, irs, t_idx, l_idx);
+                let r_false = irs.len();
+                irs.push(IR::IfFalse(rt.clone(), 0));
+                irs.push(IR::LoadConst(t.clone(), 1));
+                to_end.push(irs.len());
+                irs.push(IR::Goto(0));
+                let false_pos = irs.len();
+                irs[l_false] = IR::IfFalse(lt, if *op == Token::And { false_pos } else { r_start });
+                irs[r_false] = IR::IfFalse(rt, false_pos);
+                irs.push(IR::LoadConst(t.clone(), 0));
+                for pos in to_end { irs[pos] = IR::Goto(irs.len()); }
+                irs.push(IR::Label);
                 t
             }
-            Expr::BinaryOp(l, op, r) => {
+            ExprKind::BinaryOp(l, op, r) => {
                 let lt = self.gen_expr(l, irs, t_idx, l_idx);
                 let rt = self.gen_expr(r, irs, t_idx, l_idx);
                 let t = format!("t{}", t_idx); *t_idx += 1;
@@ -303,12 +1300,19 @@ impl VM {
                     Token::Plus => irs.push(IR::Add(t.clone(), lt, rt)),
                     Token::Minus => irs.push(IR::Sub(t.clone(), lt, rt)),
                     Token::Mul => irs.push(IR::Mul(t.clone(), lt, rt)),
+                    Token::Div => irs.push(IR::Div(t.clone(), lt, rt)),
+                    Token::Mod => irs.push(IR::Mod(t.clone(), lt, rt)),
                     Token::Eq => irs.push(IR::Eq(t.clone(), lt, rt)),
+                    Token::Ne => irs.push(IR::Ne(t.clone(), lt, rt)),
+                    Token::Lt => irs.push(IR::Lt(t.clone(), lt, rt)),
+                    Token::Le => irs.push(IR::Le(t.clone(), lt, rt)),
+                    Token::Gt => irs.push(IR::Gt(t.clone(), lt, rt)),
+                    Token::Ge => irs.push(IR::Ge(t.clone(), lt, rt)),
                     _ => {}
                 }
                 t
             }
-            Expr::Call(name, args) => {
+            ExprKind::Call(name, args) => {
                 let mut arg_temps = Vec::new();
                 for a in args { arg_temps.push(self.gen_expr(a, irs, t_idx, l_idx)); }
                 let t = format!("t{}", t_idx); *t_idx += 1;
@@ -318,192 +1322,516 @@ impl VM {
         }
     }
 
-    fn gen_stmt(&self, stmt: &Stmt, irs: &mut Vec<IR>, t_idx: &mut i32, l_idx: &mut i32) {
-        match stmt {
-            Stmt::VarDecl(name, expr) => {
+    // 產生一個敘述的 IR 並記錄行號：子敘述開始前的 IR 屬於外層敘述 (例如 while 的條件)，其餘屬於自己
+    fn gen_stmt(&self, stmt: &Stmt, irs: &mut Vec<IR>, t_idx: &mut i32, l_idx: &mut i32, loops: &mut Vec<LoopCtx>) {
+        let outer = self.cur_line.replace(stmt.span.line);
+        self.mark_lines(irs.len(), outer);
+        self.gen_stmt_kind(stmt, irs, t_idx, l_idx, loops);
+        self.mark_lines(irs.len(), stmt.span.line);
+        self.cur_line.set(outer);
+    }
+
+    fn mark_lines(&self, len: usize, line: usize) {
+        let mut table = self.line_table.borrow_mut();
+        while table.len() < len { table.push(line); }
+    }
+
+    fn gen_stmt_kind(&self, stmt: &Stmt, irs: &mut Vec<IR>, t_idx: &mut i32, l_idx: &mut i32, loops: &mut Vec<LoopCtx>) {
+        match &stmt.kind {
+            StmtKind::VarDecl(name, expr) | StmtKind::Assign(name, expr) | StmtKind::ConstDecl(name, expr) => {
                 let t = self.gen_expr(expr, irs, t_idx, l_idx);
-                irs.push(IR::StoreVar(name.clone(), t));
+                if self.global_names.contains(name) { irs.push(IR::StoreGlobal(name.clone(), t)); }
+                else { irs.push(IR::StoreVar(name.clone(), t)); }
+            }
+            StmtKind::IndexAssign(base, index, value) => {
+                let bt = self.gen_expr(base, irs, t_idx, l_idx);
+                let it = self.gen_expr(index, irs, t_idx, l_idx);
+                let vt = self.gen_expr(value, irs, t_idx, l_idx);
+                irs.push(IR::IndexStore(bt, it, vt));
             }
-            Stmt::Return(expr) => {
+            StmtKind::Return(expr) => {
                 let t = self.gen_expr(expr, irs, t_idx, l_idx);
                 irs.push(IR::Return(t));
             }
-            Stmt::ExprStmt(expr) => { self.gen_expr(expr, irs, t_idx, l_idx); }
-            Stmt::If(cond, then_part, else_part) => {
+            StmtKind::ExprStmt(expr) => { self.gen_expr(expr, irs, t_idx, l_idx); }
+            StmtKind::If(cond, then_part, else_part) => {
                 let ct = self.gen_expr(cond, irs, t_idx, l_idx);
                 let if_false_pos = irs.len();
                 irs.push(IR::IfFalse(ct.clone(), 0));
-                for s in then_part { self.gen_stmt(s, irs, t_idx, l_idx); }
+                for s in then_part { self.gen_stmt(s, irs, t_idx, l_idx, loops); }
                 if let Some(else_stmts) = else_part {
                     let goto_pos = irs.len();
                     irs.push(IR::Goto(0));
                     irs[if_false_pos] = IR::IfFalse(ct, irs.len());
-                    for s in else_stmts { self.gen_stmt(s, irs, t_idx, l_idx); }
+                    for s in else_stmts { self.gen_stmt(s, irs, t_idx, l_idx, loops); }
                     irs[goto_pos] = IR::Goto(irs.len());
                 } else { irs[if_false_pos] = IR::IfFalse(ct, irs.len()); }
                 irs.push(IR::Label);
             }
+            StmtKind::While(cond, body) => {
+                let start = irs.len();
+                let ct = self.gen_expr(cond, irs, t_idx, l_idx);
+                let if_false_pos = irs.len();
+                irs.push(IR::IfFalse(ct.clone(), 0));
+                self.gen_loop_body(body, None, start, irs, t_idx, l_idx, loops);
+                irs[if_false_pos] = IR::IfFalse(ct, irs.len());
+                irs.push(IR::Label);
+            }
+            StmtKind::For(init, cond, step, body) => {
+                if let Some(init) = init { self.gen_stmt(init, irs, t_idx, l_idx, loops); }
+                let start = irs.len();
+                let mut if_false = None;
+                if let Some(cond) = cond {
+                    let ct = self.gen_expr(cond, irs, t_idx, l_idx);
+                    if_false = Some((irs.len(), ct.clone()));
+                    irs.push(IR::IfFalse(ct, 0));
+                }
+                self.gen_loop_body(body, step.as_deref(), start, irs, t_idx, l_idx, loops);
+                if let Some((pos, ct)) = if_false { irs[pos] = IR::IfFalse(ct, irs.len()); }
+                irs.push(IR::Label);
+            }
+            StmtKind::Break => {
+                let ctx = loops.last_mut().expect("break 不在迴圈內");
+                ctx.breaks.push(irs.len());
+                irs.push(IR::Goto(0));
+            }
+            StmtKind::Continue => {
+                let ctx = loops.last_mut().expect("continue 不在迴圈內");
+                ctx.continues.push(irs.len());
+                irs.push(IR::Goto(0));
+            }
             _ => {}
         }
     }
 
-    fn dump_ir(&self) -> String {
-        let mut output = String::new();
-        for (name, (params, irs)) in &self.functions {
-            output.push_str(&format!("FUNC {} {}\n", name, params.join(" ")));
-            for ir in irs {
-                let line = match ir {
-                    IR::LoadConst(t, v) => format!("  LOAD_CONST {} {}", t, v),
-                    IR::LoadStr(t, s) => format!("  LOAD_STR {} \"{}\"", t, s),
-                    IR::LoadVar(t, n) => format!("  LOAD_VAR {} {}", t, n),
-                    IR::StoreVar(n, t) => format!("  STORE_VAR {} {}", n, t),
-                    IR::Add(t, l, r) => format!("  ADD {} {} {}", t, l, r),
-                    IR::Sub(t, l, r) => format!("  SUB {} {} {}", t, l, r),
-                    IR::Mul(t, l, r) => format!("  MUL {} {} {}", t, l, r),
-                    IR::Eq(t, l, r) => format!("  EQ {} {} {}", t, l, r),
-                    IR::Call(f, args, t) => format!("  CALL {} {} {}", f, t, args.join(" ")),
-                    IR::Return(t) => format!("  RETURN {}", t),
-                    IR::IfFalse(t, target) => format!("  IFFALSE {} {}", t, target),
-                    IR::Goto(target) => format!("  GOTO {}", target),
-                    IR::Label => format!("  LABEL"),
-                };
-                output.push_str(&line); output.push('\n');
-            }
-            output.push_str("ENDFUNC\n\n");
-        }
-        output
-    }
-
-    fn load_ir(&mut self, content: &str) {
-        let lines: Vec<&str> = content.lines().collect();
-        let mut i = 0;
-        while i < lines.len() {
-            let line = lines[i].trim();
-            if line.starts_with("FUNC") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                let func_name = parts[1].to_string();
-                let params = parts[2..].iter().map(|s| s.to_string()).collect();
-                let mut irs = Vec::new();
-                i += 1;
-                while i < lines.len() {
-                    let ir_line = lines[i].trim();
-                    if ir_line == "ENDFUNC" { break; }
-                    let p: Vec<&str> = ir_line.split_whitespace().collect();
-                    let ir = match p[0] {
-                        "LOAD_CONST" => IR::LoadConst(p[1].into(), p[2].parse().unwrap()),
-                        "LOAD_STR"   => {
-                            // 解析 "..." 中的內容
-                            let content = ir_line.split('"').nth(1).unwrap_or("").to_string();
-                            IR::LoadStr(p[1].into(), content)
-                        },
-                        "LOAD_VAR"   => IR::LoadVar(p[1].into(), p[2].into()),
-                        "STORE_VAR"  => IR::StoreVar(p[1].into(), p[2].into()),
-                        "ADD"        => IR::Add(p[1].into(), p[2].into(), p[3].into()),
-                        "SUB"        => IR::Sub(p[1].into(), p[2].into(), p[3].into()),
-                        "MUL"        => IR::Mul(p[1].into(), p[2].into(), p[3].into()),
-                        "EQ"         => IR::Eq(p[1].into(), p[2].into(), p[3].into()),
-                        "RETURN"     => IR::Return(p[1].into()),
-                        "IFFALSE"    => IR::IfFalse(p[1].into(), p[2].parse().unwrap()),
-                        "GOTO"       => IR::Goto(p[1].parse().unwrap()),
-                        "LABEL"      => IR::Label,
-                        "CALL"       => IR::Call(p[1].into(), p[3..].iter().map(|s| s.to_string()).collect(), p[2].into()),
-                        _ => panic!("未知指令: {}", p[0]),
-                    };
-                    irs.push(ir); i += 1;
-                }
-                self.functions.insert(func_name, (params, irs));
-            }
-            i += 1;
+    // 產生迴圈主體 + step + 跳回 start，並回填 break (迴圈之後) 與 continue (step 之前)
+    #[allow(clippy::too_many_arguments)]
+    fn gen_loop_body(&self, body: &[Stmt], step: Option<&Stmt>, start: usize, irs: &mut Vec<IR>, t_idx: &mut i32, l_idx: &mut i32, loops: &mut Vec<LoopCtx>) {
+        loops.push(LoopCtx { breaks: Vec::new(), continues: Vec::new() });
+        for s in body { self.gen_stmt(s, irs, t_idx, l_idx, loops); }
+        let ctx = loops.pop().unwrap();
+        let continue_target = irs.len();
+        if let Some(step) = step { self.gen_stmt(step, irs, t_idx, l_idx, loops); }
+        irs.push(IR::Goto(start));
+        let end = irs.len();
+        for pos in ctx.continues { irs[pos] = IR::Goto(continue_target); }
+        for pos in ctx.breaks { irs[pos] = IR::Goto(end); }
+    }
+
+    // 對每個函數執行 opt::optimize，並在 stderr 印出最佳化前後的指令數 (不和程式的輸出混在一起)
+    fn optimize(&mut self) {
+        let mut names: Vec<String> = self.functions.keys().cloned().collect();
+        names.sort();
+        let (mut total_before, mut total_after) = (0, 0);
+        for name in names {
+            let code = &mut self.functions.get_mut(&name).unwrap().1;
+            let before = code.len();
+            *code = opt::optimize(std::mem::take(code));
+            self.lines.remove(&name);
+            eprintln!("最佳化 {}: {} -> {} 條 IR", name, before, code.len());
+            total_before += before;
+            total_after += code.len();
         }
+        eprintln!("最佳化 全部: {} -> {} 條 IR", total_before, total_after);
     }
 
-    fn run(&self, func_name: &str, args: Vec<Value>) -> Value {
-        let (params, code) = self.functions.get(func_name).expect("找不到函數");
-        let mut locals = HashMap::<String, Value>::new();
-        for (i, p) in params.iter().enumerate() { locals.insert(p.clone(), args[i].clone()); }
+    fn dump_ir(&self) -> String {
+        irtext::dump(&self.functions)
+    }
 
-        let mut ip = 0;
-        let mut temps = HashMap::<String, Value>::new();
+    fn load_ir(&mut self, content: &str) -> Result<(), Vec<Diagnostic>> {
+        self.functions = irtext::load(content)?;
+        Ok(())
+    }
 
-        while ip < code.len() {
-            match &code[ip] {
-                IR::LoadConst(t, v) => { temps.insert(t.clone(), Value::Int(*v)); }
-                IR::LoadStr(t, s) => { temps.insert(t.clone(), Value::Str(s.clone())); }
-                IR::LoadVar(t, n) => { temps.insert(t.clone(), locals.get(n).cloned().unwrap_or(Value::Int(0))); }
-                IR::StoreVar(n, t) => { locals.insert(n.clone(), temps[t].clone()); }
-                IR::Add(t, l, r) => {
-                    if let (Value::Int(lv), Value::Int(rv)) = (&temps[l], &temps[r]) {
-                        temps.insert(t.clone(), Value::Int(lv + rv));
-                    }
-                }
-                IR::Sub(t, l, r) => {
-                    if let (Value::Int(lv), Value::Int(rv)) = (&temps[l], &temps[r]) {
-                        temps.insert(t.clone(), Value::Int(lv - rv));
-                    }
-                }
-                IR::Mul(t, l, r) => {
-                    if let (Value::Int(lv), Value::Int(rv)) = (&temps[l], &temps[r]) {
-                        temps.insert(t.clone(), Value::Int(lv * rv));
-                    }
-                }
-                IR::Eq(t, l, r) => {
-                    let eq = match (&temps[l], &temps[r]) {
-                        (Value::Int(lv), Value::Int(rv)) => lv == rv,
-                        (Value::Str(lv), Value::Str(rv)) => lv == rv,
-                        _ => false,
-                    };
-                    temps.insert(t.clone(), Value::Int(if eq { 1 } else { 0 }));
-                }
-                IR::IfFalse(t, target) => {
-                    if let Value::Int(v) = temps[t] { if v == 0 { ip = *target; continue; } }
-                }
-                IR::Goto(target) => { ip = *target; continue; }
-                IR::Return(t) => return temps[t].clone(),
-                IR::Call(name, arg_temps, result_t) => {
-                    let call_args: Vec<Value> = arg_temps.iter().map(|at| temps[at].clone()).collect();
-                    if name == "print" {
-                        for val in &call_args {
-                            match val {
-                                Value::Int(v) => print!("{} ", v),
-                                Value::Str(s) => print!("{} ", s),
-                            }
-                        }
-                        println!();
-                        temps.insert(result_t.clone(), Value::Int(0));
-                    } else {
-                        let res = self.run(name, call_args);
-                        temps.insert(result_t.clone(), res);
+    // 在明確的呼叫堆疊上執行，p0 的遞迴不會用到 Rust 的堆疊；
+    // 出錯時依照當下的堆疊補上呼叫鏈
+    fn run(&self, func_name: &str, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        self.init_globals()?;
+        let frame = self.new_frame(func_name, args)
+            .ok_or_else(|| RuntimeError::new(func_name, 0, &format!("找不到函數 '{}'", func_name)))?;
+        let mut stack = vec![frame];
+        self.execute(&mut stack).map_err(|mut e| { e.backtrace = self.backtrace(&stack); e })
+    }
+
+    // 執行 __init__ 設定全域變數的初始值 (沒有全域變數就沒有 __init__)
+    fn init_globals(&self) -> Result<(), RuntimeError> {
+        let mut stack = match self.new_frame(INIT, Vec::new()) { Some(frame) => vec![frame], None => return Ok(()) };
+        self.execute(&mut stack).map(|_| ()).map_err(|mut e| { e.backtrace = self.backtrace(&stack); e })
+    }
+
+    fn new_frame(&self, name: &str, args: Vec<Value>) -> Option<Frame<'_>> {
+        let (func, (params, code)) = self.functions.get_key_value(name)?;
+        let locals = params.iter().cloned().zip(args).collect();
+        Some(Frame { func, code, ip: 0, locals, temps: HashMap::new() })
+    }
+
+    // 一條 IR 在原始碼裡的位置：(檔案, 行號)
+    fn location(&self, func: &str, ip: usize) -> Option<(String, usize)> {
+        let line = *self.lines.get(func)?.get(ip)?;
+        Some((self.files.get(func)?.clone(), line))
+    }
+
+    // 呼叫鏈，最內層在前：(函數, IR 位置, 原始碼位置)
+    fn backtrace(&self, stack: &[Frame]) -> Vec<CallSite> {
+        stack.iter().rev().map(|f| (f.func.to_string(), f.ip, self.location(f.func, f.ip))).collect()
+    }
+
+    fn execute<'a>(&'a self, stack: &mut Vec<Frame<'a>>) -> Result<Value, RuntimeError> {
+        loop {
+            if let Some(v) = self.step(stack)? { return Ok(v); }
+        }
+    }
+
+    // 執行堆疊頂端框架的一條指令；整個程式結束時回傳 main 的回傳值
+    fn step<'a>(&'a self, stack: &mut Vec<Frame<'a>>) -> Result<Option<Value>, RuntimeError> {
+        let depth = stack.len();
+        let frame = stack.last_mut().unwrap();
+        let (func_name, code, ip) = (frame.func, frame.code, frame.ip);
+        if ip >= code.len() {
+            // 走到函數結尾沒有 RETURN，回傳 0
+            return Ok(return_to_caller(stack, Value::Int(0)));
+        }
+        let (temps, locals) = (&mut frame.temps, &mut frame.locals);
+        match &code[ip] {
+            IR::LoadConst(t, v) => { temps.insert(t.clone(), Value::Int(*v)); }
+            IR::LoadStr(t, s) => { temps.insert(t.clone(), Value::Str(s.clone())); }
+            IR::LoadVar(t, n) => { temps.insert(t.clone(), locals.get(n).cloned().unwrap_or(Value::Int(0))); }
+            IR::StoreVar(n, t) => { locals.insert(n.clone(), temps[t].clone()); }
+            IR::LoadGlobal(t, g) => { temps.insert(t.clone(), self.globals.borrow().get(g).cloned().unwrap_or(Value::Int(0))); }
+            IR::StoreGlobal(g, t) => { self.globals.borrow_mut().insert(g.clone(), temps[t].clone()); }
+            IR::Add(t, l, r) | IR::Sub(t, l, r) | IR::Mul(t, l, r) | IR::Div(t, l, r) | IR::Mod(t, l, r)
+            | IR::Lt(t, l, r) | IR::Le(t, l, r) | IR::Gt(t, l, r) | IR::Ge(t, l, r) => {
+                let v = binary_op(&code[ip], &temps[l], &temps[r]).map_err(|msg| RuntimeError::new(func_name, ip, &msg))?;
+                temps.insert(t.clone(), v);
+            }
+            IR::Eq(t, l, r) | IR::Ne(t, l, r) => {
+                let eq = values_equal(&temps[l], &temps[r]);
+                let res = if matches!(&code[ip], IR::Eq(..)) { eq } else { !eq };
+                temps.insert(t.clone(), Value::Int(if res { 1 } else { 0 }));
+            }
+            IR::Neg(t, s) => match &temps[s] {
+                Value::Int(v) => { temps.insert(t.clone(), Value::Int(v.wrapping_neg())); }
+                v => return Err(RuntimeError::new(func_name, ip, &format!("型別錯誤: 不能對{}使用 '-'", v.type_name()))),
+            },
+            IR::Not(t, s) => {
+                let truthy = match &temps[s] { Value::Int(v) => *v != 0, _ => true };
+                temps.insert(t.clone(), Value::Int(if truthy { 0 } else { 1 }));
+            }
+            IR::NewArray(t, items) => {
+                let values = items.iter().map(|it| temps[it].clone()).collect();
+                temps.insert(t.clone(), Value::Array(Rc::new(RefCell::new(values))));
+            }
+            IR::IndexLoad(t, a, i) => {
+                let (items, idx) = array_slot(func_name, ip, &temps[a], &temps[i])?;
+                let v = items.borrow()[idx].clone();
+                temps.insert(t.clone(), v);
+            }
+            IR::IndexStore(a, i, v) => {
+                let (items, idx) = array_slot(func_name, ip, &temps[a], &temps[i])?;
+                items.borrow_mut()[idx] = temps[v].clone();
+            }
+            IR::IfFalse(t, target) => {
+                if let Value::Int(v) = temps[t] { if v == 0 { frame.ip = *target; return Ok(None); } }
+            }
+            IR::Goto(target) => { frame.ip = *target; return Ok(None); }
+            IR::Return(t) => {
+                let v = temps[t].clone();
+                return Ok(return_to_caller(stack, v));
+            }
+            IR::Call(name, arg_temps, result_t) => {
+                let call_args: Vec<Value> = arg_temps.iter().map(|at| temps[at].clone()).collect();
+                if let Some(native) = self.natives.get(name.as_str()) {
+                    let res = native(&call_args).map_err(|msg| RuntimeError::new(func_name, ip, &msg))?;
+                    temps.insert(result_t.clone(), res);
+                } else {
+                    // 呼叫端的 ip 停在 CALL 上，等被呼叫的函數回傳時才前進
+                    if depth >= self.max_depth {
+                        return Err(RuntimeError::new(func_name, ip, &format!("呼叫深度超過上限 {}", self.max_depth)));
                     }
+                    let callee = self.new_frame(name, call_args)
+                        .ok_or_else(|| RuntimeError::new(func_name, ip, &format!("找不到函數 '{}'", name)))?;
+                    stack.push(callee);
+                    return Ok(None);
                 }
-                IR::Label => {}
             }
-            ip += 1;
+            IR::Label => {}
+        }
+        frame.ip += 1;
+        Ok(None)
+    }
+}
+
+// 彈出目前的框架，把回傳值交給呼叫端 CALL 的結果暫存值；堆疊空了就是整個程式結束
+fn return_to_caller(stack: &mut Vec<Frame>, v: Value) -> Option<Value> {
+    stack.pop();
+    match stack.last_mut() {
+        None => Some(v),
+        Some(caller) => {
+            if let IR::Call(_, _, result_t) = &caller.code[caller.ip] { caller.temps.insert(result_t.clone(), v); }
+            caller.ip += 1;
+            None
+        }
+    }
+}
+
+// ==========================================================
+// 6. 內建函數 (native functions)
+// ==========================================================
+
+// 內建函數收到已求值的參數；回傳 Err 時由 VM 補上函數名稱與 IR 位置
+type NativeFn = fn(&[Value]) -> Result<Value, String>;
+
+// 名稱、參數個數 (None 表示可變參數)、實作。語意分析與 VM 都查這張表
+const NATIVES: &[(&str, Option<usize>, NativeFn)] = &[
+    ("print", None, native_print),
+    ("len", Some(1), native_len),
+    ("push", Some(2), native_push),
+    ("substr", Some(3), native_substr),
+    ("int_to_str", Some(1), native_int_to_str),
+    ("str_to_int", Some(1), native_str_to_int),
+    ("input", Some(0), native_input),
+    ("read_file", Some(1), native_read_file),
+    ("write_file", Some(2), native_write_file),
+];
+
+fn expect_int(func: &str, v: &Value) -> Result<i32, String> {
+    match v {
+        Value::Int(i) => Ok(*i),
+        _ => Err(format!("型別錯誤: {} 需要整數，但收到{}", func, v.type_name())),
+    }
+}
+
+fn expect_str<'a>(func: &str, v: &'a Value) -> Result<&'a str, String> {
+    match v {
+        Value::Str(s) => Ok(s),
+        _ => Err(format!("型別錯誤: {} 需要字串，但收到{}", func, v.type_name())),
+    }
+}
+
+fn native_print(args: &[Value]) -> Result<Value, String> {
+    for val in args { print!("{} ", val); }
+    println!();
+    Ok(Value::Int(0))
+}
+
+fn native_len(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Array(items) => Ok(Value::Int(items.borrow().len() as i32)),
+        Value::Str(s) => Ok(Value::Int(s.chars().count() as i32)),
+        v => Err(format!("型別錯誤: len 需要陣列或字串，但收到{}", v.type_name())),
+    }
+}
+
+fn native_push(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Array(items) => {
+            items.borrow_mut().push(args[1].clone());
+            Ok(Value::Int(items.borrow().len() as i32))
         }
-        Value::Int(0)
+        v => Err(format!("型別錯誤: push 需要陣列，但收到{}", v.type_name())),
     }
 }
 
+// substr(s, start, count)，以字元為單位
+fn native_substr(args: &[Value]) -> Result<Value, String> {
+    let s = expect_str("substr", &args[0])?;
+    let (start, count) = (expect_int("substr", &args[1])?, expect_int("substr", &args[2])?);
+    let len = s.chars().count() as i32;
+    if start < 0 || count < 0 || count > len - start {
+        return Err(format!("substr({}, {}) 超出範圍 (字串長度 {})", start, count, len));
+    }
+    Ok(Value::Str(s.chars().skip(start as usize).take(count as usize).collect()))
+}
+
+fn native_int_to_str(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Str(expect_int("int_to_str", &args[0])?.to_string()))
+}
+
+fn native_str_to_int(args: &[Value]) -> Result<Value, String> {
+    let s = expect_str("str_to_int", &args[0])?;
+    s.trim().parse().map(Value::Int).map_err(|_| format!("str_to_int: 無法把 '{}' 轉成整數", s))
+}
+
+// 從標準輸入讀一行 (去掉換行)，讀到檔案結尾時回傳空字串
+fn native_input(_args: &[Value]) -> Result<Value, String> {
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line).map_err(|e| format!("input: {}", e))?;
+    Ok(Value::Str(line.trim_end_matches(['\n', '\r']).to_string()))
+}
+
+fn native_read_file(args: &[Value]) -> Result<Value, String> {
+    let path = expect_str("read_file", &args[0])?;
+    fs::read_to_string(path).map(Value::Str).map_err(|e| format!("read_file('{}'): {}", path, e))
+}
+
+fn native_write_file(args: &[Value]) -> Result<Value, String> {
+    let path = expect_str("write_file", &args[0])?;
+    let content = expect_str("write_file", &args[1])?;
+    fs::write(path, content).map(|_| Value::Int(0)).map_err(|e| format!("write_file('{}'): {}", path, e))
+}
+
 // ==========================================================
-// 5. 主程式
+// 7. 主程式
 // ==========================================================
 
+fn report_errors(errors: &[Diagnostic], path: &str, source: &str) -> ! {
+    for e in errors { eprintln!("{}", e.render(path, source)); }
+    eprintln!("編譯失敗，共 {} 個錯誤", errors.len());
+    process::exit(1);
+}
+
+// 命令列：compiler [-O0|-O1] [--emit asm|riscv|cfg|p0b] [--max-depth N] [--debug] [--profile] [--folded FILE] [-I DIR] [source_file]
+//         compiler fmt [--check] <file.p0>...  (格式化原始碼，見 pretty.rs)
+struct Options {
+    emit: Option<String>,
+    opt_level: u8,
+    max_depth: usize,
+    debug: bool,
+    profile: bool,
+    folded: Option<String>, // --folded 的輸出檔，同時開啟 --profile
+    include: Vec<String>,   // -I 指定的模組搜尋目錄
+    file: Option<String>, // 沒有給檔案時進入 REPL
+}
+
+fn parse_args() -> Options {
+    let args: Vec<String> = env::args().collect();
+    let usage = || -> ! {
+        eprintln!("用法: {} [-O0|-O1] [--emit asm|riscv|cfg|p0b] [--max-depth N] [--debug] [--profile] [--folded FILE] [-I DIR] [source_file]\n      {} fmt [--check] <file.p0>...", args[0], args[0]);
+        process::exit(1);
+    };
+    let (mut emit, mut opt_level, mut max_depth, mut debug, mut file) = (None, 0, DEFAULT_MAX_DEPTH, false, None);
+    let (mut profile, mut folded, mut include) = (false, None, Vec::new());
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--emit" => {
+                i += 1;
+                match args.get(i).map(|s| s.as_str()) {
+                    Some("asm" | "riscv" | "cfg" | "p0b") => emit = Some(args[i].clone()),
+                    _ => usage(),
+                }
+            }
+            "--max-depth" => {
+                i += 1;
+                match args.get(i).and_then(|s| s.parse().ok()) {
+                    Some(n) if n > 0 => max_depth = n,
+                    _ => usage(),
+                }
+            }
+            "-I" => {
+                i += 1;
+                include.push(args.get(i).cloned().unwrap_or_else(|| usage()));
+            }
+            "--debug" => debug = true,
+            "--profile" => profile = true,
+            "--folded" => {
+                i += 1;
+                folded = Some(args.get(i).cloned().unwrap_or_else(|| usage()));
+                profile = true;
+            }
+            "-O0" => opt_level = 0,
+            "-O1" => opt_level = 1,
+            a if a.starts_with('-') => usage(),
+            a => file = Some(a.to_string()),
+        }
+        i += 1;
+    }
+    Options { emit, opt_level, max_depth, debug, profile, folded, include, file }
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 { eprintln!("用法: {} <source_file>", args[0]); process::exit(1); }
-    let file_path = &args[1];
+    if args.get(1).map(|s| s.as_str()) == Some("fmt") { process::exit(pretty::run(&args[2..])); }
+    let opts = parse_args();
+    let file_path = match &opts.file {
+        Some(file) => file,
+        None => {
+            let mut vm = VM::new();
+            vm.max_depth = opts.max_depth;
+            repl::run(vm);
+            return;
+        }
+    };
     let extension = Path::new(file_path).extension().and_then(|s| s.to_str()).unwrap_or("");
+    if extension == "p0b" {
+        // 位元組碼直接交給暫存器式直譯器
+        let mut program = bytecode::Program::decode(&fs::read(file_path).expect("無法讀取位元組碼"))
+            .unwrap_or_else(|e| { eprintln!("{}: {}", file_path, e); process::exit(1); });
+        program.max_depth = opts.max_depth;
+        report_result(program.run_main());
+        return;
+    }
     let mut vm = VM::new();
-    if extension == "ir" {
-        vm.load_ir(&fs::read_to_string(file_path).expect("無法讀取 IR"));
+    vm.max_depth = opts.max_depth;
+    // 除錯器的 `b <行號>` 指的是主程式的行；.ir 沒有行號資訊，只顯示 IR
+    let main_file = if extension == "ir" {
+        let source = fs::read_to_string(file_path).expect("無法讀取 IR");
+        vm.load_ir(&source).unwrap_or_else(|errors| report_errors(&errors, file_path, &source));
+        if opts.opt_level > 0 { vm.optimize(); }
+        None
     } else {
-        let source = fs::read_to_string(file_path).expect("無法讀取原始碼");
-        let lexer = Lexer::new(&source);
-        let mut parser = Parser::new(lexer);
-        vm.compile(parser.parse_program());
+        // 主程式與 import 的模組一起編譯；寫出的 .ir 包含所有函數，可以單獨執行
+        modules::load(&mut vm, file_path, &opts.include, true)
+            .unwrap_or_else(|e| report_errors(&e.errors, &e.path, &e.source));
+        if opts.opt_level > 0 { vm.optimize(); }
         fs::write(Path::new(file_path).with_extension("ir"), vm.dump_ir()).expect("無法寫入 IR");
+        Some(file_path.as_str())
+    };
+    if opts.emit.as_deref() == Some("asm") {
+        let out_path = Path::new(file_path).with_extension("s");
+        match x86::emit_program(&vm.functions) {
+            Ok(asm) => fs::write(&out_path, asm).expect("無法寫入組合語言"),
+            Err(e) => { eprintln!("{}", e); process::exit(1); }
+        }
+        println!("已輸出 {}", out_path.display());
+        return;
+    }
+    if opts.emit.as_deref() == Some("cfg") {
+        // 每個函數一個 .dot 檔：<檔名>.<函數>.dot
+        let mut names: Vec<&String> = vm.functions.keys().collect();
+        names.sort();
+        for name in names {
+            let (params, code) = &vm.functions[name];
+            let out_path = Path::new(file_path).with_extension(format!("{}.dot", name));
+            fs::write(&out_path, cfg::to_dot(name, params, code)).expect("無法寫入 .dot 檔");
+            println!("已輸出 {}", out_path.display());
+        }
+        return;
+    }
+    if opts.emit.as_deref() == Some("p0b") {
+        let out_path = Path::new(file_path).with_extension("p0b");
+        match bytecode::lower(&vm.functions) {
+            Ok(program) => fs::write(&out_path, program.encode()).expect("無法寫入位元組碼"),
+            Err(e) => { eprintln!("{}", e); process::exit(1); }
+        }
+        println!("已輸出 {}", out_path.display());
+        return;
     }
-    match vm.run("main", vec![]) {
-        Value::Int(v) => println!("(main 結束，回傳值: {})", v),
-        Value::Str(s) => println!("(main 結束，回傳值: \"{}\")", s),
+    if opts.emit.as_deref() == Some("riscv") {
+        let out_path = Path::new(file_path).with_extension("elf");
+        match riscv::emit_program(&vm.functions) {
+            Ok(elf) => fs::write(&out_path, elf).expect("無法寫入 ELF"),
+            Err(e) => { eprintln!("{}", e); process::exit(1); }
+        }
+        println!("已輸出 {}", out_path.display());
+        return;
+    }
+    if opts.debug {
+        debugger::run(&vm, main_file);
+        return;
+    }
+    if opts.profile {
+        report_result(profile::run(&vm, opts.folded.as_deref()));
+        return;
+    }
+    report_result(vm.run("main", vec![]));
+}
+
+fn report_result(result: Result<Value, RuntimeError>) {
+    match result {
+        Ok(Value::Str(s)) => println!("(main 結束，回傳值: \"{}\")", s),
+        Ok(v) => println!("(main 結束，回傳值: {})", v),
+        Err(e) => { eprintln!("{}", e); process::exit(1); }
     }
 }
\ No newline at end of file
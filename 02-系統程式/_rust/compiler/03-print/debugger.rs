@@ -0,0 +1,248 @@
+// ==========================================================
+// 互動式除錯器 (--debug)
+//
+// 直接操作 VM 的呼叫堆疊，每次用 VM::step 執行一條 IR 指令。
+// 程式開始時停在 main 的入口，可用的指令：
+//   break <函數> | <函數>:<IR> | <行號>  設中斷點 (行號是主程式的行，只適用於從原始碼編譯、沒有最佳化的函數)
+//   delete [編號]    刪除中斷點，不給編號就全部刪除
+//   info             列出中斷點
+//   step             執行一條 IR，遇到 CALL 會進入被呼叫的函數
+//   next             執行一條 IR，CALL 會整個執行完才停
+//   finish           執行到目前的函數回傳
+//   continue         執行到下一個中斷點或程式結束
+//   locals / temps   目前函數的變數 / 暫存值
+//   print <名稱>     一個變數或暫存值
+//   bt               呼叫堆疊
+//   list             目前位置附近的 IR
+//   quit
+// 直接按 Enter 會重複上一個指令。
+// ==========================================================
+
+use super::*;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+break <函數>|<函數>:<IR>|<行號>  設中斷點 (b)
+delete [編號]                    刪除中斷點 (d)
+info                             列出中斷點
+step                             執行一條 IR，進入函數呼叫 (s)
+next                             執行一條 IR，不進入函數呼叫 (n)
+finish                           執行到目前的函數回傳
+continue                         執行到下一個中斷點 (c)
+locals / temps                   目前函數的變數 / 暫存值
+print <名稱>                     印出變數或暫存值 (p)
+bt                               呼叫堆疊
+list                             目前位置附近的 IR (l)
+quit                             離開 (q)";
+
+struct Debugger<'a> {
+    vm: &'a VM,
+    main: Option<&'a str>,           // 主程式的路徑 (.ir 沒有)
+    sources: HashMap<String, String>, // 有行號的函數用到的原始碼檔案
+    stack: Vec<Frame<'a>>,
+    breakpoints: Vec<(usize, String, usize)>, // (編號, 函數, IR 位置)
+    next_id: usize,
+    finished: bool,
+}
+
+pub fn run(vm: &VM, main: Option<&str>) {
+    // 全域變數的初始化不逐步執行
+    if let Err(e) = vm.init_globals() { eprintln!("{}", e); process::exit(1); }
+    let stack = match vm.new_frame("main", Vec::new()) {
+        Some(frame) => vec![frame],
+        None => { eprintln!("找不到函數 'main'"); process::exit(1); }
+    };
+    // import 的模組各自有原始碼，顯示位置時依函數所在的檔案取行
+    let sources = vm.files.values().filter_map(|f| Some((f.clone(), fs::read_to_string(f).ok()?))).collect();
+    let mut db = Debugger { vm, main, sources, stack, breakpoints: Vec::new(), next_id: 1, finished: false };
+    println!("p0 除錯器，輸入 help 查看指令");
+    db.show_location();
+
+    let stdin = io::stdin();
+    let mut last = String::new();
+    loop {
+        print!("(p0db) ");
+        io::stdout().flush().ok();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 { println!(); return; }
+        if line.trim().is_empty() { line = last.clone(); } else { last = line.clone(); }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {}
+            ["help" | "h"] => println!("{}", HELP),
+            ["break" | "b", spec] => db.add_breakpoint(spec),
+            ["delete" | "d"] => { db.breakpoints.clear(); println!("已刪除所有中斷點"); }
+            ["delete" | "d", id] => db.delete_breakpoint(id),
+            ["info"] => db.list_breakpoints(),
+            ["step" | "s"] => db.resume(|_| true),
+            ["next" | "n"] => { let depth = db.stack.len(); db.resume(move |s| s.len() <= depth) }
+            ["finish"] => { let depth = db.stack.len(); db.resume(move |s| s.len() < depth) }
+            ["continue" | "c"] => db.resume(|_| false),
+            ["locals"] => db.show_values(false),
+            ["temps"] => db.show_values(true),
+            ["print" | "p", name] => db.print_value(name),
+            ["bt"] => db.show_backtrace(),
+            ["list" | "l"] => db.list(),
+            ["quit" | "q"] => return,
+            _ => println!("看不懂的指令: {} (輸入 help 查看指令)", line.trim()),
+        }
+    }
+}
+
+// 除錯器與 REPL 顯示值時字串加上引號，才分得出 1 和 "1"
+pub(super) fn show(v: &Value) -> String {
+    match v {
+        Value::Str(s) => format!("\"{}\"", irtext::escape(s)),
+        v => v.to_string(),
+    }
+}
+
+impl<'a> Debugger<'a> {
+    // 一直執行到 stop(堆疊) 成立、遇到中斷點或程式結束。
+    // 第一步不檢查中斷點，這樣才能從停著的中斷點繼續往下走
+    fn resume(&mut self, stop: impl Fn(&[Frame]) -> bool) {
+        if self.finished { println!("程式已經結束"); return; }
+        loop {
+            match self.vm.step(&mut self.stack) {
+                Ok(Some(v)) => {
+                    println!("(main 結束，回傳值: {})", show(&v));
+                    self.finished = true;
+                    return;
+                }
+                Ok(None) => {}
+                Err(mut e) => {
+                    // 堆疊保留在出錯的位置，還可以用 bt、locals 查看
+                    e.backtrace = self.vm.backtrace(&self.stack);
+                    println!("{}", e);
+                    self.finished = true;
+                    return;
+                }
+            }
+            if stop(&self.stack) { break; }
+            let frame = self.stack.last().unwrap();
+            if let Some((id, ..)) = self.breakpoints.iter().find(|(_, f, ip)| f == frame.func && *ip == frame.ip) {
+                println!("中斷點 {}", id);
+                break;
+            }
+        }
+        self.show_location();
+    }
+
+    // 主程式的位置只顯示行號，其他模組加上檔名
+    fn describe(&self, file: &str, line: usize) -> String {
+        if Some(file) == self.main { format!("第 {} 行", line) } else { format!("{} 第 {} 行", file, line) }
+    }
+
+    fn show_location(&self) {
+        let frame = match self.stack.last() {
+            Some(frame) => frame,
+            None => return,
+        };
+        match frame.code.get(frame.ip) {
+            Some(ir) => println!("{} IR #{}: {}", frame.func, frame.ip, ir),
+            None => println!("{} IR #{}: (函數結尾)", frame.func, frame.ip),
+        }
+        if let Some((file, line)) = self.vm.location(frame.func, frame.ip) {
+            if Some(file.as_str()) != self.main { println!("  --> {}", file); }
+            if let Some(text) = self.sources.get(&file).and_then(|s| s.lines().nth(line - 1)) { println!("{:>4} | {}", line, text); }
+        }
+    }
+
+    fn add_breakpoint(&mut self, spec: &str) {
+        let mut found = Vec::new();
+        if let Ok(line) = spec.parse::<usize>() {
+            // 行號：主程式每個函數裡屬於這一行的連續 IR 只停在第一條
+            let mut names: Vec<&String> = self.vm.lines.keys().filter(|f| self.vm.files.get(*f).map(String::as_str) == self.main).collect();
+            names.sort();
+            for name in names {
+                let lines = &self.vm.lines[name];
+                for (ip, l) in lines.iter().enumerate() {
+                    if *l == line && (ip == 0 || lines[ip - 1] != line) { found.push((name.clone(), ip)); }
+                }
+            }
+            if found.is_empty() {
+                if self.vm.lines.is_empty() { println!("沒有行號資訊 (從 .ir 載入或經過最佳化)，請用 <函數>:<IR>"); }
+                else { println!("第 {} 行沒有對應的 IR", line); }
+                return;
+            }
+        } else {
+            let (func, ip) = match spec.split_once(':') {
+                Some((func, ip)) => match ip.parse::<usize>() {
+                    Ok(ip) => (func, ip),
+                    Err(_) => { println!("IR 位置必須是整數: {}", ip); return; }
+                },
+                None => (spec, 0),
+            };
+            match self.vm.functions.get(func) {
+                None => { println!("找不到函數 '{}'", func); return; }
+                Some((_, code)) if ip >= code.len() => { println!("{} 只有 {} 條 IR", func, code.len()); return; }
+                Some(_) => found.push((func.to_string(), ip)),
+            }
+        }
+        for (func, ip) in found {
+            print!("中斷點 {}: {} IR #{}", self.next_id, func, ip);
+            match self.vm.location(&func, ip) {
+                Some((file, line)) => println!(" ({})", self.describe(&file, line)),
+                None => println!(),
+            }
+            self.breakpoints.push((self.next_id, func, ip));
+            self.next_id += 1;
+        }
+    }
+
+    fn delete_breakpoint(&mut self, id: &str) {
+        let before = self.breakpoints.len();
+        self.breakpoints.retain(|(n, ..)| n.to_string() != id);
+        if self.breakpoints.len() == before { println!("沒有中斷點 {}", id); }
+    }
+
+    fn list_breakpoints(&self) {
+        if self.breakpoints.is_empty() { println!("沒有中斷點"); }
+        for (id, func, ip) in &self.breakpoints { println!("{}: {} IR #{}", id, func, ip); }
+    }
+
+    fn show_values(&self, temps: bool) {
+        let frame = match self.stack.last() {
+            Some(frame) => frame,
+            None => { println!("程式已經結束"); return; }
+        };
+        let map = if temps { &frame.temps } else { &frame.locals };
+        // t2 排在 t10 前面
+        let mut names: Vec<&String> = map.keys().collect();
+        names.sort_by_key(|n| (n.len(), n.as_str()));
+        if names.is_empty() { println!("(沒有{})", if temps { "暫存值" } else { "變數" }); }
+        for name in names { println!("{} = {}", name, show(&map[name])); }
+    }
+
+    fn print_value(&self, name: &str) {
+        let frame = match self.stack.last() {
+            Some(frame) => frame,
+            None => { println!("程式已經結束"); return; }
+        };
+        match frame.locals.get(name).or_else(|| frame.temps.get(name)) {
+            Some(v) => println!("{} = {}", name, show(v)),
+            None => println!("目前的函數沒有 '{}'", name),
+        }
+    }
+
+    fn show_backtrace(&self) {
+        if self.stack.is_empty() { println!("程式已經結束"); return; }
+        for (i, (func, ip, location)) in self.vm.backtrace(&self.stack).into_iter().enumerate() {
+            match location {
+                Some((file, line)) => println!("#{} {} (IR #{}, {})", i, func, ip, self.describe(&file, line)),
+                None => println!("#{} {} (IR #{})", i, func, ip),
+            }
+        }
+    }
+
+    fn list(&self) {
+        let frame = match self.stack.last() {
+            Some(frame) => frame,
+            None => { println!("程式已經結束"); return; }
+        };
+        let start = frame.ip.saturating_sub(3);
+        for (ip, ir) in frame.code.iter().enumerate().skip(start).take(8) {
+            println!("{} {:>3}: {}", if ip == frame.ip { "=>" } else { "  " }, ip, ir);
+        }
+    }
+}
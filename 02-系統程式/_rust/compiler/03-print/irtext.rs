@@ -0,0 +1,429 @@
+// ==========================================================
+// IR 文字格式 (.ir 檔)
+//
+//   ; 分號之後到行尾是註解
+//   .version 1
+//   FUNC factorial n
+//     LOAD_VAR t1 n
+//     IFFALSE t3 L1
+//     LOAD_STR t2 "a \"quoted\" string\n"
+//     ...
+//   L1:
+//     ...
+//   ENDFUNC
+//
+// 全域變數用 LOAD_GLOBAL t name / STORE_GLOBAL name t 存取，初始值由 FUNC __init__ 在 main 之前設定。
+//
+// 模組的 IR 快取 (__p0cache__/*.ir) 在函數之前另外記錄 import 的路徑與 export 的函數：
+//   .import "util.p0"
+//   .export square
+//
+// 跳躍目標寫成具名標籤，載入時才換回指令位置，手動編輯 .ir 檔也不會跑掉。
+// 載入後會驗證：標籤都有定義、暫存值在每條路徑上都先定義再使用、
+// 呼叫的函數存在且參數個數正確。錯誤以 Diagnostic 回報，標示 .ir 檔的行號。
+// ==========================================================
+
+use super::cfg::Cfg;
+use super::opt::{def, uses_mut};
+use super::*;
+use std::collections::HashSet;
+
+pub const VERSION: u32 = 1;
+
+type Functions = HashMap<String, (Vec<String>, Vec<IR>)>;
+
+pub struct Module {
+    pub functions: Functions,
+    pub exports: Vec<String>,
+}
+
+// 字串常數的跳脫：\" \\ \n \t \r，其他控制字元寫成 \u{..}
+pub fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn dump(functions: &Functions) -> String {
+    dump_module(functions, &[], &[])
+}
+
+pub fn dump_module(functions: &Functions, imports: &[String], exports: &[String]) -> String {
+    let mut out = format!("; p0 IR\n.version {}\n", VERSION);
+    for path in imports { out.push_str(&format!(".import \"{}\"\n", escape(path))); }
+    for name in exports { out.push_str(&format!(".export {}\n", name)); }
+    let mut names: Vec<&String> = functions.keys().collect();
+    names.sort();
+    for name in names {
+        let (params, code) = &functions[name];
+        // 依位置順序替跳躍目標編號 L1, L2, ...
+        let mut targets: Vec<usize> = code.iter().filter_map(|ir| match ir {
+            IR::IfFalse(_, target) | IR::Goto(target) => Some(*target),
+            _ => None,
+        }).collect();
+        targets.sort();
+        targets.dedup();
+        let label = |target: &usize| format!("L{}", targets.binary_search(target).unwrap() + 1);
+
+        out.push_str(&format!("\nFUNC {} {}\n", name, params.join(" ")).replace(" \n", "\n"));
+        for ip in 0..=code.len() {
+            if targets.binary_search(&ip).is_ok() { out.push_str(&format!("{}:\n", label(&ip))); }
+            let line = match code.get(ip) {
+                None => break,
+                Some(IR::IfFalse(t, target)) => format!("IFFALSE {} {}", t, label(target)),
+                Some(IR::Goto(target)) => format!("GOTO {}", label(target)),
+                Some(ir) => ir.to_string(),
+            };
+            out.push_str(&format!("  {}\n", line));
+        }
+        out.push_str("ENDFUNC\n");
+    }
+    out
+}
+
+enum Tok {
+    Word(String),
+    Str(String),
+}
+
+// 把一行切成 (token, 欄位, 長度)，處理字串常數與註解
+fn tokenize(line: &str, line_no: usize) -> Result<Vec<(Tok, usize, usize)>, Diagnostic> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut toks = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() { i += 1; continue; }
+        if c == ';' { break; }
+        let start = i;
+        if c == '"' {
+            let mut s = String::new();
+            i += 1;
+            loop {
+                let err = |msg: &str, col: usize| Diagnostic::new(msg, Span { line: line_no, col: col + 1, len: 1 });
+                match chars.get(i) {
+                    None => return Err(Diagnostic::new("字串沒有結束的 '\"'", Span { line: line_no, col: start + 1, len: i - start })),
+                    Some('"') => { i += 1; break; }
+                    Some('\\') => {
+                        i += 1;
+                        match chars.get(i) {
+                            Some('"') => s.push('"'),
+                            Some('\\') => s.push('\\'),
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some('r') => s.push('\r'),
+                            Some('u') if chars.get(i + 1) == Some(&'{') => {
+                                let close = chars[i..].iter().position(|c| *c == '}').map(|p| i + p);
+                                let code = close.and_then(|close| {
+                                    u32::from_str_radix(&chars[i + 2..close].iter().collect::<String>(), 16).ok().and_then(char::from_u32)
+                                });
+                                match (close, code) {
+                                    (Some(close), Some(c)) => { s.push(c); i = close; }
+                                    _ => return Err(err("無效的 \\u{..} 跳脫", i - 1)),
+                                }
+                            }
+                            _ => return Err(err("未知的跳脫字元", i - 1)),
+                        }
+                        i += 1;
+                    }
+                    Some(c) => { s.push(*c); i += 1; }
+                }
+            }
+            toks.push((Tok::Str(s), start + 1, i - start));
+        } else {
+            while i < chars.len() && !chars[i].is_whitespace() && chars[i] != ';' && chars[i] != '"' { i += 1; }
+            toks.push((Tok::Word(chars[start..i].iter().collect()), start + 1, i - start));
+        }
+    }
+    Ok(toks)
+}
+
+// 正在讀取的函數
+struct PendingFunc {
+    name: String,
+    params: Vec<String>,
+    code: Vec<IR>,
+    spans: Vec<Span>,                  // 每個指令在 .ir 檔中的位置
+    labels: HashMap<String, usize>,    // 標籤 -> 指令位置
+    jumps: Vec<(usize, String, Span)>, // 尚未回填的跳躍 (指令位置, 標籤, 位置)
+    first_error: usize,                // 開始讀這個函數時已經有幾個錯誤
+}
+
+pub fn load(content: &str) -> Result<Functions, Vec<Diagnostic>> {
+    load_module(content, &HashMap::new()).map(|m| m.functions)
+}
+
+// 只讀出 .import 的路徑，讓呼叫端先載入被 import 的模組
+pub fn imports(content: &str) -> Vec<String> {
+    content.lines().enumerate().filter_map(|(idx, line)| match tokenize(line, idx + 1).ok()?.as_slice() {
+        [(Tok::Word(w), ..), (Tok::Str(path), ..)] if w == ".import" => Some(path.clone()),
+        _ => None,
+    }).collect()
+}
+
+// externals：其他模組提供的函數與參數個數，驗證 CALL 時也算已定義
+pub fn load_module(content: &str, externals: &HashMap<String, usize>) -> Result<Module, Vec<Diagnostic>> {
+    let mut errors = Vec::new();
+    let mut exports = Vec::new();
+    let mut functions: Functions = HashMap::new();
+    let mut func_spans: HashMap<String, Vec<Span>> = HashMap::new();
+    let mut version_seen = false;
+    let mut current: Option<PendingFunc> = None;
+    let mut last_line = 0;
+
+    for (idx, line) in content.lines().enumerate() {
+        let line_no = idx + 1;
+        last_line = line_no;
+        let toks = match tokenize(line, line_no) {
+            Ok(toks) => toks,
+            Err(e) => { errors.push(e); continue; }
+        };
+        let Some((first, col, len)) = toks.first() else { continue };
+        let span = Span { line: line_no, col: *col, len: *len };
+        let words: Vec<&str> = toks.iter().map(|(t, ..)| match t { Tok::Word(w) => w.as_str(), Tok::Str(_) => "" }).collect();
+
+        if !version_seen {
+            match (words[0], words.get(1).map(|v| v.parse::<u32>())) {
+                (".version", Some(Ok(VERSION))) if words.len() == 2 => { version_seen = true; continue; }
+                (".version", Some(Ok(v))) => {
+                    errors.push(Diagnostic::new(format!("不支援的 IR 版本 {} (目前是 {})", v, VERSION), span));
+                }
+                _ => errors.push(Diagnostic::new(format!("缺少版本標頭 `.version {}`", VERSION), span)),
+            }
+            return Err(errors);
+        }
+
+        let Tok::Word(first) = first else {
+            errors.push(Diagnostic::new("這裡不能放字串", span));
+            continue;
+        };
+        match first.as_str() {
+            ".import" | ".export" if current.is_some() => {
+                errors.push(Diagnostic::new(format!("{} 不能放在 FUNC 裡面", first), span));
+            }
+            ".import" => match toks.get(1) {
+                // 路徑由 imports() 另外讀取，這裡只檢查格式
+                Some((Tok::Str(_), ..)) if toks.len() == 2 => {}
+                _ => errors.push(Diagnostic::new(".import 需要一個以 \"...\" 括起來的路徑", span)),
+            },
+            ".export" => match words.as_slice() {
+                [_, name] if !name.is_empty() => exports.push((name.to_string(), span)),
+                _ => errors.push(Diagnostic::new(".export 需要一個函數名稱", span)),
+            },
+            "FUNC" => {
+                if let Some(f) = &current {
+                    errors.push(Diagnostic::new(format!("函數 {} 還沒有 ENDFUNC", f.name), span));
+                }
+                let Some(name) = words.get(1).filter(|n| !n.is_empty()) else {
+                    errors.push(Diagnostic::new("FUNC 後面需要函數名稱", span));
+                    current = None;
+                    continue;
+                };
+                if functions.contains_key(*name) {
+                    errors.push(Diagnostic::new(format!("函數 {} 重複定義", name), span));
+                }
+                current = Some(PendingFunc {
+                    name: name.to_string(),
+                    params: words[2..].iter().map(|s| s.to_string()).collect(),
+                    code: Vec::new(),
+                    spans: Vec::new(),
+                    labels: HashMap::new(),
+                    jumps: Vec::new(),
+                    first_error: errors.len(),
+                });
+            }
+            "ENDFUNC" => match current.take() {
+                Some(mut f) => {
+                    for (ip, label, span) in std::mem::take(&mut f.jumps) {
+                        match f.labels.get(&label) {
+                            Some(&target) => match &mut f.code[ip] {
+                                IR::IfFalse(_, t) | IR::Goto(t) => *t = target,
+                                _ => unreachable!(),
+                            },
+                            None => errors.push(Diagnostic::new(format!("未定義的標籤 {}", label), span)),
+                        }
+                    }
+                    // 語法正確的函數才做後面的驗證，避免同一個錯誤引出一串訊息
+                    if errors.len() == f.first_error { func_spans.insert(f.name.clone(), f.spans); }
+                    functions.insert(f.name, (f.params, f.code));
+                }
+                None => errors.push(Diagnostic::new("ENDFUNC 沒有對應的 FUNC", span)),
+            },
+            w if w.ends_with(':') && toks.len() == 1 => match &mut current {
+                Some(f) => {
+                    if f.labels.insert(w.trim_end_matches(':').to_string(), f.code.len()).is_some() {
+                        errors.push(Diagnostic::new(format!("標籤 {} 重複定義", w.trim_end_matches(':')), span));
+                    }
+                }
+                None => errors.push(Diagnostic::new("標籤必須在 FUNC 裡面", span)),
+            },
+            op => {
+                let Some(f) = &mut current else {
+                    errors.push(Diagnostic::new("指令必須在 FUNC 裡面", span));
+                    continue;
+                };
+                match parse_instr(op, &toks[1..], span) {
+                    Ok((ir, jump)) => {
+                        if let Some((label, label_span)) = jump { f.jumps.push((f.code.len(), label, label_span)); }
+                        f.code.push(ir);
+                        f.spans.push(span);
+                    }
+                    Err(e) => errors.push(e),
+                }
+            }
+        }
+    }
+    if !version_seen {
+        errors.push(Diagnostic::new(format!("缺少版本標頭 `.version {}`", VERSION), Span { line: 1, col: 1, len: 1 }));
+    }
+    if let Some(f) = current {
+        errors.push(Diagnostic::new(format!("函數 {} 缺少 ENDFUNC", f.name), Span { line: last_line, col: 1, len: 1 }));
+    }
+    for (name, span) in &exports {
+        if !functions.contains_key(name) { errors.push(Diagnostic::new(format!("export 的函數 {} 沒有定義", name), *span)); }
+    }
+    let mut names: Vec<&String> = func_spans.keys().collect();
+    names.sort();
+    for name in names {
+        verify(&functions, externals, name, &func_spans[name], &mut errors);
+    }
+    let exports = exports.into_iter().map(|(name, _)| name).collect();
+    if errors.is_empty() { Ok(Module { functions, exports }) } else { Err(errors) }
+}
+
+// 解析一條指令；跳躍指令的目標先填 0，連同標籤名稱一起回傳，等 ENDFUNC 時回填
+#[allow(clippy::type_complexity)]
+fn parse_instr(op: &str, args: &[(Tok, usize, usize)], span: Span) -> Result<(IR, Option<(String, Span)>), Diagnostic> {
+    let arg_span = |i: usize| args.get(i).map(|(_, col, len)| Span { line: span.line, col: *col, len: *len }).unwrap_or(span);
+    let name = |i: usize| match args.get(i) {
+        Some((Tok::Word(w), ..)) if !w.ends_with(':') => Ok(w.clone()),
+        Some(_) => Err(Diagnostic::new("這裡需要名稱", arg_span(i))),
+        None => Err(Diagnostic::new(format!("{} 的運算元不夠", op), span)),
+    };
+    let names_from = |i: usize| (i..args.len()).map(name).collect::<Result<Vec<String>, _>>();
+    let fixed = |n: usize| {
+        if args.len() > n { Err(Diagnostic::new(format!("{} 只需要 {} 個運算元", op, n), arg_span(n))) } else { Ok(()) }
+    };
+
+    let ir = match op {
+        "LOAD_CONST" => {
+            fixed(2)?;
+            let v = match args.get(1) {
+                Some((Tok::Word(w), ..)) => w.parse().map_err(|_| Diagnostic::new(format!("無效的整數 {}", w), arg_span(1)))?,
+                _ => return Err(Diagnostic::new("LOAD_CONST 需要整數", arg_span(1))),
+            };
+            IR::LoadConst(name(0)?, v)
+        }
+        "LOAD_STR" => {
+            fixed(2)?;
+            match args.get(1) {
+                Some((Tok::Str(s), ..)) => IR::LoadStr(name(0)?, s.clone()),
+                _ => return Err(Diagnostic::new("LOAD_STR 需要以 \"...\" 括起來的字串", arg_span(1))),
+            }
+        }
+        "LOAD_VAR" | "STORE_VAR" | "LOAD_GLOBAL" | "STORE_GLOBAL" | "NEG" | "NOT" => {
+            fixed(2)?;
+            let (a, b) = (name(0)?, name(1)?);
+            match op {
+                "LOAD_VAR" => IR::LoadVar(a, b),
+                "STORE_VAR" => IR::StoreVar(a, b),
+                "LOAD_GLOBAL" => IR::LoadGlobal(a, b),
+                "STORE_GLOBAL" => IR::StoreGlobal(a, b),
+                "NEG" => IR::Neg(a, b),
+                _ => IR::Not(a, b),
+            }
+        }
+        "ADD" | "SUB" | "MUL" | "DIV" | "MOD" | "EQ" | "NE" | "LT" | "LE" | "GT" | "GE" | "INDEX_LOAD" | "INDEX_STORE" => {
+            fixed(3)?;
+            let (a, b, c) = (name(0)?, name(1)?, name(2)?);
+            match op {
+                "ADD" => IR::Add(a, b, c),
+                "SUB" => IR::Sub(a, b, c),
+                "MUL" => IR::Mul(a, b, c),
+                "DIV" => IR::Div(a, b, c),
+                "MOD" => IR::Mod(a, b, c),
+                "EQ" => IR::Eq(a, b, c),
+                "NE" => IR::Ne(a, b, c),
+                "LT" => IR::Lt(a, b, c),
+                "LE" => IR::Le(a, b, c),
+                "GT" => IR::Gt(a, b, c),
+                "GE" => IR::Ge(a, b, c),
+                "INDEX_LOAD" => IR::IndexLoad(a, b, c),
+                _ => IR::IndexStore(a, b, c),
+            }
+        }
+        "NEW_ARRAY" => IR::NewArray(name(0)?, names_from(1)?),
+        "CALL" => IR::Call(name(0)?, names_from(2)?, name(1)?),
+        "RETURN" => { fixed(1)?; IR::Return(name(0)?) }
+        "LABEL" => { fixed(0)?; IR::Label }
+        "IFFALSE" => { fixed(2)?; return Ok((IR::IfFalse(name(0)?, 0), Some((name(1)?, arg_span(1))))); }
+        "GOTO" => { fixed(1)?; return Ok((IR::Goto(0), Some((name(0)?, arg_span(0))))); }
+        _ => return Err(Diagnostic::new(format!("未知指令 {}", op), span)),
+    };
+    Ok((ir, None))
+}
+
+// 檢查暫存值在每條路徑上都先定義再使用 (對 CFG 做「一定已定義」的前向資料流分析)，
+// 以及呼叫的函數存在、參數個數正確
+fn verify(functions: &Functions, externals: &HashMap<String, usize>, name: &str, spans: &[Span], errors: &mut Vec<Diagnostic>) {
+    let (_, code) = &functions[name];
+    let at = |ip: usize| spans[ip];
+
+    for (ip, ir) in code.iter().enumerate() {
+        if let IR::Call(f, args, _) = ir {
+            let arity = match functions.get(f) {
+                Some((params, _)) => Some(params.len()),
+                None if externals.contains_key(f) => Some(externals[f]),
+                None => match NATIVES.iter().find(|(n, ..)| n == f) {
+                    Some((_, arity, _)) => *arity,
+                    None => { errors.push(Diagnostic::new(format!("呼叫了未定義的函數 {}", f), at(ip))); continue; }
+                },
+            };
+            if let Some(n) = arity.filter(|n| *n != args.len()) {
+                errors.push(Diagnostic::new(format!("函數 {} 需要 {} 個參數，這裡給了 {} 個", f, n, args.len()), at(ip)));
+            }
+        }
+    }
+
+    let cfg = Cfg::build(code);
+    // out[b] = 離開區塊 b 時一定已定義的暫存值；None 表示還沒算過 (視為全集)
+    let mut out: Vec<Option<HashSet<String>>> = vec![None; cfg.blocks.len()];
+    let entry_of = |b: usize, out: &[Option<HashSet<String>>]| -> HashSet<String> {
+        let mut known = cfg.blocks[b].preds.iter().filter_map(|p| out[*p].as_ref());
+        let first = known.next().cloned().unwrap_or_default();
+        known.fold(first, |acc, s| acc.intersection(s).cloned().collect())
+    };
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &b in &cfg.rpo {
+            let mut defined = entry_of(b, &out);
+            for ir in &code[cfg.blocks[b].start..cfg.blocks[b].end] {
+                if let Some(t) = def(ir) { defined.insert(t.clone()); }
+            }
+            if out[b].as_ref() != Some(&defined) { out[b] = Some(defined); changed = true; }
+        }
+    }
+    for &b in &cfg.rpo {
+        let mut defined = entry_of(b, &out);
+        let start = cfg.blocks[b].start;
+        for (ip, ir) in code[start..cfg.blocks[b].end].iter().enumerate().map(|(i, ir)| (start + i, ir)) {
+            for u in uses_mut(&mut ir.clone()) {
+                if !defined.contains(u.as_str()) {
+                    errors.push(Diagnostic::new(format!("暫存值 {} 可能在定義之前就被使用", u), at(ip)));
+                }
+            }
+            if let Some(t) = def(ir) { defined.insert(t.clone()); }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+// ==========================================================
+// 多檔案程式：import 與分開編譯
+//
+//   import 'util.p0';
+//   export fn square(n) { return n * n; }
+//
+// 每個檔案是一個模組，import 只看得到對方 export 的函數；沒有 export 的函數
+// 只能在自己的模組裡呼叫。所有函數最後都放進同一個 VM::functions，所以不同模組
+// 定義同名的函數 (不管有沒有 export) 是編譯錯誤。
+// 找模組的順序：先找寫 import 的檔案所在的目錄，再依序找 -I 指定的目錄。
+// 被 import 的模組編譯後，IR 連同 .import / .export 存到同目錄的 __p0cache__/<名稱>.ir；
+// 之後快取比原始碼和它 import 的模組 (原始碼與快取) 都新，就直接載入 IR，不再剖析；
+// 依賴的模組改過或重新編譯過，依賴它的模組也會重新編譯。快取載入或驗證失敗時改從原始碼編譯。
+// 全域變數只在宣告它的模組裡看得到，但和函數一樣不能和其他模組同名；
+// 初始化的程式碼接在共用的 __init__ 裡，所以有全域變數的模組不寫快取。
+// ==========================================================
+
+use super::*;
+use std::path::PathBuf;
+
+// 編譯錯誤，連同出錯的檔案，方便 report_errors 顯示原始碼
+pub struct ModuleError {
+    pub path: String,
+    pub source: String,
+    pub errors: Vec<Diagnostic>,
+}
+
+pub struct Loaded {
+    pub functions: Vec<String>, // 這次載入的所有函數
+    pub globals: Vec<(String, bool)>, // 主程式的全域變數與它是不是常數
+}
+
+struct Loader<'a> {
+    vm: &'a mut VM,
+    search: Vec<PathBuf>,
+    exports: HashMap<PathBuf, Vec<(String, usize)>>, // 已載入的模組 -> export 的函數與參數個數
+    owner: HashMap<String, String>,                  // 函數 -> 定義它的檔案
+    global_owner: HashMap<String, String>,           // 全域變數 -> 宣告它的檔案
+    visiting: Vec<(PathBuf, String)>,                // 正在載入的模組 (偵測循環 import)
+}
+
+// 載入主程式與它 import 的所有模組，編譯進 vm
+pub fn load(vm: &mut VM, path: &str, search: &[String], require_main: bool) -> Result<Loaded, ModuleError> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| ModuleError { path: path.to_string(), source: String::new(), errors: vec![read_error(path, e)] })?;
+    let key = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let mut loader = Loader { vm, search: search.iter().map(PathBuf::from).collect(), exports: HashMap::new(), owner: HashMap::new(), global_owner: HashMap::new(), visiting: Vec::new() };
+    let (_, _, globals) = loader.compile(&key, path, &source, require_main)?;
+    let mut functions: Vec<String> = loader.owner.into_keys().collect();
+    functions.sort();
+    Ok(Loaded { functions, globals })
+}
+
+fn read_error(path: &str, e: std::io::Error) -> Diagnostic {
+    Diagnostic::new(format!("無法讀取 {}: {}", path, e), Span { line: 1, col: 1, len: 1 })
+}
+
+fn cache_path(path: &Path) -> PathBuf {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    dir.join("__p0cache__").join(path.file_stem().unwrap_or_default()).with_extension("ir")
+}
+
+// 快取的修改時間不早於原始碼才算有效
+fn is_fresh(source: &Path, cache: &Path) -> bool {
+    let mtime = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+    matches!((mtime(source), mtime(cache)), (Some(s), Some(c)) if c >= s)
+}
+
+impl<'a> Loader<'a> {
+    // 依 import 寫的路徑找到模組：(用來比對的絕對路徑, 顯示用的路徑)
+    fn resolve(&self, importer: &str, name: &str) -> Option<(PathBuf, String)> {
+        let base = Path::new(importer).parent().unwrap_or(Path::new("")).to_path_buf();
+        std::iter::once(base).chain(self.search.iter().cloned())
+            .map(|dir| dir.join(name))
+            .find(|p| p.is_file())
+            .map(|p| (fs::canonicalize(&p).unwrap_or_else(|_| p.clone()), p.display().to_string()))
+    }
+
+    // 載入一個被 import 的模組 (已經載入過的直接跳過)
+    fn load_module(&mut self, key: &Path, path: &str) -> Result<(), ModuleError> {
+        if self.exports.contains_key(key) { return Ok(()); }
+        let cache = cache_path(key);
+        if is_fresh(key, &cache) && self.load_cache(key, path, &cache)? { return Ok(()); }
+        let source = fs::read_to_string(key)
+            .map_err(|e| ModuleError { path: path.to_string(), source: String::new(), errors: vec![read_error(path, e)] })?;
+        let (imports, names, globals) = self.compile(key, path, &source, false)?;
+        if !globals.is_empty() { return Ok(()); }
+
+        // 寫快取失敗 (例如目錄不能寫) 不影響編譯
+        let functions = names.iter().map(|f| (f.clone(), self.vm.functions[f].clone())).collect();
+        let exports: Vec<String> = self.exports[key].iter().map(|(f, _)| f.clone()).collect();
+        if let Some(dir) = cache.parent() {
+            if fs::create_dir_all(dir).is_ok() { fs::write(&cache, irtext::dump_module(&functions, &imports, &exports)).ok(); }
+        }
+        Ok(())
+    }
+
+    // 從快取載入；快取有任何問題就回傳 false，改從原始碼編譯 (才有準確的錯誤位置)
+    fn load_cache(&mut self, key: &Path, path: &str, cache: &Path) -> Result<bool, ModuleError> {
+        let text = match fs::read_to_string(cache) { Ok(text) => text, Err(_) => return Ok(false) };
+        self.visiting.push((key.to_path_buf(), path.to_string()));
+        let mut visible = HashMap::new();
+        for name in irtext::imports(&text) {
+            let (dep, dep_path) = match self.resolve(path, &name) {
+                Some(found) if !self.visiting.iter().any(|(k, _)| *k == found.0) => found,
+                _ => { self.visiting.pop(); return Ok(false); }
+            };
+            self.load_module(&dep, &dep_path)?;
+            // 依賴的模組在這個快取之後改過 (或剛重新編譯)，export 可能不一樣了
+            let dep_cache = cache_path(&dep);
+            if !is_fresh(&dep, cache) || (dep_cache.exists() && !is_fresh(&dep_cache, cache)) {
+                self.visiting.pop();
+                return Ok(false);
+            }
+            visible.extend(self.exports[&dep].iter().cloned());
+        }
+        self.visiting.pop();
+
+        let module = match irtext::load_module(&text, &visible) { Ok(module) => module, Err(_) => return Ok(false) };
+        if module.functions.keys().any(|f| self.owner.contains_key(f)) { return Ok(false); }
+        let exports = module.exports.iter().map(|f| (f.clone(), module.functions[f].0.len())).collect();
+        self.exports.insert(key.to_path_buf(), exports);
+        for (name, func) in module.functions {
+            self.owner.insert(name.clone(), path.to_string());
+            self.vm.lines.remove(&name);
+            self.vm.files.remove(&name);
+            self.vm.functions.insert(name, func);
+        }
+        Ok(true)
+    }
+
+    // 剖析、檢查並編譯一個模組的原始碼；先載入它 import 的模組。
+    // 回傳 import 的路徑、定義的函數，以及宣告的全域變數 (是不是常數)
+    #[allow(clippy::type_complexity)]
+    fn compile(&mut self, key: &Path, path: &str, source: &str, require_main: bool) -> Result<(Vec<String>, Vec<String>, Vec<(String, bool)>), ModuleError> {
+        let fail = |errors| ModuleError { path: path.to_string(), source: source.to_string(), errors };
+        let mut parser = Parser::new(Lexer::new(source));
+        let program = parser.parse_program().map_err(fail)?;
+
+        self.visiting.push((key.to_path_buf(), path.to_string()));
+        let mut errors = Vec::new();
+        let mut visible = HashMap::new();
+        for (name, span) in &parser.imports {
+            let (dep, dep_path) = match self.resolve(path, name) {
+                Some(found) => found,
+                None => { errors.push(Diagnostic::new(format!("找不到模組 '{}'", name), *span)); continue; }
+            };
+            if let Some(i) = self.visiting.iter().position(|(k, _)| *k == dep) {
+                let chain: Vec<&str> = self.visiting[i..].iter().map(|(_, p)| p.as_str()).chain([dep_path.as_str()]).collect();
+                errors.push(Diagnostic::new(format!("循環 import: {}", chain.join(" -> ")), *span));
+                continue;
+            }
+            self.load_module(&dep, &dep_path)?;
+            visible.extend(self.exports[&dep].iter().cloned());
+        }
+        self.visiting.pop();
+
+        for stmt in &program {
+            match &stmt.kind {
+                StmtKind::FuncDecl(name, ..) => if let Some(other) = self.owner.get(name) {
+                    errors.push(Diagnostic::new(format!("函數 '{}' 已經在 {} 定義過", name, other), stmt.span));
+                },
+                StmtKind::VarDecl(name, _) | StmtKind::ConstDecl(name, _) => if let Some(other) = self.global_owner.get(name) {
+                    errors.push(Diagnostic::new(format!("全域變數 '{}' 已經在 {} 宣告過", name, other), stmt.span));
+                },
+                _ => {}
+            }
+        }
+        if !errors.is_empty() { return Err(fail(errors)); }
+        Resolver::with_functions(visible.into_iter().collect(), require_main).resolve_program(&program).map_err(fail)?;
+
+        let (mut names, mut exports, mut globals) = (Vec::new(), Vec::new(), Vec::new());
+        for stmt in &program {
+            match &stmt.kind {
+                StmtKind::FuncDecl(name, params, _) => {
+                    self.owner.insert(name.clone(), path.to_string());
+                    names.push(name.clone());
+                    if parser.exports.contains(name) { exports.push((name.clone(), params.len())); }
+                }
+                StmtKind::VarDecl(name, _) | StmtKind::ConstDecl(name, _) => {
+                    self.global_owner.insert(name.clone(), path.to_string());
+                    globals.push((name.clone(), matches!(stmt.kind, StmtKind::ConstDecl(..))));
+                }
+                _ => {}
+            }
+        }
+        self.exports.insert(key.to_path_buf(), exports);
+        self.vm.compile(program, path);
+        Ok((parser.imports.into_iter().map(|(name, _)| name).collect(), names, globals))
+    }
+}
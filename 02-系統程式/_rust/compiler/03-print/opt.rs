@@ -0,0 +1,168 @@
+// ==========================================================
+// IR 最佳化 (-O1)
+//
+// gen_expr 每個子運算式都會產生一個新的暫存值，所以 IR 裡有大量多餘的
+// LOAD_VAR / LOAD_CONST。這裡對每個函數反覆做下列幾件事，直到不再變化：
+//   1. 基本區塊內的常數摺疊、常數/複製傳播 (變數剛存進去就讀出來時直接沿用原本的暫存值)
+//   2. 刪除沒有人使用、也沒有副作用的暫存值定義
+//   3. 刪除從入口走不到的指令 (例如 RETURN 之後的程式碼)、LABEL 以及跳到下一行的 GOTO
+// 刪除指令後，IFFALSE / GOTO 的目標會重新對應到新的位置。
+// ==========================================================
+
+use super::*;
+use std::collections::HashSet;
+
+pub fn optimize(mut code: Vec<IR>) -> Vec<IR> {
+    loop {
+        let before = code.clone();
+        propagate(&mut code);
+        code = remove_dead(code);
+        if code == before { return code; }
+    }
+}
+
+// 基本區塊的開頭：函數入口、所有跳躍目標，以及跳躍/回傳的下一行
+fn block_starts(code: &[IR]) -> HashSet<usize> {
+    let mut starts = HashSet::from([0]);
+    for (ip, ir) in code.iter().enumerate() {
+        match ir {
+            IR::IfFalse(_, target) | IR::Goto(target) => { starts.insert(*target); starts.insert(ip + 1); }
+            IR::Return(_) => { starts.insert(ip + 1); }
+            _ => {}
+        }
+    }
+    starts
+}
+
+// 指令定義的暫存值 (StoreVar、StoreGlobal 與 IndexStore 寫的是變數/陣列，不算)
+pub(super) fn def(ir: &IR) -> Option<&String> {
+    match ir {
+        IR::LoadConst(t, _) | IR::LoadStr(t, _) | IR::LoadVar(t, _) | IR::LoadGlobal(t, _) | IR::Neg(t, _) | IR::Not(t, _)
+        | IR::Add(t, ..) | IR::Sub(t, ..) | IR::Mul(t, ..) | IR::Div(t, ..) | IR::Mod(t, ..)
+        | IR::Eq(t, ..) | IR::Ne(t, ..) | IR::Lt(t, ..) | IR::Le(t, ..) | IR::Gt(t, ..) | IR::Ge(t, ..)
+        | IR::NewArray(t, _) | IR::IndexLoad(t, ..) | IR::Call(_, _, t) => Some(t),
+        _ => None,
+    }
+}
+
+// 指令讀取的暫存值
+pub(super) fn uses_mut(ir: &mut IR) -> Vec<&mut String> {
+    match ir {
+        IR::StoreVar(_, t) | IR::StoreGlobal(_, t) | IR::Neg(_, t) | IR::Not(_, t) | IR::Return(t) | IR::IfFalse(t, _) => vec![t],
+        IR::Add(_, l, r) | IR::Sub(_, l, r) | IR::Mul(_, l, r) | IR::Div(_, l, r) | IR::Mod(_, l, r)
+        | IR::Eq(_, l, r) | IR::Ne(_, l, r) | IR::Lt(_, l, r) | IR::Le(_, l, r) | IR::Gt(_, l, r) | IR::Ge(_, l, r)
+        | IR::IndexLoad(_, l, r) => vec![l, r],
+        IR::IndexStore(a, i, t) => vec![a, i, t],
+        IR::NewArray(_, items) | IR::Call(_, items, _) => items.iter_mut().collect(),
+        IR::LoadConst(..) | IR::LoadStr(..) | IR::LoadVar(..) | IR::LoadGlobal(..) | IR::Goto(_) | IR::Label => vec![],
+    }
+}
+
+// 常數摺疊與傳播，只在基本區塊內進行，區塊開頭把所有已知資訊清掉
+fn propagate(code: &mut [IR]) {
+    let starts = block_starts(code);
+    let mut consts: HashMap<String, i32> = HashMap::new();
+    let mut copies: HashMap<String, String> = HashMap::new(); // 暫存值 -> 內容相同、較早定義的暫存值
+    let mut vars: HashMap<String, String> = HashMap::new(); // 變數 -> 目前持有它的值的暫存值
+    for (ip, ir) in code.iter_mut().enumerate() {
+        if starts.contains(&ip) { consts.clear(); copies.clear(); vars.clear(); }
+        for u in uses_mut(ir) {
+            if let Some(src) = copies.get(u) { *u = src.clone(); }
+        }
+
+        let folded = match &*ir {
+            IR::Add(t, l, r) | IR::Sub(t, l, r) | IR::Mul(t, l, r) | IR::Div(t, l, r) | IR::Mod(t, l, r)
+            | IR::Lt(t, l, r) | IR::Le(t, l, r) | IR::Gt(t, l, r) | IR::Ge(t, l, r) => match (consts.get(l), consts.get(r)) {
+                // 除以零等執行期錯誤照樣留到執行時才發生
+                (Some(a), Some(b)) => match binary_op(ir, &Value::Int(*a), &Value::Int(*b)) {
+                    Ok(Value::Int(v)) => Some(IR::LoadConst(t.clone(), v)),
+                    _ => None,
+                },
+                _ => None,
+            },
+            IR::Eq(t, l, r) | IR::Ne(t, l, r) => match (consts.get(l), consts.get(r)) {
+                (Some(a), Some(b)) => Some(IR::LoadConst(t.clone(), ((a == b) == matches!(ir, IR::Eq(..))) as i32)),
+                _ => None,
+            },
+            IR::Neg(t, s) => consts.get(s).map(|v| IR::LoadConst(t.clone(), v.wrapping_neg())),
+            IR::Not(t, s) => consts.get(s).map(|v| IR::LoadConst(t.clone(), (*v == 0) as i32)),
+            IR::LoadVar(t, n) => vars.get(n).map(|src| match consts.get(src) {
+                Some(v) => IR::LoadConst(t.clone(), *v),
+                None => IR::LoadVar(t.clone(), n.clone()),
+            }),
+            // 條件已知：永遠跳就換成 GOTO，永遠不跳就變成空指令，留給 remove_dead 刪掉
+            IR::IfFalse(t, target) => consts.get(t).map(|v| if *v == 0 { IR::Goto(*target) } else { IR::Label }),
+            _ => None,
+        };
+        if let Some(new_ir) = folded { *ir = new_ir; }
+
+        // 被重新定義的暫存值，之前記錄的關係都失效了
+        if let Some(t) = def(ir).cloned() {
+            consts.remove(&t);
+            copies.remove(&t);
+            copies.retain(|_, src| *src != t);
+            vars.retain(|_, src| *src != t);
+        }
+        match &*ir {
+            IR::LoadConst(t, v) => { consts.insert(t.clone(), *v); }
+            IR::LoadVar(t, n) => match vars.get(n) {
+                Some(src) => { copies.insert(t.clone(), src.clone()); }
+                None => { vars.insert(n.clone(), t.clone()); }
+            },
+            IR::StoreVar(n, t) => { vars.insert(n.clone(), t.clone()); }
+            _ => {}
+        }
+    }
+}
+
+// 刪除無用的暫存值定義、走不到的指令、LABEL 與多餘的 GOTO，並重新對應跳躍目標
+fn remove_dead(code: Vec<IR>) -> Vec<IR> {
+    let mut used: HashSet<String> = HashSet::new();
+    for ir in &code {
+        for u in uses_mut(&mut ir.clone()) { used.insert(u.clone()); }
+    }
+
+    let mut reachable = vec![false; code.len()];
+    let mut work = vec![0];
+    while let Some(ip) = work.pop() {
+        if ip >= code.len() || reachable[ip] { continue; }
+        reachable[ip] = true;
+        match &code[ip] {
+            IR::Goto(target) => work.push(*target),
+            IR::IfFalse(_, target) => { work.push(*target); work.push(ip + 1); }
+            IR::Return(_) => {}
+            _ => work.push(ip + 1),
+        }
+    }
+
+    // 只刪除不會出錯的指令；算術運算可能在執行期發生型別錯誤，即使結果沒用也要保留
+    let pure = |ir: &IR| matches!(ir, IR::LoadConst(..) | IR::LoadStr(..) | IR::LoadVar(..) | IR::LoadGlobal(..) | IR::Eq(..) | IR::Ne(..) | IR::Not(..) | IR::NewArray(..));
+    let mut keep: Vec<bool> = code.iter().enumerate().map(|(ip, ir)| {
+        reachable[ip] && !matches!(ir, IR::Label) && !(pure(ir) && def(ir).is_some_and(|t| !used.contains(t)))
+    }).collect();
+
+    // new_pos[ip] = 原本第 ip 行之後第一個保留下來的指令的新位置 (可以等於新長度，表示函數結尾)
+    let remap = |keep: &[bool]| {
+        let mut new_pos = vec![0; code.len() + 1];
+        let mut n = 0;
+        for ip in 0..code.len() {
+            new_pos[ip] = n;
+            if keep[ip] { n += 1; }
+        }
+        new_pos[code.len()] = n;
+        new_pos
+    };
+    let mut new_pos = remap(&keep);
+    // 跳到 (刪除後的) 下一行的 GOTO 沒有作用
+    for ip in 0..code.len() {
+        if let IR::Goto(target) = code[ip] {
+            if keep[ip] && new_pos[target] == new_pos[ip] + 1 { keep[ip] = false; new_pos = remap(&keep); }
+        }
+    }
+
+    code.into_iter().enumerate().filter(|(ip, _)| keep[*ip]).map(|(_, ir)| match ir {
+        IR::Goto(target) => IR::Goto(new_pos[target]),
+        IR::IfFalse(t, target) => IR::IfFalse(t, new_pos[target]),
+        ir => ir,
+    }).collect()
+}
@@ -0,0 +1,296 @@
+// ==========================================================
+// 格式化程式 (compiler fmt [--check] <檔案>...)
+//
+// 從 AST 重新印出原始碼，統一成一種寫法：
+//   - 縮排兩個空白，{ 接在同一行，一行一個敘述
+//   - 二元運算子前後各一個空白，逗號後面一個空白，只在優先順序需要時加括號
+//   - 字串用單引號，內容有單引號時才用雙引號
+//   - 最外層的函數之間空一行；其他地方保留原始碼裡的空行 (最多一行)
+// 註解不在 AST 裡，依位置放回去：寫在程式碼後面的註解仍然接在同一行的後面，
+// 其他的放在下一個敘述 (或區塊結尾的 }) 前面。
+// --check 不改檔案，有檔案需要格式化時結束碼是 1。
+// ==========================================================
+
+use super::*;
+
+// 後置的索引 a[i] 比單元運算子綁得更緊
+const POSTFIX_PREC: i32 = UNARY_PREC + 1;
+
+struct Printer<'a> {
+    out: String,
+    indent: usize,
+    lines: Vec<&'a str>,
+    comments: Vec<Comment>,
+    next_comment: usize,
+    block_ends: std::vec::IntoIter<Span>,
+    starts: Vec<(usize, usize)>, // 每個敘述開頭的 (行, 列)，依位置排序
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let check = args.iter().any(|a| a == "--check");
+    let files: Vec<&String> = args.iter().filter(|a| *a != "--check").collect();
+    if files.is_empty() || files.iter().any(|f| f.starts_with('-')) {
+        eprintln!("用法: compiler fmt [--check] <檔案>...");
+        return 1;
+    }
+    let mut status = 0;
+    for path in files {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => { eprintln!("無法讀取 {}: {}", path, e); status = 1; continue; }
+        };
+        let formatted = match format(&source) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                for e in &errors { eprintln!("{}", e.render(path, &source)); }
+                status = 1;
+                continue;
+            }
+        };
+        if formatted == source { continue; }
+        if check {
+            println!("需要格式化: {}", path);
+            status = 1;
+        } else {
+            fs::write(path, &formatted).expect("無法寫入原始碼");
+            println!("已格式化 {}", path);
+        }
+    }
+    status
+}
+
+// 剖析整個檔案並印回原始碼；有語法錯誤時不格式化
+fn format(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let mut parser = Parser::new(Lexer::new(source));
+    let program = parser.parse_program()?;
+    let mut starts = Vec::new();
+    collect_starts(&program, &mut starts);
+    starts.sort();
+    let mut p = Printer {
+        out: String::new(), indent: 0, lines: source.lines().collect(),
+        comments: std::mem::take(&mut parser.lexer.comments), next_comment: 0,
+        block_ends: std::mem::take(&mut parser.block_ends).into_iter(),
+        starts,
+    };
+
+    // import 不在 AST 裡，和其他宣告一起依位置排序
+    let mut items: Vec<(Span, Option<&Stmt>, String)> = parser.imports.iter()
+        .map(|(path, span)| (*span, None, format!("import {};", quote(path))))
+        .collect();
+    items.extend(program.iter().map(|s| (s.span, Some(s), String::new())));
+    items.sort_by_key(|(span, ..)| (span.line, span.col));
+
+    let mut after_fn = false;
+    for (span, stmt, text) in items {
+        let is_fn = matches!(stmt, Some(Stmt { kind: StmtKind::FuncDecl(..), .. }));
+        if is_fn || after_fn { p.blank_line(); }
+        after_fn = is_fn;
+        p.comments_before(span);
+        p.keep_blank(span.line);
+        match stmt {
+            Some(Stmt { kind: StmtKind::FuncDecl(name, params, body), .. }) => {
+                let export = if parser.exports.contains(name) { "export " } else { "" };
+                p.line(&format!("{}fn {}({}) {{", export, name, params.join(", ")));
+                p.open_block(span, body);
+            }
+            Some(stmt) => p.stmt(stmt),
+            None => {
+                p.line(&text);
+                p.trailing(span);
+            }
+        }
+    }
+    p.comments_before(Span { line: usize::MAX, col: 0, len: 0 });
+    Ok(p.out)
+}
+
+// 區塊裡的敘述 (for 的 init 與 step 寫在標頭裡，不算)
+fn collect_starts(stmts: &[Stmt], starts: &mut Vec<(usize, usize)>) {
+    for s in stmts {
+        starts.push((s.span.line, s.span.col));
+        match &s.kind {
+            StmtKind::FuncDecl(_, _, body) | StmtKind::While(_, body) | StmtKind::For(_, _, _, body) => collect_starts(body, starts),
+            StmtKind::If(_, then_part, else_part) => {
+                collect_starts(then_part, starts);
+                if let Some(else_part) = else_part { collect_starts(else_part, starts); }
+            }
+            _ => {}
+        }
+    }
+}
+
+// 字串的內容不能跳脫，只能挑一種不會出現在內容裡的引號
+fn quote(s: &str) -> String {
+    if s.contains('\'') { format!("\"{}\"", s) } else { format!("'{}'", s) }
+}
+
+fn op_text(op: &Token) -> &'static str {
+    match op {
+        Token::Plus => "+", Token::Minus => "-", Token::Mul => "*", Token::Div => "/", Token::Mod => "%",
+        Token::Eq => "==", Token::Ne => "!=", Token::Lt => "<", Token::Le => "<=", Token::Gt => ">", Token::Ge => ">=",
+        Token::And => "&&", Token::Or => "||", Token::Not => "!",
+        _ => unreachable!("不是運算子: {}", op),
+    }
+}
+
+fn expr(e: &Expr) -> String {
+    match &e.kind {
+        ExprKind::Number(v) => v.to_string(),
+        ExprKind::Variable(name) => name.clone(),
+        ExprKind::Str(s) => quote(s),
+        ExprKind::Array(items) => format!("[{}]", list(items)),
+        ExprKind::Index(base, index) => format!("{}[{}]", operand(base, POSTFIX_PREC), expr(index)),
+        // -(-g) 印成 --g 會讓人誤以為是另一個運算子，保留括號
+        ExprKind::UnaryOp(op, e) if matches!(e.kind, ExprKind::UnaryOp(..)) => format!("{}({})", op_text(op), expr(e)),
+        ExprKind::UnaryOp(op, e) => format!("{}{}", op_text(op), operand(e, UNARY_PREC)),
+        ExprKind::BinaryOp(l, op, r) => {
+            // 左結合：右邊同一層的運算要加括號
+            let p = precedence(op).unwrap();
+            format!("{} {} {}", operand(l, p), op_text(op), operand(r, p + 1))
+        }
+        ExprKind::Call(name, args) => format!("{}({})", name, list(args)),
+    }
+}
+
+// 子表達式綁得比 min 鬆時加上括號
+fn operand(e: &Expr, min: i32) -> String {
+    let prec = match &e.kind {
+        ExprKind::BinaryOp(_, op, _) => precedence(op).unwrap(),
+        ExprKind::UnaryOp(..) => UNARY_PREC,
+        _ => POSTFIX_PREC,
+    };
+    if prec < min { format!("({})", expr(e)) } else { expr(e) }
+}
+
+fn list(items: &[Expr]) -> String {
+    items.iter().map(expr).collect::<Vec<_>>().join(", ")
+}
+
+// 不含結尾分號的簡單敘述 (for 的 init 與 step 也用這個)
+fn simple(stmt: &Stmt) -> String {
+    match &stmt.kind {
+        StmtKind::VarDecl(name, e) => format!("let {} = {}", name, expr(e)),
+        StmtKind::ConstDecl(name, e) => format!("const {} = {}", name, expr(e)),
+        StmtKind::Assign(name, e) => format!("{} = {}", name, expr(e)),
+        StmtKind::IndexAssign(base, index, value) => format!("{}[{}] = {}", operand(base, POSTFIX_PREC), expr(index), expr(value)),
+        StmtKind::ExprStmt(e) => expr(e),
+        _ => unreachable!("不是簡單敘述"),
+    }
+}
+
+impl Printer<'_> {
+    fn line(&mut self, text: &str) {
+        self.out.push_str(&"  ".repeat(self.indent));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn blank_line(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") { self.out.push('\n'); }
+    }
+
+    // 原始碼裡第 line 行的上一行是空行就保留一個空行，區塊的開頭除外
+    fn keep_blank(&mut self, line: usize) {
+        let blank = line >= 2 && self.lines.get(line - 2).is_some_and(|l| l.trim().is_empty());
+        if blank && !self.out.ends_with("{\n") { self.blank_line(); }
+    }
+
+    // 位置在 span 之前、還沒印出的註解各自成一行
+    fn comments_before(&mut self, span: Span) {
+        while let Some(c) = self.comments.get(self.next_comment) {
+            if (c.span.line, c.span.col) >= (span.line, span.col) { break; }
+            let (line, text) = (c.span.line, c.text.clone());
+            self.next_comment += 1;
+            self.keep_blank(line);
+            self.line(&text);
+        }
+    }
+
+    // 寫在 after 那一行程式碼後面的註解接在剛印出的那一行後面。中間還隔著沒印出的敘述或 }
+    // 的註解屬於後面的程式碼，留給 comments_before 或 close_block
+    fn trailing(&mut self, after: Span) {
+        let from = (after.line, after.col);
+        while let Some(c) = self.comments.get(self.next_comment) {
+            if !c.trailing || c.span.line != after.line { break; }
+            let at = (c.span.line, c.span.col);
+            let next_end = self.block_ends.as_slice().first().map(|e| (e.line, e.col));
+            if next_end.is_some_and(|e| e < at) || self.starts.iter().any(|s| from < *s && *s < at) { break; }
+            self.out.pop(); // 換行
+            self.out.push_str("  ");
+            self.out.push_str(&c.text);
+            self.out.push('\n');
+            self.next_comment += 1;
+        }
+    }
+
+    // 印完 { 那一行之後：同一行如果還有敘述，註解留給那個敘述
+    fn header_trailing(&mut self, after: Span, body: &[Stmt]) {
+        if body.first().is_none_or(|s| s.span.line != after.line) { self.trailing(after); }
+    }
+
+    fn open_block(&mut self, after: Span, body: &[Stmt]) {
+        self.header_trailing(after, body);
+        self.block(body);
+    }
+
+    // 區塊內容與結尾的 }；開頭的 { 由呼叫端印出
+    fn block(&mut self, stmts: &[Stmt]) {
+        self.block_body(stmts);
+        let end = self.close_block();
+        self.line("}");
+        self.trailing(end);
+    }
+
+    fn block_body(&mut self, stmts: &[Stmt]) {
+        self.indent += 1;
+        for s in stmts {
+            self.comments_before(s.span);
+            self.keep_blank(s.span.line);
+            self.stmt(s);
+        }
+    }
+
+    // 印出區塊結尾 } 之前的註解，回傳 } 的位置
+    fn close_block(&mut self) -> Span {
+        let end = self.block_ends.next().expect("區塊結尾的數量應該和剖析時一樣");
+        self.comments_before(end);
+        self.indent -= 1;
+        end
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        let span = stmt.span;
+        match &stmt.kind {
+            StmtKind::If(cond, then_part, else_part) => {
+                self.line(&format!("if ({}) {{", expr(cond)));
+                match else_part {
+                    None => self.open_block(span, then_part),
+                    Some(else_stmts) => {
+                        self.header_trailing(span, then_part);
+                        self.block_body(then_part);
+                        let end = self.close_block();
+                        self.line("} else {");
+                        self.open_block(end, else_stmts);
+                    }
+                }
+            }
+            StmtKind::While(cond, body) => {
+                self.line(&format!("while ({}) {{", expr(cond)));
+                self.open_block(span, body);
+            }
+            StmtKind::For(init, cond, step, body) => {
+                let init = init.as_deref().map(simple).unwrap_or_default();
+                let cond = cond.as_ref().map(|c| format!(" {}", expr(c))).unwrap_or_default();
+                let step = step.as_deref().map(|s| format!(" {}", simple(s))).unwrap_or_default();
+                self.line(&format!("for ({};{};{}) {{", init, cond, step));
+                self.open_block(span, body);
+            }
+            StmtKind::Break => self.line("break;"),
+            StmtKind::Continue => self.line("continue;"),
+            StmtKind::Return(e) => self.line(&format!("return {};", expr(e))),
+            StmtKind::FuncDecl(..) => unreachable!("函數只會出現在最外層"),
+            _ => self.line(&format!("{};", simple(stmt))),
+        }
+        if !matches!(stmt.kind, StmtKind::If(..) | StmtKind::While(..) | StmtKind::For(..)) { self.trailing(span); }
+    }
+}
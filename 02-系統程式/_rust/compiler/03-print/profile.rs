@@ -0,0 +1,139 @@
+// ==========================================================
+// 效能分析 (--profile)
+//
+// 和除錯器一樣用 VM::step 一條一條執行，同時記錄：
+//   - 每種 IR 指令、每個函數各執行了幾條指令
+//   - 每個函數的呼叫次數，以及含/不含子呼叫的時間
+// 遞迴時只有最外層那次呼叫的時間算進「含子呼叫」，才不會重複計算。
+// 程式結束 (包括執行期錯誤) 時把報告印到 stderr，不會和程式本身的輸出混在一起。
+// --folded <檔案> 另外輸出 flamegraph.pl / inferno 可以直接使用的 folded stacks，
+// 權重是 IR 指令數，同一個程式每次跑出來都一樣。
+// ==========================================================
+
+use super::*;
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct FuncStats {
+    calls: u64,
+    instrs: u64,
+    inclusive: Duration,
+    exclusive: Duration,
+}
+
+// 呼叫堆疊上的一層：開始時間、子呼叫花掉的時間、folded 的路徑、還沒記進統計的指令數
+struct Active<'a> {
+    func: &'a str,
+    start: Instant,
+    children: Duration,
+    path: String,
+    pending: u64,
+}
+
+struct Profiler<'a> {
+    opcodes: HashMap<&'static str, u64>,
+    funcs: HashMap<&'a str, FuncStats>,
+    on_stack: HashMap<&'a str, usize>,
+    active: Vec<Active<'a>>,
+    folded: Option<HashMap<String, u64>>,
+}
+
+pub fn run(vm: &VM, folded_path: Option<&str>) -> Result<Value, RuntimeError> {
+    vm.init_globals()?; // 全域變數的初始化不算進分析
+    let started = Instant::now();
+    let mut stack = vec![vm.new_frame("main", Vec::new()).ok_or_else(|| RuntimeError::new("main", 0, "找不到函數 'main'"))?];
+    let mut prof = Profiler {
+        opcodes: HashMap::new(), funcs: HashMap::new(), on_stack: HashMap::new(), active: Vec::new(),
+        folded: folded_path.map(|_| HashMap::new()),
+    };
+    prof.enter(stack[0].func);
+
+    let result = loop {
+        let frame = stack.last().unwrap();
+        if let Some(ir) = frame.code.get(frame.ip) {
+            *prof.opcodes.entry(ir.opcode()).or_insert(0) += 1;
+            prof.active.last_mut().unwrap().pending += 1;
+        }
+        let depth = stack.len();
+        let step = vm.step(&mut stack);
+        if stack.len() > depth { prof.enter(stack.last().unwrap().func); }
+        if stack.len() < depth { prof.leave(); }
+        match step {
+            Ok(Some(v)) => break Ok(v),
+            Ok(None) => {}
+            Err(mut e) => { e.backtrace = vm.backtrace(&stack); break Err(e); }
+        }
+    };
+    // 出錯時堆疊上還有沒結束的呼叫，把它們的時間也算進去
+    while !prof.active.is_empty() { prof.leave(); }
+
+    prof.report(started.elapsed());
+    if let (Some(path), Some(folded)) = (folded_path, &prof.folded) {
+        let mut lines: Vec<String> = folded.iter().filter(|(_, n)| **n > 0).map(|(stack, n)| format!("{} {}", stack, n)).collect();
+        lines.sort();
+        fs::write(path, lines.join("\n") + "\n").expect("無法寫入 folded stacks");
+        eprintln!("已輸出 {}", path);
+    }
+    result
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+impl<'a> Profiler<'a> {
+    // 把目前這一層累積的指令數記進函數統計與 folded
+    fn flush(&mut self) {
+        let top = match self.active.last_mut() { Some(top) => top, None => return };
+        self.funcs.entry(top.func).or_default().instrs += top.pending;
+        if let Some(folded) = &mut self.folded { *folded.entry(top.path.clone()).or_insert(0) += top.pending; }
+        top.pending = 0;
+    }
+
+    fn enter(&mut self, func: &'a str) {
+        self.flush();
+        let path = match (&self.folded, self.active.last()) {
+            (None, _) => String::new(),
+            (Some(_), None) => func.to_string(),
+            (Some(_), Some(parent)) => format!("{};{}", parent.path, func),
+        };
+        self.funcs.entry(func).or_default().calls += 1;
+        *self.on_stack.entry(func).or_insert(0) += 1;
+        self.active.push(Active { func, start: Instant::now(), children: Duration::ZERO, path, pending: 0 });
+    }
+
+    fn leave(&mut self) {
+        self.flush();
+        let done = self.active.pop().unwrap();
+        let elapsed = done.start.elapsed();
+        let count = self.on_stack.get_mut(done.func).unwrap();
+        *count -= 1;
+        let outermost = *count == 0;
+        let stats = self.funcs.get_mut(done.func).unwrap();
+        stats.exclusive += elapsed.saturating_sub(done.children);
+        if outermost { stats.inclusive += elapsed; }
+        if let Some(parent) = self.active.last_mut() { parent.children += elapsed; }
+    }
+
+    fn report(&self, total: Duration) {
+        let instrs: u64 = self.opcodes.values().sum();
+        eprintln!();
+        eprintln!("== 效能分析：共 {} 條 IR 指令，{:.3} ms ==", instrs, ms(total));
+
+        let mut opcodes: Vec<(&&str, &u64)> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        eprintln!("{:<12} {:>12} {:>8}", "opcode", "count", "%");
+        for (op, n) in opcodes {
+            eprintln!("{:<12} {:>12} {:>7.1}%", op, n, *n as f64 * 100.0 / instrs.max(1) as f64);
+        }
+
+        // 依不含子呼叫的時間排序，最花時間的函數在最前面
+        let mut funcs: Vec<(&&str, &FuncStats)> = self.funcs.iter().collect();
+        funcs.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(b.0)));
+        eprintln!();
+        eprintln!("{:<16} {:>10} {:>12} {:>14} {:>14}", "function", "calls", "instrs", "inclusive ms", "exclusive ms");
+        for (name, st) in funcs {
+            eprintln!("{:<16} {:>10} {:>12} {:>14.3} {:>14.3}", name, st.calls, st.instrs, ms(st.inclusive), ms(st.exclusive));
+        }
+    }
+}
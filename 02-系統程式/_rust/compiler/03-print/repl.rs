@@ -0,0 +1,226 @@
+// ==========================================================
+// 互動模式 (REPL)：執行 compiler 時沒有給檔案就進入
+//
+// 以 fn 開頭的輸入是函數定義，編譯後加進 VM::functions (同名的會被取代)。
+// 其他輸入是敘述，包成暫時的函數 __repl__ 執行；頂層 let 宣告的變數
+// 以參數的形式帶到下一次輸入。最後一個敘述是表達式時印出它的值。
+// 大括號還沒配對完之前會繼續讀下一行。
+//   :ir [函數]      印出一個函數 (或全部) 的 IR
+//   :load <檔案>    載入檔案 (與它 import 的模組) 裡的函數定義並設定全域變數，不會執行 main
+//   :help  :quit
+// ==========================================================
+
+use super::*;
+use std::io::{self, BufRead, Write};
+
+const ENTRY: &str = "__repl__";
+const RESULT: &str = "$it"; // 最後一個表達式的值，使用者寫不出這個名字
+
+const HELP: &str = "\
+fn f(...) { ... }   定義 (或重新定義) 函數
+<敘述>              執行敘述，最後一個是表達式時印出它的值
+:ir [函數]          印出一個函數 (或全部) 的 IR
+:load <檔案>        載入檔案裡的函數定義
+:quit               離開 (:q)";
+
+struct Repl {
+    vm: VM,
+    vars: Vec<(String, Value)>, // 頂層變數，依宣告順序
+    globals: Vec<(String, bool)>, // :load 載入的全域變數 (名稱、是不是常數)
+}
+
+pub fn run(vm: VM) {
+    let mut repl = Repl { vm, vars: Vec::new(), globals: Vec::new() };
+    println!("p0 REPL，輸入 :help 查看指令");
+    let stdin = io::stdin();
+    let mut input = String::new();
+    loop {
+        print!("{}", if input.is_empty() { "p0> " } else { "... " });
+        io::stdout().flush().ok();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 { println!(); return; }
+        input.push_str(&line);
+        if brace_depth(&input) > 0 { continue; }
+        let text = std::mem::take(&mut input);
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {}
+            [":quit" | ":q"] => return,
+            [":help"] => println!("{}", HELP),
+            [":ir"] => print!("{}", repl.vm.dump_ir()),
+            [":ir", name] => repl.show_ir(name),
+            [":load", path] => repl.load(path),
+            [cmd, ..] if cmd.starts_with(':') => println!("看不懂的指令: {} (輸入 :help 查看指令)", text.trim()),
+            _ => repl.eval(&text),
+        }
+    }
+}
+
+// 還沒配對的 { 數量，字串與註解裡的大括號不算；/* 還沒結束也要繼續讀
+fn brace_depth(text: &str) -> i32 {
+    let (mut depth, mut quote) = (0, None);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' if quote.is_none() => quote = Some(c),
+            c if quote == Some(c) => quote = None,
+            '/' if quote.is_none() && chars.peek() == Some(&'/') => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '/' if quote.is_none() && chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if prev == '*' => break,
+                        Some(c) => prev = c,
+                        None => return depth.max(1),
+                    }
+                }
+            }
+            '{' if quote.is_none() => depth += 1,
+            '}' if quote.is_none() => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+fn print_errors(errors: &[Diagnostic], path: &str, source: &str) {
+    for e in errors { eprintln!("{}", e.render(path, source)); }
+}
+
+impl Repl {
+    fn eval(&mut self, text: &str) {
+        if Lexer::new(text).next_token().0 == Token::Fn {
+            match Parser::new(Lexer::new(text)).parse_program() {
+                Ok(program) => self.define(program, "<repl>", text),
+                Err(errors) => print_errors(&errors, "<repl>", text),
+            }
+            return;
+        }
+        // 方便起見，最後一個敘述可以省略分號
+        let mut source = text.trim_end().to_string();
+        if !source.ends_with(';') && !source.ends_with('}') { source.push(';'); }
+        match Parser::new(Lexer::new(&source)).parse_stmts() {
+            Ok(stmts) => self.execute(stmts, &source),
+            Err(errors) => print_errors(&errors, "<repl>", &source),
+        }
+    }
+
+    // 先前定義過、這次沒有重新定義的函數
+    fn known_functions(&self, redefined: &[String]) -> Vec<(String, usize)> {
+        self.vm.functions.iter()
+            .filter(|(name, _)| !redefined.contains(name))
+            .map(|(name, (params, _))| (name.clone(), params.len()))
+            .collect()
+    }
+
+    fn define(&mut self, program: Vec<Stmt>, path: &str, source: &str) {
+        let names: Vec<String> = program.iter().filter_map(|s| match &s.kind {
+            StmtKind::FuncDecl(name, ..) => Some(name.clone()),
+            _ => None,
+        }).collect();
+        if let Err(errors) = Resolver::with_functions(self.known_functions(&names), false).with_globals(&self.globals).resolve_program(&program) {
+            print_errors(&errors, path, source);
+            return;
+        }
+        self.vm.compile(program, "<repl>");
+        println!("已定義 {}", names.join(", "));
+    }
+
+    fn execute(&mut self, stmts: Vec<Stmt>, source: &str) {
+        // 已經宣告過的頂層變數 (或載入的全域變數) 再 let 一次就當成指定，值才會留在同一個名字上
+        let mut declared: Vec<String> = self.vars.iter().map(|(name, _)| name.clone()).collect();
+        let mut body = Vec::new();
+        for stmt in stmts {
+            let kind = match stmt.kind {
+                StmtKind::VarDecl(name, expr) if declared.contains(&name) || self.globals.iter().any(|(g, _)| *g == name) => StmtKind::Assign(name, expr),
+                StmtKind::VarDecl(name, expr) => { declared.push(name.clone()); StmtKind::VarDecl(name, expr) }
+                kind => kind,
+            };
+            body.push(Stmt { kind, span: stmt.span });
+        }
+        // print 自己已經輸出了，不再顯示它的回傳值
+        let mut show_result = false;
+        if let Some(last) = body.last_mut() {
+            if let StmtKind::ExprStmt(expr) = &last.kind {
+                if !matches!(&expr.kind, ExprKind::Call(name, _) if name == "print") {
+                    last.kind = StmtKind::VarDecl(RESULT.to_string(), expr.clone());
+                    show_result = true;
+                }
+            }
+        }
+
+        let params: Vec<String> = self.vars.iter().map(|(name, _)| name.clone()).collect();
+        let span = Span { line: 1, col: 1, len: 1 };
+        let program = vec![Stmt { kind: StmtKind::FuncDecl(ENTRY.to_string(), params, body), span }];
+        if let Err(errors) = Resolver::with_functions(self.known_functions(&[]), false).with_globals(&self.globals).resolve_program(&program) {
+            print_errors(&errors, "<repl>", source);
+            return;
+        }
+        self.vm.compile(program, "<repl>");
+
+        // 自己一步一步執行，走到 __repl__ 結尾時框架還在，才拿得到頂層變數
+        let args = self.vars.iter().map(|(_, v)| v.clone()).collect();
+        let mut stack = vec![self.vm.new_frame(ENTRY, args).unwrap()];
+        let result = loop {
+            let top = stack.last().unwrap();
+            if stack.len() == 1 && top.ip >= top.code.len() { break Ok(None); }
+            match self.vm.step(&mut stack) {
+                Ok(Some(v)) => break Ok(Some(v)), // 頂層的 return
+                Ok(None) => {}
+                Err(mut e) => { e.backtrace = self.vm.backtrace(&stack); break Err(e); }
+            }
+        };
+        let locals = stack.pop().map(|frame| frame.locals);
+        drop(stack);
+
+        match (result, locals) {
+            (Err(e), _) => eprintln!("{}", e),
+            (Ok(Some(v)), _) => println!("{}", debugger::show(&v)),
+            (Ok(None), Some(locals)) => {
+                for name in declared {
+                    let value = match locals.get(&name) { Some(v) => v.clone(), None => continue };
+                    match self.vars.iter_mut().find(|(n, _)| *n == name) {
+                        Some(var) => var.1 = value,
+                        None => self.vars.push((name, value)),
+                    }
+                }
+                if show_result { println!("{}", debugger::show(&locals[RESULT])); }
+            }
+            (Ok(None), None) => {}
+        }
+        self.vm.functions.remove(ENTRY);
+        self.vm.lines.remove(ENTRY);
+        self.vm.files.remove(ENTRY);
+    }
+
+    fn show_ir(&self, name: &str) {
+        match self.vm.functions.get(name) {
+            Some(func) => {
+                let one = HashMap::from([(name.to_string(), func.clone())]);
+                print!("{}", irtext::dump(&one));
+            }
+            None => println!("找不到函數 '{}'", name),
+        }
+    }
+
+    // 檔案裡 import 的模組也一起載入
+    fn load(&mut self, path: &str) {
+        match modules::load(&mut self.vm, path, &[], false) {
+            Ok(loaded) => {
+                println!("已定義 {}", loaded.functions.join(", "));
+                for global in loaded.globals {
+                    if !self.globals.contains(&global) { self.globals.push(global); }
+                }
+            }
+            Err(e) => { print_errors(&e.errors, &e.path, &e.source); return; }
+        }
+        // 全域變數在載入時就設定好；__init__ 不留下來，下次 :load 才不會重複執行
+        if let Err(e) = self.vm.init_globals() { eprintln!("{}", e); }
+        self.vm.functions.remove(INIT);
+        self.vm.lines.remove(INIT);
+        self.vm.files.remove(INIT);
+    }
+}
@@ -0,0 +1,495 @@
+// ==========================================================
+// RISC-V 後端：把 IR 直接編碼成 RV64I 機器碼，包成可執行的 ELF
+//
+// 只用 RV64I 基本指令 (沒有 M 擴充，乘除法由執行環境的副程式以移位完成)，
+// 系統呼叫採用 Linux 的編號：write = 64、exit = 93，
+// 所以產生的檔案可以直接在 vm/myemu (--interp) 或 qemu-riscv64 上執行，不需要交叉編譯工具鏈。
+// 程式結束時以 main 的回傳值作為 exit code。
+// ==========================================================
+
+use super::*;
+use super::x86::{infer_strings, ir_names};
+use std::collections::HashSet;
+
+// 程式載入的虛擬位址 (myemu 的記憶體只有 1MB，所以放低一點)
+const BASE: u64 = 0x10000;
+const HEADER_SIZE: u64 = 64 + 56; // ELF header + 一個 program header
+
+const ZERO: u32 = 0;
+const RA: u32 = 1;
+const SP: u32 = 2;
+const T0: u32 = 5;
+const T1: u32 = 6;
+const T2: u32 = 7;
+const S0: u32 = 8;
+const S1: u32 = 9;
+const A0: u32 = 10;
+const A1: u32 = 11;
+const A2: u32 = 12;
+const A7: u32 = 17;
+const S2: u32 = 18;
+const T3: u32 = 28;
+const T4: u32 = 29;
+const T5: u32 = 30;
+const T6: u32 = 31;
+
+const SYS_WRITE: i32 = 64;
+const SYS_EXIT: i32 = 93;
+
+enum Fixup {
+    Branch(String), // B 型態，±4KB
+    Jal(String),    // J 型態，±1MB
+    Addr(usize),    // lui + addi 載入資料區第 n 個字串的絕對位址
+}
+
+// 簡單的組譯器：指令逐一編碼，標籤位址最後再回填
+struct Asm {
+    code: Vec<u32>,
+    labels: HashMap<String, usize>,
+    fixups: Vec<(usize, Fixup)>,
+    strings: Vec<String>,
+}
+
+impl Asm {
+    fn new() -> Self {
+        Self { code: Vec::new(), labels: HashMap::new(), fixups: Vec::new(), strings: Vec::new() }
+    }
+
+    fn label(&mut self, name: &str) { self.labels.insert(name.to_string(), self.code.len()); }
+
+    fn r(&mut self, op: u32, f3: u32, f7: u32, rd: u32, rs1: u32, rs2: u32) {
+        self.code.push(f7 << 25 | rs2 << 20 | rs1 << 15 | f3 << 12 | rd << 7 | op);
+    }
+    fn i(&mut self, op: u32, f3: u32, rd: u32, rs1: u32, imm: i32) {
+        self.code.push(((imm as u32) & 0xfff) << 20 | rs1 << 15 | f3 << 12 | rd << 7 | op);
+    }
+    fn s(&mut self, f3: u32, rs1: u32, rs2: u32, imm: i32) {
+        let imm = imm as u32;
+        self.code.push((imm >> 5 & 0x7f) << 25 | rs2 << 20 | rs1 << 15 | f3 << 12 | (imm & 0x1f) << 7 | 0x23);
+    }
+    fn b(&mut self, f3: u32, rs1: u32, rs2: u32, target: &str) {
+        self.fixups.push((self.code.len(), Fixup::Branch(target.to_string())));
+        self.code.push(rs2 << 20 | rs1 << 15 | f3 << 12 | 0x63);
+    }
+
+    fn add(&mut self, rd: u32, a: u32, b: u32) { self.r(0x33, 0, 0, rd, a, b); }
+    fn sub(&mut self, rd: u32, a: u32, b: u32) { self.r(0x33, 0, 0x20, rd, a, b); }
+    fn addw(&mut self, rd: u32, a: u32, b: u32) { self.r(0x3b, 0, 0, rd, a, b); }
+    fn subw(&mut self, rd: u32, a: u32, b: u32) { self.r(0x3b, 0, 0x20, rd, a, b); }
+    fn slt(&mut self, rd: u32, a: u32, b: u32) { self.r(0x33, 2, 0, rd, a, b); }
+    fn sltu(&mut self, rd: u32, a: u32, b: u32) { self.r(0x33, 3, 0, rd, a, b); }
+    fn or(&mut self, rd: u32, a: u32, b: u32) { self.r(0x33, 6, 0, rd, a, b); }
+    fn srl(&mut self, rd: u32, a: u32, b: u32) { self.r(0x33, 5, 0, rd, a, b); }
+    fn addi(&mut self, rd: u32, rs: u32, imm: i32) { self.i(0x13, 0, rd, rs, imm); }
+    fn addiw(&mut self, rd: u32, rs: u32, imm: i32) { self.i(0x1b, 0, rd, rs, imm); }
+    fn sltiu(&mut self, rd: u32, rs: u32, imm: i32) { self.i(0x13, 3, rd, rs, imm); }
+    fn xori(&mut self, rd: u32, rs: u32, imm: i32) { self.i(0x13, 4, rd, rs, imm); }
+    fn ori(&mut self, rd: u32, rs: u32, imm: i32) { self.i(0x13, 6, rd, rs, imm); }
+    fn andi(&mut self, rd: u32, rs: u32, imm: i32) { self.i(0x13, 7, rd, rs, imm); }
+    fn slli(&mut self, rd: u32, rs: u32, sh: i32) { self.i(0x13, 1, rd, rs, sh); }
+    fn srli(&mut self, rd: u32, rs: u32, sh: i32) { self.i(0x13, 5, rd, rs, sh); }
+    fn ld(&mut self, rd: u32, rs: u32, imm: i32) { self.i(0x03, 3, rd, rs, imm); }
+    fn lbu(&mut self, rd: u32, rs: u32, imm: i32) { self.i(0x03, 4, rd, rs, imm); }
+    fn sd(&mut self, rs2: u32, rs1: u32, imm: i32) { self.s(3, rs1, rs2, imm); }
+    fn sb(&mut self, rs2: u32, rs1: u32, imm: i32) { self.s(0, rs1, rs2, imm); }
+    fn mv(&mut self, rd: u32, rs: u32) { self.addi(rd, rs, 0); }
+    fn beq(&mut self, a: u32, b: u32, t: &str) { self.b(0, a, b, t); }
+    fn bne(&mut self, a: u32, b: u32, t: &str) { self.b(1, a, b, t); }
+    fn bge(&mut self, a: u32, b: u32, t: &str) { self.b(5, a, b, t); }
+    fn bltu(&mut self, a: u32, b: u32, t: &str) { self.b(6, a, b, t); }
+    fn jalr(&mut self, rd: u32, rs: u32, imm: i32) { self.i(0x67, 0, rd, rs, imm); }
+    fn ret(&mut self) { self.jalr(ZERO, RA, 0); }
+    fn ecall(&mut self) { self.code.push(0x73); }
+
+    fn jal(&mut self, rd: u32, target: &str) {
+        self.fixups.push((self.code.len(), Fixup::Jal(target.to_string())));
+        self.code.push(rd << 7 | 0x6f);
+    }
+
+    // 載入 32 位元常數 (結果做符號延伸)
+    fn li(&mut self, rd: u32, v: i32) {
+        if (-2048..2048).contains(&v) {
+            self.addi(rd, ZERO, v);
+        } else {
+            let hi = (v as i64 + 0x800) >> 12;
+            let lo = v - ((hi as i32) << 12);
+            self.code.push(((hi as u32) & 0xfffff) << 12 | rd << 7 | 0x37); // lui
+            self.addiw(rd, rd, lo);
+        }
+    }
+
+    // rd = rs + imm，imm 超出 12 位元時先用 t6 組出常數
+    fn add_imm(&mut self, rd: u32, rs: u32, imm: i32) {
+        if (-2048..2048).contains(&imm) { self.addi(rd, rs, imm); } else { self.li(T6, imm); self.add(rd, rs, T6); }
+    }
+
+    // 載入字串常數的位址 (字串以 0 結尾，放在程式碼後面)
+    fn la(&mut self, rd: u32, s: &str) {
+        let idx = self.strings.iter().position(|x| x == s).unwrap_or_else(|| { self.strings.push(s.to_string()); self.strings.len() - 1 });
+        self.fixups.push((self.code.len(), Fixup::Addr(idx)));
+        self.code.push(rd << 7 | 0x37);
+        self.addi(rd, rd, 0);
+    }
+
+    // 回填所有跳躍與位址，回傳程式碼與資料區的位元組
+    fn finish(mut self) -> Result<Vec<u8>, String> {
+        let code_start = BASE + HEADER_SIZE;
+        let data_start = code_start + self.code.len() as u64 * 4;
+        let mut data = Vec::new();
+        let mut string_addrs = Vec::new();
+        for s in &self.strings {
+            string_addrs.push(data_start + data.len() as u64);
+            data.extend_from_slice(s.as_bytes());
+            data.push(0);
+        }
+        for (pos, fixup) in &self.fixups {
+            match fixup {
+                Fixup::Branch(l) | Fixup::Jal(l) => {
+                    let target = *self.labels.get(l).ok_or_else(|| format!("RISC-V 後端: 找不到標籤 {}", l))?;
+                    let off = (target as i64 - *pos as i64) * 4;
+                    let ins = &mut self.code[*pos];
+                    if let Fixup::Branch(_) = fixup {
+                        if !(-4096..4096).contains(&off) { return Err(format!("RISC-V 後端: 分支到 {} 超出範圍", l)); }
+                        let o = off as u32;
+                        *ins |= (o >> 12 & 1) << 31 | (o >> 5 & 0x3f) << 25 | (o >> 1 & 0xf) << 8 | (o >> 11 & 1) << 7;
+                    } else {
+                        if !(-(1 << 20)..(1 << 20)).contains(&off) { return Err(format!("RISC-V 後端: 跳躍到 {} 超出範圍", l)); }
+                        let o = off as u32;
+                        *ins |= (o >> 20 & 1) << 31 | (o >> 1 & 0x3ff) << 21 | (o >> 11 & 1) << 20 | (o >> 12 & 0xff) << 12;
+                    }
+                }
+                Fixup::Addr(idx) => {
+                    let addr = string_addrs[*idx] as i64;
+                    let hi = (addr + 0x800) >> 12;
+                    let lo = (addr - (hi << 12)) as u32;
+                    self.code[*pos] |= ((hi as u32) & 0xfffff) << 12;
+                    self.code[*pos + 1] |= (lo & 0xfff) << 20;
+                }
+            }
+        }
+        let mut bytes: Vec<u8> = self.code.iter().flat_map(|w| w.to_le_bytes()).collect();
+        bytes.extend(data);
+        Ok(bytes)
+    }
+}
+
+pub fn emit_program(functions: &HashMap<String, (Vec<String>, Vec<IR>)>) -> Result<Vec<u8>, String> {
+    let mut asm = Asm::new();
+    // _start：呼叫 main，再以回傳值 exit
+    asm.jal(RA, "f_main");
+    asm.li(A7, SYS_EXIT);
+    asm.ecall();
+
+    let strs = infer_strings(functions);
+    let mut names: Vec<&String> = functions.keys().collect();
+    names.sort();
+    for name in names {
+        let (params, code) = &functions[name];
+        emit_function(&mut asm, functions, &strs, name, params, code)?;
+    }
+    emit_runtime(&mut asm);
+    Ok(elf(asm.finish()?))
+}
+
+fn emit_function(
+    asm: &mut Asm,
+    functions: &HashMap<String, (Vec<String>, Vec<IR>)>,
+    strs: &HashSet<(String, String)>,
+    name: &str,
+    params: &[String],
+    code: &[IR],
+) -> Result<(), String> {
+    let unsupported = |what: &str, ip: usize| Err(format!("函數 {} 的 IR #{}: RISC-V 後端不支援{}", name, ip, what));
+    if params.len() > 8 { return unsupported("超過 8 個參數", 0); }
+
+    // 堆疊框架：s0 指向進入時的 sp，下面依序是 ra、舊 s0，以及每個名稱一格
+    let mut slots: HashMap<&String, i32> = HashMap::new();
+    for n in params.iter().chain(code.iter().flat_map(ir_names)) {
+        let next = -16 - 8 * (slots.len() as i32 + 1);
+        slots.entry(n).or_insert(next);
+    }
+    let locals_size = (slots.len() as i32 * 8 + 15) / 16 * 16;
+    let load = |asm: &mut Asm, rd: u32, n: &String| {
+        let off = slots[n];
+        if off >= -2048 { asm.ld(rd, S0, off); } else { asm.add_imm(T6, S0, off); asm.ld(rd, T6, 0); }
+    };
+    let store = |asm: &mut Asm, rs: u32, n: &String| {
+        let off = slots[n];
+        if off >= -2048 { asm.sd(rs, S0, off); } else { asm.li(T6, off); asm.add(T6, S0, T6); asm.sd(rs, T6, 0); }
+    };
+    let label = |ip: usize| format!("L_{}_{}", name, ip);
+    let epilogue = format!("E_{}", name);
+
+    asm.label(&format!("f_{}", name));
+    asm.addi(SP, SP, -16);
+    asm.sd(RA, SP, 8);
+    asm.sd(S0, SP, 0);
+    asm.addi(S0, SP, 16);
+    asm.add_imm(SP, SP, -locals_size);
+    for (i, p) in params.iter().enumerate() { store(asm, A0 + i as u32, p); }
+
+    for (ip, ir) in code.iter().enumerate() {
+        asm.label(&label(ip));
+        match ir {
+            IR::LoadConst(t, v) => { asm.li(T0, *v); store(asm, T0, t); }
+            IR::LoadStr(t, s) => { asm.la(T0, s); store(asm, T0, t); }
+            IR::LoadVar(dst, src) | IR::StoreVar(dst, src) => { load(asm, T0, src); store(asm, T0, dst); }
+            IR::Add(t, l, r) | IR::Sub(t, l, r) | IR::Lt(t, l, r) | IR::Le(t, l, r) | IR::Gt(t, l, r) | IR::Ge(t, l, r)
+            | IR::Eq(t, l, r) | IR::Ne(t, l, r) => {
+                load(asm, T0, l);
+                load(asm, T1, r);
+                match ir {
+                    IR::Add(..) => asm.addw(T0, T0, T1),
+                    IR::Sub(..) => asm.subw(T0, T0, T1),
+                    IR::Lt(..) => asm.slt(T0, T0, T1),
+                    IR::Gt(..) => asm.slt(T0, T1, T0),
+                    IR::Le(..) => { asm.slt(T0, T1, T0); asm.xori(T0, T0, 1); }
+                    IR::Ge(..) => { asm.slt(T0, T0, T1); asm.xori(T0, T0, 1); }
+                    IR::Eq(..) => { asm.sub(T0, T0, T1); asm.sltiu(T0, T0, 1); }
+                    _ => { asm.sub(T0, T0, T1); asm.sltu(T0, ZERO, T0); }
+                }
+                store(asm, T0, t);
+            }
+            IR::Mul(t, l, r) => {
+                load(asm, A0, l);
+                load(asm, A1, r);
+                asm.jal(RA, "rt_mul");
+                asm.addiw(A0, A0, 0);
+                store(asm, A0, t);
+            }
+            IR::Div(t, l, r) | IR::Mod(t, l, r) => {
+                load(asm, A0, l);
+                load(asm, A1, r);
+                let ok = format!("D_{}_{}", name, ip);
+                asm.bne(A1, ZERO, &ok);
+                asm.la(A0, &format!("執行期錯誤: 除以零 (於函數 {} 的 IR #{})\n", name, ip));
+                asm.jal(RA, "rt_panic");
+                asm.label(&ok);
+                asm.jal(RA, "rt_divmod");
+                let result = if matches!(ir, IR::Div(..)) { A0 } else { A1 };
+                asm.addiw(A0, result, 0);
+                store(asm, A0, t);
+            }
+            IR::Neg(t, s) => { load(asm, T0, s); asm.subw(T0, ZERO, T0); store(asm, T0, t); }
+            IR::Not(t, s) => { load(asm, T0, s); asm.sltiu(T0, T0, 1); store(asm, T0, t); }
+            IR::NewArray(..) | IR::IndexLoad(..) | IR::IndexStore(..) => return unsupported("陣列", ip),
+            IR::LoadGlobal(..) | IR::StoreGlobal(..) => return unsupported("全域變數", ip),
+            IR::Call(f, args, t) if f == "print" => {
+                for a in args {
+                    load(asm, A0, a);
+                    let is_str = strs.contains(&(name.to_string(), a.clone()));
+                    asm.jal(RA, if is_str { "rt_print_str" } else { "rt_print_int" });
+                }
+                asm.li(A0, b'\n' as i32);
+                asm.jal(RA, "rt_putchar");
+                store(asm, ZERO, t);
+            }
+            IR::Call(f, args, t) => {
+                if !functions.contains_key(f) { return unsupported(&format!("內建函數 {}", f), ip); }
+                if args.len() > 8 { return unsupported("超過 8 個參數", ip); }
+                for (i, a) in args.iter().enumerate() { load(asm, A0 + i as u32, a); }
+                asm.jal(RA, &format!("f_{}", f));
+                store(asm, A0, t);
+            }
+            IR::Return(t) => { load(asm, A0, t); asm.jal(ZERO, &epilogue); }
+            IR::IfFalse(t, target) => {
+                // bne 跳過 jal，jal 的範圍比條件分支大得多
+                let skip = format!("S_{}_{}", name, ip);
+                load(asm, T0, t);
+                asm.bne(T0, ZERO, &skip);
+                asm.jal(ZERO, &label(*target));
+                asm.label(&skip);
+            }
+            IR::Goto(target) => asm.jal(ZERO, &label(*target)),
+            IR::Label => {}
+        }
+    }
+    // 跑到函數結尾 (沒有 return) 時回傳 0，和 VM::run 一致
+    asm.label(&label(code.len()));
+    asm.li(A0, 0);
+    asm.label(&epilogue);
+    asm.addi(SP, S0, -16);
+    asm.ld(RA, SP, 8);
+    asm.ld(S0, SP, 0);
+    asm.addi(SP, SP, 16);
+    asm.ret();
+    Ok(())
+}
+
+// 執行環境副程式：只會動到 a0~a2、a7 與 t0~t5 (呼叫端不依賴這些暫存器)
+fn emit_runtime(asm: &mut Asm) {
+    // rt_mul(a0, a1) -> a0：移位相加
+    asm.label("rt_mul");
+    asm.mv(T0, A0);
+    asm.mv(T1, A1);
+    asm.li(A0, 0);
+    asm.label("rt_mul_loop");
+    asm.beq(T1, ZERO, "rt_mul_done");
+    asm.andi(T2, T1, 1);
+    asm.beq(T2, ZERO, "rt_mul_skip");
+    asm.add(A0, A0, T0);
+    asm.label("rt_mul_skip");
+    asm.slli(T0, T0, 1);
+    asm.srli(T1, T1, 1);
+    asm.jal(ZERO, "rt_mul_loop");
+    asm.label("rt_mul_done");
+    asm.ret();
+
+    // rt_divmod(a0, a1) -> a0 = 商, a1 = 餘數 (向零截斷，和 Rust 的 / 與 % 相同)
+    asm.label("rt_divmod");
+    asm.slt(T3, A0, ZERO); // 被除數為負 => 餘數為負
+    asm.slt(T4, A1, ZERO);
+    asm.r(0x33, 4, 0, T4, T3, T4); // xor：符號不同 => 商為負
+    asm.beq(T3, ZERO, "rt_div_a_pos");
+    asm.sub(A0, ZERO, A0);
+    asm.label("rt_div_a_pos");
+    asm.bge(A1, ZERO, "rt_div_b_pos");
+    asm.sub(A1, ZERO, A1);
+    asm.label("rt_div_b_pos");
+    asm.li(T0, 0); // 商
+    asm.li(T1, 0); // 餘數
+    asm.li(T5, 64);
+    asm.label("rt_div_loop");
+    asm.beq(T5, ZERO, "rt_div_done");
+    asm.addi(T5, T5, -1);
+    asm.slli(T1, T1, 1);
+    asm.srl(T2, A0, T5);
+    asm.andi(T2, T2, 1);
+    asm.or(T1, T1, T2);
+    asm.slli(T0, T0, 1);
+    asm.bltu(T1, A1, "rt_div_loop");
+    asm.sub(T1, T1, A1);
+    asm.ori(T0, T0, 1);
+    asm.jal(ZERO, "rt_div_loop");
+    asm.label("rt_div_done");
+    asm.beq(T4, ZERO, "rt_div_q_pos");
+    asm.sub(T0, ZERO, T0);
+    asm.label("rt_div_q_pos");
+    asm.beq(T3, ZERO, "rt_div_r_pos");
+    asm.sub(T1, ZERO, T1);
+    asm.label("rt_div_r_pos");
+    asm.mv(A0, T0);
+    asm.mv(A1, T1);
+    asm.ret();
+
+    // rt_putchar(a0)：借用堆疊上的一個位元組呼叫 write(1, ...)
+    asm.label("rt_putchar");
+    asm.addi(SP, SP, -16);
+    asm.sb(A0, SP, 0);
+    asm.li(A0, 1);
+    asm.mv(A1, SP);
+    asm.li(A2, 1);
+    asm.li(A7, SYS_WRITE);
+    asm.ecall();
+    asm.addi(SP, SP, 16);
+    asm.ret();
+
+    // rt_write_cstr(a0 = fd, a1 = 以 0 結尾的字串)
+    asm.label("rt_write_cstr");
+    asm.mv(A2, A1);
+    asm.label("rt_strlen_loop");
+    asm.lbu(T0, A2, 0);
+    asm.beq(T0, ZERO, "rt_strlen_done");
+    asm.addi(A2, A2, 1);
+    asm.jal(ZERO, "rt_strlen_loop");
+    asm.label("rt_strlen_done");
+    asm.sub(A2, A2, A1);
+    asm.li(A7, SYS_WRITE);
+    asm.ecall();
+    asm.ret();
+
+    // rt_print_str(a0)：印出字串與一個空白 (和 VM 的 print 相同)
+    asm.label("rt_print_str");
+    asm.addi(SP, SP, -16);
+    asm.sd(RA, SP, 8);
+    asm.mv(A1, A0);
+    asm.li(A0, 1);
+    asm.jal(RA, "rt_write_cstr");
+    asm.li(A0, b' ' as i32);
+    asm.jal(RA, "rt_putchar");
+    asm.ld(RA, SP, 8);
+    asm.addi(SP, SP, 16);
+    asm.ret();
+
+    // rt_print_int(a0)：從緩衝區尾端往前填入十進位數字、負號，最後加一個空白
+    asm.label("rt_print_int");
+    asm.addi(SP, SP, -64);
+    asm.sd(RA, SP, 56);
+    asm.sd(S1, SP, 48);
+    asm.sd(S2, SP, 40);
+    asm.sd(A0, SP, 32);
+    asm.mv(S1, A0);
+    asm.addi(S2, SP, 32);
+    asm.li(T0, b' ' as i32);
+    asm.addi(S2, S2, -1);
+    asm.sb(T0, S2, 0);
+    asm.bge(S1, ZERO, "rt_pi_loop");
+    asm.sub(S1, ZERO, S1);
+    asm.label("rt_pi_loop");
+    asm.mv(A0, S1);
+    asm.li(A1, 10);
+    asm.jal(RA, "rt_divmod");
+    asm.addi(A1, A1, b'0' as i32);
+    asm.addi(S2, S2, -1);
+    asm.sb(A1, S2, 0);
+    asm.mv(S1, A0);
+    asm.bne(S1, ZERO, "rt_pi_loop");
+    asm.ld(T0, SP, 32);
+    asm.bge(T0, ZERO, "rt_pi_write");
+    asm.li(T0, b'-' as i32);
+    asm.addi(S2, S2, -1);
+    asm.sb(T0, S2, 0);
+    asm.label("rt_pi_write");
+    asm.li(A0, 1);
+    asm.mv(A1, S2);
+    asm.addi(A2, SP, 32);
+    asm.sub(A2, A2, S2);
+    asm.li(A7, SYS_WRITE);
+    asm.ecall();
+    asm.ld(RA, SP, 56);
+    asm.ld(S1, SP, 48);
+    asm.ld(S2, SP, 40);
+    asm.addi(SP, SP, 64);
+    asm.ret();
+
+    // rt_panic(a0 = 訊息)：寫到 stderr 後以 1 結束
+    asm.label("rt_panic");
+    asm.mv(A1, A0);
+    asm.li(A0, 2);
+    asm.jal(RA, "rt_write_cstr");
+    asm.li(A0, 1);
+    asm.li(A7, SYS_EXIT);
+    asm.ecall();
+}
+
+// 最精簡的 ELF64 執行檔：一個 PT_LOAD 把整個檔案 (含標頭) 載入到 BASE
+fn elf(body: Vec<u8>) -> Vec<u8> {
+    let file_size = HEADER_SIZE + body.len() as u64;
+    let mut out = Vec::new();
+    out.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    out.extend_from_slice(&2u16.to_le_bytes()); // e_type = EXEC
+    out.extend_from_slice(&243u16.to_le_bytes()); // e_machine = RISC-V
+    out.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    out.extend_from_slice(&(BASE + HEADER_SIZE).to_le_bytes()); // e_entry
+    out.extend_from_slice(&64u64.to_le_bytes()); // e_phoff
+    out.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    out.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    out.extend_from_slice(&64u16.to_le_bytes()); // e_ehsize
+    out.extend_from_slice(&56u16.to_le_bytes()); // e_phentsize
+    out.extend_from_slice(&1u16.to_le_bytes()); // e_phnum
+    out.extend_from_slice(&64u16.to_le_bytes()); // e_shentsize
+    out.extend_from_slice(&0u16.to_le_bytes()); // e_shnum
+    out.extend_from_slice(&0u16.to_le_bytes()); // e_shstrndx
+
+    out.extend_from_slice(&1u32.to_le_bytes()); // p_type = PT_LOAD
+    out.extend_from_slice(&7u32.to_le_bytes()); // p_flags = RWX
+    out.extend_from_slice(&0u64.to_le_bytes()); // p_offset
+    out.extend_from_slice(&BASE.to_le_bytes()); // p_vaddr
+    out.extend_from_slice(&BASE.to_le_bytes()); // p_paddr
+    out.extend_from_slice(&file_size.to_le_bytes()); // p_filesz
+    out.extend_from_slice(&file_size.to_le_bytes()); // p_memsz
+    out.extend_from_slice(&0x1000u64.to_le_bytes()); // p_align
+
+    out.extend(body);
+    out
+}
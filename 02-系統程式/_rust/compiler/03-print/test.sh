@@ -1,3 +1,47 @@
 rustc compiler.rs -o compiler
 ./compiler p0/fact.p0
-./compiler p0/fact.ir
\ No newline at end of file
+./compiler p0/fact.ir
+./compiler p0/hand.ir
+./compiler p0/bad.ir
+./compiler p0/loop.p0
+./compiler p0/ops.p0
+./compiler p0/div.p0
+./compiler p0/errors.p0
+./compiler p0/semantic.p0
+./compiler p0/array.p0
+./compiler p0/deep.p0
+./compiler p0/global.p0
+./compiler p0/global.ir
+./compiler -I p0/lib p0/import.p0
+./compiler -I p0/lib p0/import.p0
+touch -d '1 hour ago' p0/util.p0 p0/__p0cache__/util.ir && ./compiler -I p0/lib p0/import.p0 > /dev/null
+if [ -n "$(find p0/__p0cache__/util.ir -mmin -1)" ]; then echo "mathx.p0 比 util.p0 的快取新，快取重新產生"; else echo "util.p0 的快取沒有重新產生"; exit 1; fi
+./compiler --max-depth 3 p0/fact.p0
+echo world | ./compiler p0/string.p0
+./compiler p0/substr.p0
+./compiler --emit asm p0/fact.p0
+cc p0/fact.s -o p0/fact_native && ./p0/fact_native
+./compiler --emit asm p0/intmin.p0
+cc p0/intmin.s -o p0/intmin_native && diff <(./compiler p0/intmin.p0) <(./p0/intmin_native) && echo "intmin: 原生程式和 VM 的輸出相同"
+./compiler --emit riscv p0/fact.p0
+cargo run -q --manifest-path ../../vm/myemu/Cargo.toml -- --interp p0/fact.elf; status=$?
+if [ $status -eq 120 ]; then echo "myemu: fact.elf 結束碼 120 (正確)"; else echo "myemu: fact.elf 結束碼 $status，預期 120"; exit 1; fi
+./compiler -O1 p0/fact.p0
+./compiler -O1 p0/ops.p0
+diff <(./compiler p0/ops.p0) <(./compiler -O1 p0/ops.p0 2> /dev/null) && echo "-O1 的標準輸出和 -O0 相同"
+./compiler --emit cfg p0/loop.p0
+./compiler --emit p0b p0/fact.p0
+./compiler p0/fact.p0b
+./compiler --emit p0b p0/deep.p0
+./compiler p0/deep.p0b
+./compiler --max-depth 3 p0/fact.p0b
+printf 'b 8\nc\nbt\nlocals\nfinish\nc\n' | ./compiler --debug p0/fact.p0
+printf 'b factorial:2\nc\nn\ntemps\nc\n' | ./compiler --debug p0/fact.ir
+rm -rf p0/__p0cache__ p0/lib/__p0cache__ && printf 'b 4\nb square\nc\nbt\nc\n' | ./compiler --debug -I p0/lib p0/import.p0
+printf 'fn sq(n) {\n  return n * n;\n}\nlet x = 7;\nsq(x) + 1\n:ir sq\n:load p0/fact.p0\nfactorial(6)\n' | ./compiler
+printf ':load p0/global.p0\ncount\nfn g() {\n  return count + total;\n}\ncount = 2;\ng()\n' | ./compiler
+./compiler --profile --folded p0/fact.folded p0/fact.p0 && cat p0/fact.folded
+cp p0/format.p0 p0/format_tmp.p0
+./compiler fmt --check p0/format_tmp.p0; echo "fmt --check 結束碼 $? (預期 1)"
+./compiler fmt p0/format_tmp.p0 && cat p0/format_tmp.p0
+./compiler fmt --check p0/format_tmp.p0 && ./compiler -I p0/lib p0/format_tmp.p0
@@ -0,0 +1,285 @@
+// ==========================================================
+// x86-64 組合語言後端：把 IR 轉成 GNU (AT&T) 語法、System V ABI 的 .s 檔
+//
+// 每個函數的參數、變數與暫存值都放在堆疊上 (各佔 8 bytes)，
+// print 對應到檔案最後附帶的小型執行環境 (透過 libc 的 printf)，
+// 產生的檔案可以直接用 `cc fact.s -o fact` 組譯並連結。
+// ==========================================================
+
+use super::*;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+// 第 1~6 個整數參數使用的暫存器 (System V ABI)
+const ARG_REGS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+
+pub fn emit_program(functions: &HashMap<String, (Vec<String>, Vec<IR>)>) -> Result<String, String> {
+    let strs = infer_strings(functions);
+    let mut names: Vec<&String> = functions.keys().collect();
+    names.sort();
+
+    let mut out = String::new();
+    // 執行環境用到的格式字串，和 VM 的輸出一致
+    let mut rodata = vec![
+        (".Lfmt_result".to_string(), "(main 結束，回傳值: %d)\n".to_string()),
+        (".Lfmt_int".to_string(), "%d ".to_string()),
+        (".Lfmt_str".to_string(), "%s ".to_string()),
+        (".Lfmt_div0".to_string(), "執行期錯誤: 除以零 (於函數 %s 的 IR #%d)\n".to_string()),
+    ];
+    out.push_str("\t.text\n");
+    for name in names {
+        let (params, code) = &functions[name];
+        emit_function(&mut out, &mut rodata, functions, &strs, name, params, code)?;
+    }
+    out.push_str(RUNTIME);
+    out.push_str("\n\t.section .rodata\n");
+    for (label, s) in rodata {
+        writeln!(out, "{}:\n\t.string \"{}\"", label, escape(&s)).unwrap();
+    }
+    out.push_str("\t.section .note.GNU-stack,\"\",@progbits\n");
+    Ok(out)
+}
+
+// 以跨函數的固定點找出哪些變數/暫存值裝的是字串，print 才知道要呼叫哪個執行環境函數
+pub(super) fn infer_strings(functions: &HashMap<String, (Vec<String>, Vec<IR>)>) -> HashSet<(String, String)> {
+    let mut strs: HashSet<(String, String)> = HashSet::new();
+    let mut ret_strs: HashSet<String> = HashSet::new();
+    loop {
+        let before = (strs.len(), ret_strs.len());
+        for (f, (_, code)) in functions {
+            let is_str = |strs: &HashSet<(String, String)>, n: &String| strs.contains(&(f.clone(), n.clone()));
+            for ir in code {
+                match ir {
+                    IR::LoadStr(t, _) => { strs.insert((f.clone(), t.clone())); }
+                    IR::LoadVar(dst, src) | IR::StoreVar(dst, src) if is_str(&strs, src) => {
+                        strs.insert((f.clone(), dst.clone()));
+                    }
+                    IR::Return(t) if is_str(&strs, t) => { ret_strs.insert(f.clone()); }
+                    IR::Call(g, args, t) => {
+                        if let Some((params, _)) = functions.get(g) {
+                            for (p, a) in params.iter().zip(args) {
+                                if is_str(&strs, a) { strs.insert((g.clone(), p.clone())); }
+                            }
+                        }
+                        if ret_strs.contains(g) { strs.insert((f.clone(), t.clone())); }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if (strs.len(), ret_strs.len()) == before { return strs; }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn emit_function(
+    out: &mut String,
+    rodata: &mut Vec<(String, String)>,
+    functions: &HashMap<String, (Vec<String>, Vec<IR>)>,
+    strs: &HashSet<(String, String)>,
+    name: &str,
+    params: &[String],
+    code: &[IR],
+) -> Result<(), String> {
+    // 每個名稱 (參數、變數、暫存值) 在堆疊上配一格
+    let mut slots: HashMap<String, i32> = HashMap::new();
+    let mut slot = |n: &String| {
+        let next = -8 * (slots.len() as i32 + 1);
+        *slots.entry(n.clone()).or_insert(next)
+    };
+    for p in params { slot(p); }
+    for ir in code {
+        for n in ir_names(ir) { slot(n); }
+    }
+    let frame = (slots.len() as i32 * 8 + 15) / 16 * 16;
+    let off = |n: &String| format!("{}(%rbp)", slots[n]);
+    let is_str = |n: &String| strs.contains(&(name.to_string(), n.clone()));
+    let unsupported = |what: &str, ip: usize| Err(format!("函數 {} 的 IR #{}: x86-64 後端不支援{}", name, ip, what));
+
+    let mut targets: HashSet<usize> = HashSet::new();
+    for ir in code {
+        match ir {
+            IR::IfFalse(_, t) | IR::Goto(t) => { targets.insert(*t); }
+            _ => {}
+        }
+    }
+
+    writeln!(out, "\n\t.globl p0_{}\np0_{}:", name, name).unwrap();
+    out.push_str("\tpushq %rbp\n\tmovq %rsp, %rbp\n");
+    if frame > 0 { writeln!(out, "\tsubq ${}, %rsp", frame).unwrap(); }
+    for (i, p) in params.iter().enumerate() {
+        if i < ARG_REGS.len() {
+            writeln!(out, "\tmovq {}, {}\t# 參數 {}", ARG_REGS[i], off(p), p).unwrap();
+        } else {
+            writeln!(out, "\tmovq {}(%rbp), %rax\n\tmovq %rax, {}\t# 參數 {}", 16 + 8 * (i - 6), off(p), p).unwrap();
+        }
+    }
+
+    for (ip, ir) in code.iter().enumerate() {
+        if targets.contains(&ip) { writeln!(out, ".L{}_{}:", name, ip).unwrap(); }
+        match ir {
+            IR::LoadConst(t, v) => writeln!(out, "\tmovq ${}, {}", v, off(t)).unwrap(),
+            IR::LoadStr(t, s) => {
+                let label = format!(".Lstr{}", rodata.len());
+                writeln!(out, "\tleaq {}(%rip), %rax\n\tmovq %rax, {}", label, off(t)).unwrap();
+                rodata.push((label, s.clone()));
+            }
+            IR::LoadVar(dst, src) | IR::StoreVar(dst, src) => {
+                writeln!(out, "\tmovq {}, %rax\n\tmovq %rax, {}", off(src), off(dst)).unwrap();
+            }
+            IR::Add(t, l, r) | IR::Sub(t, l, r) | IR::Mul(t, l, r) => {
+                if is_str(l) || is_str(r) { return unsupported("字串運算", ip); }
+                let op = match ir { IR::Add(..) => "addl", IR::Sub(..) => "subl", _ => "imull" };
+                writeln!(out, "\tmovl {}, %eax\n\t{} {}, %eax\n\tmovslq %eax, %rax\n\tmovq %rax, {}", off(l), op, off(r), off(t)).unwrap();
+            }
+            IR::Div(t, l, r) | IR::Mod(t, l, r) => {
+                let ok = format!(".L{}_div{}", name, ip);
+                writeln!(out, "\tcmpl $0, {}\n\tjne {}", off(r), ok).unwrap();
+                let msg = format!(".Lstr{}", rodata.len());
+                rodata.push((msg.clone(), name.to_string()));
+                writeln!(out, "\tleaq {}(%rip), %rdi\n\tmovl ${}, %esi\n\tcall __p0rt_div_by_zero", msg, ip).unwrap();
+                // 除數是 -1 時 idivl 遇到 INT_MIN 會觸發 SIGFPE，和 VM 一樣改成繞回：商取負號、餘數是 0
+                let (idiv, done) = (format!(".L{}_idiv{}", name, ip), format!(".L{}_divend{}", name, ip));
+                let (minus_one, result) = if matches!(ir, IR::Div(..)) { ("negl %eax", "") } else { ("xorl %eax, %eax", "\tmovl %edx, %eax\n") };
+                writeln!(out, "{}:\n\tmovl {}, %eax\n\tcmpl $-1, {}\n\tjne {}\n\t{}\n\tjmp {}", ok, off(l), off(r), idiv, minus_one, done).unwrap();
+                writeln!(out, "{}:\n\tcltd\n\tidivl {}\n{}{}:\n\tmovslq %eax, %rax\n\tmovq %rax, {}", idiv, off(r), result, done, off(t)).unwrap();
+            }
+            IR::Eq(t, l, r) | IR::Ne(t, l, r) | IR::Lt(t, l, r) | IR::Le(t, l, r) | IR::Gt(t, l, r) | IR::Ge(t, l, r) => {
+                if is_str(l) || is_str(r) { return unsupported("字串比較", ip); }
+                let set = match ir {
+                    IR::Eq(..) => "sete", IR::Ne(..) => "setne", IR::Lt(..) => "setl",
+                    IR::Le(..) => "setle", IR::Gt(..) => "setg", _ => "setge",
+                };
+                writeln!(out, "\tmovl {}, %eax\n\tcmpl {}, %eax\n\t{} %al\n\tmovzbq %al, %rax\n\tmovq %rax, {}", off(l), off(r), set, off(t)).unwrap();
+            }
+            IR::Neg(t, s) => writeln!(out, "\tmovl {}, %eax\n\tnegl %eax\n\tmovslq %eax, %rax\n\tmovq %rax, {}", off(s), off(t)).unwrap(),
+            IR::Not(t, s) => writeln!(out, "\tcmpl $0, {}\n\tsete %al\n\tmovzbq %al, %rax\n\tmovq %rax, {}", off(s), off(t)).unwrap(),
+            IR::NewArray(..) | IR::IndexLoad(..) | IR::IndexStore(..) => return unsupported("陣列", ip),
+            IR::LoadGlobal(..) | IR::StoreGlobal(..) => return unsupported("全域變數", ip),
+            IR::Call(f, args, t) if f == "print" => {
+                for a in args {
+                    let func = if is_str(a) { "__p0rt_print_str" } else { "__p0rt_print_int" };
+                    writeln!(out, "\tmovq {}, %rdi\n\tcall {}", off(a), func).unwrap();
+                }
+                writeln!(out, "\tcall __p0rt_print_newline\n\tmovq $0, {}", off(t)).unwrap();
+            }
+            IR::Call(f, args, t) => {
+                if !functions.contains_key(f) { return unsupported(&format!("內建函數 {}", f), ip); }
+                // 第 7 個以後的參數由右到左推入堆疊，並維持 16 bytes 對齊
+                let stack_args = args.len().saturating_sub(ARG_REGS.len());
+                let pad = if stack_args % 2 == 1 { 8 } else { 0 };
+                if pad > 0 { out.push_str("\tsubq $8, %rsp\n"); }
+                for a in args.iter().skip(ARG_REGS.len()).rev() {
+                    writeln!(out, "\tpushq {}", off(a)).unwrap();
+                }
+                for (a, reg) in args.iter().zip(ARG_REGS.iter()) {
+                    writeln!(out, "\tmovq {}, {}", off(a), reg).unwrap();
+                }
+                writeln!(out, "\tcall p0_{}", f).unwrap();
+                let cleanup = stack_args * 8 + pad;
+                if cleanup > 0 { writeln!(out, "\taddq ${}, %rsp", cleanup).unwrap(); }
+                writeln!(out, "\tmovq %rax, {}", off(t)).unwrap();
+            }
+            IR::Return(t) => writeln!(out, "\tmovq {}, %rax\n\tleave\n\tret", off(t)).unwrap(),
+            IR::IfFalse(t, target) => writeln!(out, "\tcmpl $0, {}\n\tje .L{}_{}", off(t), name, target).unwrap(),
+            IR::Goto(target) => writeln!(out, "\tjmp .L{}_{}", name, target).unwrap(),
+            IR::Label => {}
+        }
+    }
+    // 跑到函數結尾 (沒有 return) 時回傳 0，和 VM::run 一致
+    if targets.contains(&code.len()) { writeln!(out, ".L{}_{}:", name, code.len()).unwrap(); }
+    out.push_str("\tmovq $0, %rax\n\tleave\n\tret\n");
+    Ok(())
+}
+
+// IR 指令中出現的所有變數與暫存值名稱
+pub(super) fn ir_names(ir: &IR) -> Vec<&String> {
+    match ir {
+        IR::LoadConst(t, _) | IR::LoadStr(t, _) | IR::LoadGlobal(t, _) | IR::StoreGlobal(_, t) | IR::Return(t) | IR::IfFalse(t, _) => vec![t],
+        IR::LoadVar(a, b) | IR::StoreVar(a, b) | IR::Neg(a, b) | IR::Not(a, b) => vec![a, b],
+        IR::Add(a, b, c) | IR::Sub(a, b, c) | IR::Mul(a, b, c) | IR::Div(a, b, c) | IR::Mod(a, b, c)
+        | IR::Eq(a, b, c) | IR::Ne(a, b, c) | IR::Lt(a, b, c) | IR::Le(a, b, c) | IR::Gt(a, b, c) | IR::Ge(a, b, c)
+        | IR::IndexLoad(a, b, c) | IR::IndexStore(a, b, c) => vec![a, b, c],
+        IR::NewArray(t, items) => std::iter::once(t).chain(items).collect(),
+        IR::Call(_, args, t) => std::iter::once(t).chain(args).collect(),
+        IR::Goto(_) | IR::Label => vec![],
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(b as char),
+            _ => write!(out, "\\{:03o}", b).unwrap(),
+        }
+    }
+    out
+}
+
+// 小型執行環境：C 的 main 呼叫 p0_main，並以和 VM 相同的格式輸出結果
+// 使用者函數的符號是 p0_<名稱>，執行環境的標籤改用 __p0rt_ 開頭，兩者不會衝突
+const RUNTIME: &str = r#"
+	.globl main
+main:
+	pushq %rbp
+	movq %rsp, %rbp
+	call p0_main
+	movl %eax, %esi
+	leaq .Lfmt_result(%rip), %rdi
+	xorl %eax, %eax
+	call printf@PLT
+	xorl %eax, %eax
+	popq %rbp
+	ret
+
+__p0rt_print_int:
+	pushq %rbp
+	movq %rsp, %rbp
+	movl %edi, %esi
+	leaq .Lfmt_int(%rip), %rdi
+	xorl %eax, %eax
+	call printf@PLT
+	popq %rbp
+	ret
+
+__p0rt_print_str:
+	pushq %rbp
+	movq %rsp, %rbp
+	movq %rdi, %rsi
+	leaq .Lfmt_str(%rip), %rdi
+	xorl %eax, %eax
+	call printf@PLT
+	popq %rbp
+	ret
+
+__p0rt_print_newline:
+	pushq %rbp
+	movq %rsp, %rbp
+	movl $10, %edi
+	call putchar@PLT
+	popq %rbp
+	ret
+
+# __p0rt_div_by_zero(函數名稱, IR 位置)：印出和 VM 相同的錯誤後結束
+__p0rt_div_by_zero:
+	pushq %rbp
+	movq %rsp, %rbp
+	pushq %rdi
+	pushq %rsi
+	xorl %edi, %edi
+	call fflush@PLT
+	popq %rsi
+	popq %rdi
+	movl %esi, %ecx
+	movq %rdi, %rdx
+	leaq .Lfmt_div0(%rip), %rsi
+	movl $2, %edi
+	xorl %eax, %eax
+	call dprintf@PLT
+	movl $1, %edi
+	call exit@PLT
+
+"#;
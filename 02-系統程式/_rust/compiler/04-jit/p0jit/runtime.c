@@ -0,0 +1,25 @@
+// p0jit --aot 產生的目的檔所需的執行環境
+// print 的輸出和 VM 的 native_print 一樣：每個參數後面接一個空白，最後換行
+#include <stdio.h>
+#include <stdlib.h>
+
+int p0___init__(void);
+int p0_main(void);
+
+void print_i32(int val) { printf("%d ", val); }
+void print_str(const char *s) { printf("%s ", s); }
+void print_end(void) { putchar('\n'); }
+
+// 執行期錯誤：訊息格式和 VM 相同，印出後結束程式
+void runtime_error(const char *msg, const char *func, int ip) {
+    fflush(stdout);
+    fprintf(stderr, "執行期錯誤: %s (於函數 %s 的 IR #%d)\n", msg, func, ip);
+    exit(1);
+}
+
+// 先設定全域變數再執行 main，回傳值的格式和 JIT 相同
+int main(void) {
+    p0___init__();
+    printf("回傳值: %d\n", p0_main());
+    return 0;
+}
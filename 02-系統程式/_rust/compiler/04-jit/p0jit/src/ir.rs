@@ -0,0 +1,520 @@
+// ==========================================================
+// 從 03-print 輸出的 IR 文字格式 (.ir 檔) 編譯
+//
+//   .version 1
+//   FUNC factorial n
+//     LOAD_VAR t1 n
+//     IFFALSE t3 L1
+//     ...
+//   L1:
+//     ...
+//   ENDFUNC
+//
+// 暫存值與變數都變成 Cranelift 的 Variable，跳躍目標 (以及 IFFALSE 不跳時的下一條指令)
+// 各自是一個 block。字串只能由 LOAD_STR 產生、經過變數交給 print，這些名稱的 Variable
+// 用指標型別。和 VM 一樣在執行期檢查除以零與呼叫深度，錯誤訊息也相同。
+// 陣列與 print 以外的內建函數不支援，載入時就回報。
+// ==========================================================
+
+use super::*;
+use std::collections::{BTreeSet, HashSet};
+
+const VERSION: u32 = 1;
+
+// 和 VM 預設的最大呼叫深度相同
+pub(crate) const MAX_DEPTH: i64 = 10000;
+
+// 呼叫深度的計數器 (main 以外還在執行的函數數) 放在 globals 裡，p0 的名稱不會有 $
+const DEPTH: &str = "$depth";
+
+pub enum Instr {
+    Const(String, i32),
+    Str(String, String),
+    LoadVar(String, String),
+    StoreVar(String, String),
+    LoadGlobal(String, String),  // t = 全域變數
+    StoreGlobal(String, String), // 全域變數 = t
+    Binary(&'static str, String, String, String), // (指令名稱, t, 左, 右)
+    Neg(String, String),
+    Not(String, String),
+    Call(String, Vec<String>, String), // (函數, 參數, 結果)
+    Return(String),
+    IfFalse(String, usize),
+    Goto(usize),
+    Label,
+}
+
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub code: Vec<Instr>,
+    strings: HashSet<String>, // 裝字串的暫存值與變數 (變數的名稱見 var_key)
+}
+
+const BINARY: [&str; 11] = ["ADD", "SUB", "MUL", "DIV", "MOD", "EQ", "NE", "LT", "LE", "GT", "GE"];
+
+// 把一行切成 token，字串常數保留引號以外的內容並處理跳脫；分號之後是註解
+fn tokenize(line: &str) -> Result<Vec<(String, bool)>, String> {
+    let mut toks = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() { chars.next(); continue; }
+        if c == ';' { break; }
+        if c == '"' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    None => return Err("字串沒有結束的 '\"'".to_string()),
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('"') => s.push('"'),
+                        Some('\\') => s.push('\\'),
+                        Some('n') => s.push('\n'),
+                        Some('t') => s.push('\t'),
+                        Some('r') => s.push('\r'),
+                        Some('u') if chars.next() == Some('{') => {
+                            let hex: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                            match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                                Some(c) => s.push(c),
+                                None => return Err("無效的 \\u{..} 跳脫".to_string()),
+                            }
+                        }
+                        _ => return Err("未知的跳脫字元".to_string()),
+                    },
+                    Some(c) => s.push(c),
+                }
+            }
+            toks.push((s, true));
+        } else {
+            let mut w = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ';' || c == '"' { break; }
+                w.push(c);
+                chars.next();
+            }
+            toks.push((w, false));
+        }
+    }
+    Ok(toks)
+}
+
+// 讀取整個 .ir 檔；錯誤訊息附上行號
+pub fn parse(text: &str) -> Result<Vec<Function>, String> {
+    let mut functions: Vec<Function> = Vec::new();
+    let mut version_seen = false;
+    // 正在讀的函數：(名稱, 參數, 指令, 標籤 -> 位置, 待回填的跳躍 (位置, 標籤, 行號))
+    #[allow(clippy::type_complexity)]
+    let mut current: Option<(String, Vec<String>, Vec<Instr>, HashMap<String, usize>, Vec<(usize, String, usize)>)> = None;
+
+    for (idx, line) in text.lines().enumerate() {
+        let line_no = idx + 1;
+        let err = |msg: String| format!("第 {} 行: {}", line_no, msg);
+        let toks = tokenize(line).map_err(err)?;
+        if toks.is_empty() { continue; }
+        let words: Vec<&str> = toks.iter().map(|(w, is_str)| if *is_str { "" } else { w.as_str() }).collect();
+        if !version_seen {
+            if words.as_slice() != [".version", VERSION.to_string().as_str()] {
+                return Err(err(format!("缺少版本標頭 `.version {}`", VERSION)));
+            }
+            version_seen = true;
+            continue;
+        }
+        match words[0] {
+            // 模組快取的 import / export，JIT 一次只編譯一個檔案，用不到
+            ".import" | ".export" => {}
+            "FUNC" => {
+                if current.is_some() { return Err(err("上一個函數還沒有 ENDFUNC".to_string())); }
+                let name = words.get(1).filter(|n| !n.is_empty()).ok_or_else(|| err("FUNC 後面需要函數名稱".to_string()))?;
+                if functions.iter().any(|f| f.name == *name) { return Err(err(format!("函數 {} 重複定義", name))); }
+                let params = words[2..].iter().map(|s| s.to_string()).collect();
+                current = Some((name.to_string(), params, Vec::new(), HashMap::new(), Vec::new()));
+            }
+            "ENDFUNC" => {
+                let (name, params, mut code, labels, jumps) = current.take().ok_or_else(|| err("ENDFUNC 沒有對應的 FUNC".to_string()))?;
+                for (ip, label, line_no) in jumps {
+                    let target = *labels.get(&label).ok_or_else(|| format!("第 {} 行: 未定義的標籤 {}", line_no, label))?;
+                    match &mut code[ip] {
+                        Instr::IfFalse(_, t) | Instr::Goto(t) => *t = target,
+                        _ => unreachable!(),
+                    }
+                }
+                functions.push(Function { name, params, code, strings: HashSet::new() });
+            }
+            w if w.ends_with(':') && toks.len() == 1 => {
+                let (_, _, code, labels, _) = current.as_mut().ok_or_else(|| err("標籤必須在 FUNC 裡面".to_string()))?;
+                if labels.insert(w.trim_end_matches(':').to_string(), code.len()).is_some() {
+                    return Err(err(format!("標籤 {} 重複定義", w.trim_end_matches(':'))));
+                }
+            }
+            op => {
+                let (_, _, code, _, jumps) = current.as_mut().ok_or_else(|| err("指令必須在 FUNC 裡面".to_string()))?;
+                let (instr, jump) = parse_instr(op, &toks[1..]).map_err(err)?;
+                if let Some(label) = jump { jumps.push((code.len(), label, line_no)); }
+                code.push(instr);
+            }
+        }
+    }
+    if !version_seen { return Err(format!("缺少版本標頭 `.version {}`", VERSION)); }
+    if let Some((name, ..)) = current { return Err(format!("函數 {} 缺少 ENDFUNC", name)); }
+    check(&mut functions)?;
+    Ok(functions)
+}
+
+// 解析一條指令；跳躍指令的目標先填 0，連同標籤名稱一起回傳
+fn parse_instr(op: &str, args: &[(String, bool)]) -> Result<(Instr, Option<String>), String> {
+    let name = |i: usize| match args.get(i) {
+        Some((w, false)) => Ok(w.clone()),
+        Some(_) => Err("這裡需要名稱".to_string()),
+        None => Err(format!("{} 的運算元不夠", op)),
+    };
+    let fixed = |n: usize| if args.len() > n { Err(format!("{} 只需要 {} 個運算元", op, n)) } else { Ok(()) };
+    let instr = match op {
+        "LOAD_CONST" => {
+            fixed(2)?;
+            let v = name(1)?.parse().map_err(|_| "LOAD_CONST 需要整數".to_string())?;
+            Instr::Const(name(0)?, v)
+        }
+        "LOAD_STR" => {
+            fixed(2)?;
+            match args.get(1) {
+                Some((s, true)) => Instr::Str(name(0)?, s.clone()),
+                _ => return Err("LOAD_STR 需要以 \"...\" 括起來的字串".to_string()),
+            }
+        }
+        "LOAD_VAR" | "STORE_VAR" | "LOAD_GLOBAL" | "STORE_GLOBAL" | "NEG" | "NOT" => {
+            fixed(2)?;
+            let (a, b) = (name(0)?, name(1)?);
+            match op {
+                "LOAD_VAR" => Instr::LoadVar(a, b),
+                "STORE_VAR" => Instr::StoreVar(a, b),
+                "LOAD_GLOBAL" => Instr::LoadGlobal(a, b),
+                "STORE_GLOBAL" => Instr::StoreGlobal(a, b),
+                "NEG" => Instr::Neg(a, b),
+                _ => Instr::Not(a, b),
+            }
+        }
+        op if BINARY.contains(&op) => {
+            fixed(3)?;
+            let op = BINARY.iter().find(|b| **b == op).unwrap();
+            Instr::Binary(op, name(0)?, name(1)?, name(2)?)
+        }
+        "CALL" => Instr::Call(name(0)?, (2..args.len()).map(name).collect::<Result<_, _>>()?, name(1)?),
+        "RETURN" => { fixed(1)?; Instr::Return(name(0)?) }
+        "LABEL" => { fixed(0)?; Instr::Label }
+        "IFFALSE" => { fixed(2)?; return Ok((Instr::IfFalse(name(0)?, 0), Some(name(1)?))); }
+        "GOTO" => { fixed(1)?; return Ok((Instr::Goto(0), Some(name(0)?))); }
+        "NEW_ARRAY" | "INDEX_LOAD" | "INDEX_STORE" => return Err(format!("JIT 不支援陣列 ({})", op)),
+        _ => return Err(format!("未知指令 {}", op)),
+    };
+    Ok((instr, None))
+}
+
+// 變數在 Function::strings 裡的名稱，加上 $ 才不會和同名的暫存值混在一起
+fn var_key(name: &str) -> String {
+    format!("${}", name)
+}
+
+// 檢查呼叫的函數與參數個數，並找出裝字串的暫存值與變數
+fn check(functions: &mut [Function]) -> Result<(), String> {
+    let arities: HashMap<String, usize> = functions.iter().map(|f| (f.name.clone(), f.params.len())).collect();
+    for f in functions.iter_mut() {
+        let at = |ip: usize| format!("函數 {} 的 IR #{}", f.name, ip);
+        for (ip, instr) in f.code.iter().enumerate() {
+            if let Instr::Call(callee, args, _) = instr {
+                match arities.get(callee) {
+                    Some(n) if *n != args.len() => {
+                        return Err(format!("函數 {} 需要 {} 個參數，這裡給了 {} 個 ({})", callee, n, args.len(), at(ip)));
+                    }
+                    None if callee != "print" => return Err(format!("JIT 不支援或未定義的函數 {} ({})", callee, at(ip))),
+                    _ => {}
+                }
+            }
+        }
+
+        // 字串沿著 LOAD_STR -> STORE_VAR -> LOAD_VAR 傳遞，直到不再增加
+        let mut strings = HashSet::new();
+        loop {
+            let before = strings.len();
+            for instr in &f.code {
+                match instr {
+                    Instr::Str(t, _) => { strings.insert(t.clone()); }
+                    Instr::StoreVar(v, t) if strings.contains(t) => { strings.insert(var_key(v)); }
+                    Instr::LoadVar(t, v) if strings.contains(&var_key(v)) => { strings.insert(t.clone()); }
+                    _ => {}
+                }
+            }
+            if strings.len() == before { break; }
+        }
+
+        // 字串只能存進變數、讀出來，或交給 print
+        for (ip, instr) in f.code.iter().enumerate() {
+            let ok = match instr {
+                Instr::Str(..) | Instr::Label | Instr::Goto(_) => true,
+                Instr::LoadVar(t, v) | Instr::StoreVar(v, t) => strings.contains(t) == strings.contains(&var_key(v)),
+                Instr::Call(callee, args, t) => !strings.contains(t) && (callee == "print" || args.iter().all(|a| !strings.contains(a))),
+                Instr::Const(t, _) | Instr::LoadGlobal(t, _) | Instr::StoreGlobal(_, t) | Instr::Return(t) | Instr::IfFalse(t, _) => !strings.contains(t),
+                Instr::Neg(t, s) | Instr::Not(t, s) => !strings.contains(t) && !strings.contains(s),
+                Instr::Binary(_, t, l, r) => [t, l, r].iter().all(|n| !strings.contains(*n)),
+            };
+            if !ok { return Err(format!("JIT 的字串只能交給 print ({})", at(ip))); }
+        }
+        if let Some(p) = f.params.iter().find(|p| strings.contains(&var_key(p))) {
+            return Err(format!("JIT 的字串只能交給 print (函數 {} 的參數 {})", f.name, p));
+        }
+        f.strings = strings;
+    }
+    Ok(())
+}
+
+impl<M: Module> JIT<M> {
+    pub(crate) fn define_ir(&mut self, mut functions: Vec<Function>) -> HashMap<String, FuncId> {
+        self.declare_global(DEPTH);
+        for f in &functions {
+            for instr in &f.code {
+                if let Instr::LoadGlobal(_, g) | Instr::StoreGlobal(g, _) = instr { self.declare_global(g); }
+            }
+        }
+        // 沒有全域變數的程式也要有 __init__，執行環境才能一律呼叫它
+        if !functions.iter().any(|f| f.name == INIT) {
+            functions.push(Function { name: INIT.to_string(), params: Vec::new(), code: Vec::new(), strings: HashSet::new() });
+        }
+
+        let mut ids = HashMap::new();
+        for f in functions {
+            let mut sig = self.module.make_signature();
+            for _ in &f.params { sig.params.push(AbiParam::new(types::I32)); }
+            sig.returns.push(AbiParam::new(types::I32));
+            let id = self.module.declare_function(&symbol(&f.name), Linkage::Export, &sig).unwrap();
+            let name = f.name.clone();
+            self.compile_fn(id, f.params.clone(), |t| t.translate_ir(&f));
+            ids.insert(name, id);
+        }
+        ids
+    }
+}
+
+impl<M: Module> FunctionTranslator<'_, M> {
+    fn new_var(&mut self, ty: Type) -> Variable {
+        let var = Variable::new(self.next_var);
+        self.next_var += 1;
+        self.builder.declare_var(var, ty);
+        var
+    }
+
+    pub(crate) fn translate_ir(&mut self, f: &Function) {
+        let ptr = self.module.target_config().pointer_type();
+        let ty = |key: &str| if f.strings.contains(key) { ptr } else { types::I32 };
+
+        // 暫存值與變數各自對應一個 Variable，先全部宣告好 (參數已經在 scopes[0] 裡)
+        let mut temps: HashMap<&str, Variable> = HashMap::new();
+        for instr in &f.code {
+            let (used, var): (Vec<&String>, Option<&String>) = match instr {
+                Instr::Const(t, _) | Instr::Str(t, _) | Instr::LoadGlobal(t, _) | Instr::StoreGlobal(_, t)
+                | Instr::Return(t) | Instr::IfFalse(t, _) => (vec![t], None),
+                Instr::LoadVar(t, v) | Instr::StoreVar(v, t) => (vec![t], Some(v)),
+                Instr::Neg(t, s) | Instr::Not(t, s) => (vec![t, s], None),
+                Instr::Binary(_, t, l, r) => (vec![t, l, r], None),
+                Instr::Call(_, args, t) => (args.iter().chain([t]).collect(), None),
+                Instr::Goto(_) | Instr::Label => (Vec::new(), None),
+            };
+            for t in used {
+                if !temps.contains_key(t.as_str()) {
+                    let var = self.new_var(ty(t));
+                    temps.insert(t, var);
+                }
+            }
+            if let Some(v) = var.filter(|v| self.lookup(v).is_none()) {
+                let var = self.new_var(ty(&var_key(v)));
+                self.scopes[0].insert(v.clone(), var);
+            }
+        }
+
+        // 每個跳躍目標與 IFFALSE 的下一條指令開始一個 block
+        let mut starts = BTreeSet::new();
+        for (ip, instr) in f.code.iter().enumerate() {
+            match instr {
+                Instr::IfFalse(_, target) => { starts.insert(*target); starts.insert(ip + 1); }
+                Instr::Goto(target) => { starts.insert(*target); }
+                _ => {}
+            }
+        }
+        let blocks: HashMap<usize, Block> = starts.into_iter().map(|ip| (ip, self.builder.create_block())).collect();
+
+        for ip in 0..=f.code.len() {
+            if let Some(&block) = blocks.get(&ip) {
+                if !self.terminated { self.builder.ins().jump(block, &[]); }
+                self.builder.switch_to_block(block);
+                self.terminated = false;
+            } else if self.terminated {
+                continue; // RETURN / GOTO 之後到下一個跳躍目標之間走不到
+            }
+            let Some(instr) = f.code.get(ip) else { break };
+            match instr {
+                Instr::Const(t, v) => {
+                    let val = self.builder.ins().iconst(types::I32, *v as i64);
+                    self.builder.def_var(temps[t.as_str()], val);
+                }
+                Instr::Str(t, s) => {
+                    let val = self.string_addr(s);
+                    self.builder.def_var(temps[t.as_str()], val);
+                }
+                Instr::LoadVar(t, v) => {
+                    let val = self.builder.use_var(self.lookup(v).unwrap());
+                    self.builder.def_var(temps[t.as_str()], val);
+                }
+                Instr::StoreVar(v, t) => {
+                    let val = self.builder.use_var(temps[t.as_str()]);
+                    self.builder.def_var(self.lookup(v).unwrap(), val);
+                }
+                Instr::LoadGlobal(t, g) => {
+                    let addr = self.global_addr(g);
+                    let val = self.builder.ins().load(types::I32, MemFlags::trusted(), addr, 0);
+                    self.builder.def_var(temps[t.as_str()], val);
+                }
+                Instr::StoreGlobal(g, t) => {
+                    let val = self.builder.use_var(temps[t.as_str()]);
+                    let addr = self.global_addr(g);
+                    self.builder.ins().store(MemFlags::trusted(), val, addr, 0);
+                }
+                Instr::Binary(op, t, l, r) => {
+                    let lhs = self.builder.use_var(temps[l.as_str()]);
+                    let rhs = self.builder.use_var(temps[r.as_str()]);
+                    let val = self.binary(op, lhs, rhs, &f.name, ip);
+                    self.builder.def_var(temps[t.as_str()], val);
+                }
+                Instr::Neg(t, s) => {
+                    let v = self.builder.use_var(temps[s.as_str()]);
+                    let val = self.builder.ins().ineg(v);
+                    self.builder.def_var(temps[t.as_str()], val);
+                }
+                Instr::Not(t, s) => {
+                    let v = self.builder.use_var(temps[s.as_str()]);
+                    let is_zero = self.builder.ins().icmp_imm(IntCC::Equal, v, 0);
+                    let val = self.builder.ins().uextend(types::I32, is_zero);
+                    self.builder.def_var(temps[t.as_str()], val);
+                }
+                Instr::Call(callee, args, t) => {
+                    let arg_vals: Vec<cranelift::prelude::Value> = args.iter().map(|a| self.builder.use_var(temps[a.as_str()])).collect();
+                    let val = if callee == "print" {
+                        for (arg, val) in args.iter().zip(arg_vals) {
+                            self.call_host(if f.strings.contains(arg) { "print_str" } else { "print_i32" }, &[val]);
+                        }
+                        self.call_host("print_end", &[]);
+                        self.builder.ins().iconst(types::I32, 0)
+                    } else {
+                        self.call_ir(callee, &arg_vals, &f.name, ip)
+                    };
+                    self.builder.def_var(temps[t.as_str()], val);
+                }
+                Instr::Return(t) => {
+                    let val = self.builder.use_var(temps[t.as_str()]);
+                    self.builder.ins().return_(&[val]);
+                    self.terminated = true;
+                }
+                Instr::IfFalse(t, target) => {
+                    let cond = self.builder.use_var(temps[t.as_str()]);
+                    self.builder.ins().brif(cond, blocks[&(ip + 1)], &[], blocks[target], &[]);
+                    self.terminated = true;
+                }
+                Instr::Goto(target) => {
+                    self.builder.ins().jump(blocks[target], &[]);
+                    self.terminated = true;
+                }
+                Instr::Label => {}
+            }
+        }
+        self.builder.seal_all_blocks();
+    }
+
+    // VM 的整數運算：溢位時繞回，除以零是執行期錯誤
+    fn binary(&mut self, op: &str, lhs: cranelift::prelude::Value, rhs: cranelift::prelude::Value, func: &str, ip: usize) -> cranelift::prelude::Value {
+        let cc = match op {
+            "EQ" => Some(IntCC::Equal),
+            "NE" => Some(IntCC::NotEqual),
+            "LT" => Some(IntCC::SignedLessThan),
+            "LE" => Some(IntCC::SignedLessThanOrEqual),
+            "GT" => Some(IntCC::SignedGreaterThan),
+            "GE" => Some(IntCC::SignedGreaterThanOrEqual),
+            _ => None,
+        };
+        if let Some(cc) = cc {
+            let res = self.builder.ins().icmp(cc, lhs, rhs);
+            return self.builder.ins().uextend(types::I32, res);
+        }
+        match op {
+            "ADD" => self.builder.ins().iadd(lhs, rhs),
+            "SUB" => self.builder.ins().isub(lhs, rhs),
+            "MUL" => self.builder.ins().imul(lhs, rhs),
+            _ => {
+                let is_zero = self.builder.ins().icmp_imm(IntCC::Equal, rhs, 0);
+                self.check(is_zero, "除以零", func, ip);
+                // sdiv / srem 在 i32::MIN / -1 時會 trap；除數是 -1 時改除以 1，商再取負號
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, rhs, -1);
+                let one = self.builder.ins().iconst(types::I32, 1);
+                let divisor = self.builder.ins().select(minus_one, one, rhs);
+                if op == "MOD" { return self.builder.ins().srem(lhs, divisor); }
+                let quotient = self.builder.ins().sdiv(lhs, divisor);
+                let negated = self.builder.ins().ineg(lhs);
+                self.builder.ins().select(minus_one, negated, quotient)
+            }
+        }
+    }
+
+    // 呼叫 IR 裡的函數，前後維護呼叫深度
+    fn call_ir(&mut self, callee: &str, args: &[cranelift::prelude::Value], func: &str, ip: usize) -> cranelift::prelude::Value {
+        if let Some(ids) = self.tier_ids {
+            return self.call_tier(ids[func], ids[callee], args, ip);
+        }
+        let addr = self.global_addr(DEPTH);
+        let depth = self.builder.ins().load(types::I32, MemFlags::trusted(), addr, 0);
+        // 加上 main 就是 VM 的堆疊深度
+        let too_deep = self.builder.ins().icmp_imm(IntCC::SignedGreaterThanOrEqual, depth, MAX_DEPTH - 1);
+        self.check(too_deep, &format!("呼叫深度超過上限 {}", MAX_DEPTH), func, ip);
+        let deeper = self.builder.ins().iadd_imm(depth, 1);
+        self.builder.ins().store(MemFlags::trusted(), deeper, addr, 0);
+
+        let mut sig = self.module.make_signature();
+        for _ in args { sig.params.push(AbiParam::new(types::I32)); }
+        sig.returns.push(AbiParam::new(types::I32));
+        let callee = self.module.declare_function(&symbol(callee), Linkage::Import, &sig).unwrap();
+        let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
+        let call = self.builder.ins().call(local_callee, args);
+        let result = self.builder.inst_results(call)[0];
+        self.builder.ins().store(MemFlags::trusted(), depth, addr, 0);
+        result
+    }
+
+    // 分層執行：參數放進堆疊上的陣列交給 tier_call，由它決定直譯還是執行編譯好的程式碼 (呼叫深度也由它檢查)
+    fn call_tier(&mut self, caller: usize, callee: usize, args: &[cranelift::prelude::Value], ip: usize) -> cranelift::prelude::Value {
+        let slot = self.builder.create_sized_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 4 * args.len().max(1) as u32, 2));
+        for (i, &arg) in args.iter().enumerate() {
+            self.builder.ins().stack_store(arg, slot, 4 * i as i32);
+        }
+        let ptr = self.module.target_config().pointer_type();
+        let mut call_args: Vec<_> = [caller, ip, callee].iter().map(|&n| self.builder.ins().iconst(types::I32, n as i64)).collect();
+        call_args.push(self.builder.ins().stack_addr(ptr, slot, 0));
+
+        let mut sig = self.module.make_signature();
+        for &arg in &call_args { sig.params.push(AbiParam::new(self.builder.func.dfg.value_type(arg))); }
+        sig.returns.push(AbiParam::new(types::I32));
+        let trampoline = self.module.declare_function("tier_call", Linkage::Import, &sig).unwrap();
+        let local = self.module.declare_func_in_func(trampoline, self.builder.func);
+        let call = self.builder.ins().call(local, &call_args);
+        self.builder.inst_results(call)[0]
+    }
+
+    // failed 成立時呼叫主機的 runtime_error (印出錯誤後結束程式)，否則繼續往下執行
+    fn check(&mut self, failed: cranelift::prelude::Value, msg: &str, func: &str, ip: usize) {
+        let error_block = self.builder.create_block();
+        let ok_block = self.builder.create_block();
+        self.builder.ins().brif(failed, error_block, &[], ok_block, &[]);
+        self.builder.switch_to_block(error_block);
+        let (msg, func) = (self.string_addr(msg), self.string_addr(func));
+        let ip = self.builder.ins().iconst(types::I32, ip as i64);
+        self.call_host("runtime_error", &[msg, func, ip]);
+        let zero = self.builder.ins().iconst(types::I32, 0);
+        self.builder.ins().return_(&[zero]);
+        self.builder.switch_to_block(ok_block);
+    }
+}
@@ -1,9 +1,18 @@
 use cranelift::prelude::*;
+use cranelift::codegen::isa::OwnedTargetIsa;
 use cranelift_jit::{JITBuilder, JITModule};
-use cranelift_module::{Linkage, Module};
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module};
+use cranelift_object::{ObjectBuilder, ObjectModule};
 use std::collections::HashMap;
 use std::env;
+use std::ffi::{c_char, CStr};
 use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+
+mod ir;
+mod tier;
 
 // ==========================================================
 // 1. 定義 Token 與 AST
@@ -11,7 +20,7 @@ use std::fs;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Fn, Let, If, Else, Return,
+    Fn, Let, Const, If, Else, Return,
     Ident(String),
     Int(i32),
     Str(String),
@@ -24,8 +33,7 @@ pub enum Token {
 pub enum Expr {
     Number(i32),
     Variable(String),
-    #[allow(dead_code)]
-    Str(String),
+    Str(String), // 目前只能當 print 的參數
     BinaryOp(Box<Expr>, Token, Box<Expr>),
     Call(String, Vec<Expr>),
 }
@@ -33,6 +41,8 @@ pub enum Expr {
 #[derive(Debug, Clone)]
 pub enum Stmt {
     VarDecl(String, Expr),
+    ConstDecl(String, Expr), // 只出現在最外層
+    Assign(String, Expr),
     If(Expr, Vec<Stmt>, Option<Vec<Stmt>>),
     Return(Expr),
     FuncDecl(String, Vec<String>, Vec<Stmt>),
@@ -74,15 +84,15 @@ impl Lexer {
             }
             let s: String = self.input[start..self.pos].iter().collect();
             return match s.as_str() {
-                "fn" => Token::Fn, "let" => Token::Let, "if" => Token::If,
+                "fn" => Token::Fn, "let" => Token::Let, "const" => Token::Const, "if" => Token::If,
                 "else" => Token::Else, "return" => Token::Return,
                 _ => Token::Ident(s),
             };
         }
 
-        if ch.is_digit(10) {
+        if ch.is_ascii_digit() {
             let start = self.pos;
-            while self.pos < self.input.len() && self.input[self.pos].is_digit(10) { self.pos += 1; }
+            while self.pos < self.input.len() && self.input[self.pos].is_ascii_digit() { self.pos += 1; }
             let s: String = self.input[start..self.pos].iter().collect();
             return Token::Int(s.parse().unwrap());
         }
@@ -118,11 +128,24 @@ impl Parser {
     fn parse_program(&mut self) -> Vec<Stmt> {
         let mut stmts = Vec::new();
         while self.cur_tok != Token::EOF {
-            if self.cur_tok == Token::Fn { stmts.push(self.parse_function()); }
-            else { self.next(); }
+            match self.cur_tok {
+                Token::Fn => stmts.push(self.parse_function()),
+                Token::Let | Token::Const => stmts.push(self.parse_global()),
+                _ => self.next(),
+            }
         }
         stmts
     }
+    // 最外層的 let / const 是全域變數
+    fn parse_global(&mut self) -> Stmt {
+        let is_const = self.cur_tok == Token::Const;
+        self.next();
+        let name = if let Token::Ident(n) = &self.cur_tok { n.clone() } else { panic!("預期變數名") };
+        self.next(); self.next(); // =
+        let expr = self.parse_expr(0);
+        if self.cur_tok == Token::Semi { self.next(); }
+        if is_const { Stmt::ConstDecl(name, expr) } else { Stmt::VarDecl(name, expr) }
+    }
     fn parse_function(&mut self) -> Stmt {
         self.next(); // fn
         let name = if let Token::Ident(n) = &self.cur_tok { n.clone() } else { panic!("預期函數名稱") };
@@ -170,8 +193,15 @@ impl Parser {
             }
             _ => {
                 let expr = self.parse_expr(0);
+                let stmt = match (expr, &self.cur_tok) {
+                    (Expr::Variable(name), Token::Assign) => {
+                        self.next(); // =
+                        Stmt::Assign(name, self.parse_expr(0))
+                    }
+                    (expr, _) => Stmt::ExprStmt(expr),
+                };
                 if self.cur_tok == Token::Semi { self.next(); }
-                Stmt::ExprStmt(expr)
+                stmt
             }
         }
     }
@@ -218,34 +248,132 @@ impl Parser {
 // 4. JIT 編譯器核心
 // ==========================================================
 
-extern "C" fn p0_print_i32(val: i32) { println!("{}", val); }
+// print 的輸出和 VM 的 native_print 一樣：每個參數後面接一個空白，最後換行
+extern "C" fn p0_print_i32(val: i32) { print!("{} ", val); }
+extern "C" fn p0_print_str(s: *const c_char) { print!("{} ", unsafe { CStr::from_ptr(s) }.to_string_lossy()); }
+extern "C" fn p0_print_end() { println!(); }
+
+// 執行期錯誤 (目前只有從 .ir 編譯的程式會檢查)：訊息格式和 VM 相同，印出後結束程式
+fn runtime_error(msg: &str, func: &str, ip: usize) -> ! {
+    std::io::stdout().flush().ok();
+    eprintln!("執行期錯誤: {} (於函數 {} 的 IR #{})", msg, func, ip);
+    process::exit(1);
+}
+
+extern "C" fn p0_runtime_error(msg: *const c_char, func: *const c_char, ip: i32) {
+    let text = |s| unsafe { CStr::from_ptr(s) }.to_string_lossy();
+    runtime_error(&text(msg), &text(func), ip as usize);
+}
+
+// 設定全域變數初始值的函數，main 之前執行
+const INIT: &str = "__init__";
+
+// AOT 目的檔要連結的執行環境：上面這些主機函數，以及呼叫 p0_main 的 C main
+const RUNTIME: &str = include_str!("../runtime.c");
+
+// p0 函數在模組裡的符號名稱。加上 p0_ 才不會和 C 的 main 或主機函數撞名 (和 03-print 的 x86 後端相同)
+fn symbol(name: &str) -> String {
+    format!("p0_{}", name)
+}
+
+fn isa(pic: bool) -> OwnedTargetIsa {
+    let mut flag_builder = settings::builder();
+    flag_builder.set("use_colocated_libcalls", "false").unwrap();
+    flag_builder.set("is_pic", if pic { "true" } else { "false" }).unwrap();
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| panic!("ISA 錯誤: {}", msg));
+    isa_builder.finish(settings::Flags::new(flag_builder)).unwrap()
+}
+
+impl Default for JIT {
+    fn default() -> Self { Self::new() }
+}
+
+// JIT 的輸入：p0 原始碼剖析出的 AST，或 03-print 輸出的 IR
+pub enum Program {
+    Ast(Vec<Stmt>),
+    Ir(Vec<ir::Function>),
+}
 
-pub struct JIT {
+// 同一套翻譯可以輸出到記憶體 (JITModule) 或目的檔 (ObjectModule)
+pub struct JIT<M: Module = JITModule> {
     builder_context: FunctionBuilderContext,
     ctx: codegen::Context,
-    module: JITModule,
+    module: M,
+    globals: HashMap<String, DataId>, // 全域變數 -> 模組裡的資料物件
+    strings: HashMap<String, DataId>, // 字串常數 -> 唯讀資料物件，內容相同的共用一個
+    // 分層執行 (--tiered) 時每個函數的編號：函數一律是 (參數陣列) -> i32，呼叫其他函數都經過 tier_call
+    tier_ids: Option<HashMap<String, usize>>,
 }
 
 impl JIT {
     pub fn new() -> Self {
-        let mut flag_builder = settings::builder();
-        flag_builder.set("use_colocated_libcalls", "false").unwrap();
-        flag_builder.set("is_pic", "false").unwrap();
-        let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| panic!("ISA 錯誤: {}", msg));
-        let isa = isa_builder.finish(settings::Flags::new(flag_builder)).unwrap();
-        let mut builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+        let mut builder = JITBuilder::with_isa(isa(false), cranelift_module::default_libcall_names());
         builder.symbol("print_i32", p0_print_i32 as *const u8);
-        let module = JITModule::new(builder);
+        builder.symbol("print_str", p0_print_str as *const u8);
+        builder.symbol("print_end", p0_print_end as *const u8);
+        builder.symbol("runtime_error", p0_runtime_error as *const u8);
+        builder.symbol("tier_call", tier::tier_call as *const u8);
+        Self::with_module(JITModule::new(builder))
+    }
+
+    pub fn compile(&mut self, program: Program) -> HashMap<String, *const u8> {
+        let ids = self.define(program);
+        self.module.finalize_definitions().unwrap();
+        ids.into_iter().map(|(name, id)| (name, self.module.get_finalized_function(id))).collect()
+    }
+}
+
+impl JIT<ObjectModule> {
+    // 目的檔要連結進 PIE 執行檔，所以產生位置無關的程式碼
+    pub fn aot(name: &str) -> Self {
+        let builder = ObjectBuilder::new(isa(true), name, cranelift_module::default_libcall_names()).unwrap();
+        Self::with_module(ObjectModule::new(builder))
+    }
+
+    // 產生可重定位的 ELF 目的檔內容，p0 函數都是 export 的符號
+    pub fn compile_object(mut self, program: Program) -> Vec<u8> {
+        self.define(program);
+        self.module.finish().emit().unwrap()
+    }
+}
+
+impl<M: Module> JIT<M> {
+    fn with_module(module: M) -> Self {
         Self {
             builder_context: FunctionBuilderContext::new(),
             ctx: codegen::Context::new(),
             module,
+            globals: HashMap::new(),
+            strings: HashMap::new(),
+            tier_ids: None,
         }
     }
 
-pub fn compile(&mut self, program: Vec<Stmt>) -> HashMap<String, *const u8> {
-        let mut function_pointers = HashMap::new();
+    fn define(&mut self, program: Program) -> HashMap<String, FuncId> {
+        match program {
+            Program::Ast(stmts) => self.define_ast(stmts),
+            Program::Ir(functions) => self.define_ir(functions),
+        }
+    }
+
+    fn define_ast(&mut self, program: Vec<Stmt>) -> HashMap<String, FuncId> {
+        // 全域變數先宣告成資料物件，初始值的計算收集成 __init__ 函數
+        // (沒有全域變數時是空函數，AOT 的執行環境才能一律呼叫它)
+        let mut functions = Vec::new();
+        let mut inits = Vec::new();
         for stmt in program {
+            match stmt {
+                Stmt::VarDecl(name, expr) | Stmt::ConstDecl(name, expr) => {
+                    self.declare_global(&name);
+                    inits.push(Stmt::Assign(name, expr));
+                }
+                stmt => functions.push(stmt),
+            }
+        }
+        functions.push(Stmt::FuncDecl(INIT.to_string(), Vec::new(), inits));
+
+        let mut ids = HashMap::new();
+        for stmt in functions {
             if let Stmt::FuncDecl(name, params, body) = stmt {
                 // --- 修正處：先建立正確的簽名再宣告 ---
                 let mut sig = self.module.make_signature();
@@ -257,21 +385,38 @@ pub fn compile(&mut self, program: Vec<Stmt>) -> HashMap<String, *const u8> {
                 sig.returns.push(AbiParam::new(types::I32));
 
                 // 使用正確的 sig 進行宣告
-                let id = self.module.declare_function(&name, Linkage::Export, &sig).unwrap();
+                let id = self.module.declare_function(&symbol(&name), Linkage::Export, &sig).unwrap();
                 
-                let ptr = self.compile_fn(id, params, body);
-                function_pointers.insert(name, ptr);
+                self.compile_fn(id, params, |t| t.translate_block(body));
+                ids.insert(name, id);
             }
         }
-        function_pointers
+        ids
     }
     
-fn compile_fn(&mut self, id: cranelift_module::FuncId, params: Vec<String>, body: Vec<Stmt>) -> *const u8 {
+    // 每個全域變數是一個 4 bytes、初始為 0 的可寫資料物件。
+    // 函數與資料共用同一個名稱空間，所以符號名稱加上 "global." (p0 的名稱不會有 .)
+    fn declare_global(&mut self, name: &str) {
+        if self.globals.contains_key(name) { return; }
+        let id = self.module.declare_data(&format!("global.{}", name), Linkage::Local, true, false).unwrap();
+        let mut desc = DataDescription::new();
+        desc.define_zeroinit(4);
+        self.module.define_data(id, &desc).unwrap();
+        self.globals.insert(name.to_string(), id);
+    }
+
+    // 建立函數的入口與參數，函數本體交給 translate 翻譯
+    fn compile_fn(&mut self, id: FuncId, params: Vec<String>, translate: impl FnOnce(&mut FunctionTranslator<'_, M>)) {
         // 確保 ctx 內的簽名與剛剛宣告的一致
         self.ctx.func.signature.params.clear();
         self.ctx.func.signature.returns.clear();
-        for _ in &params {
-            self.ctx.func.signature.params.push(AbiParam::new(types::I32));
+        let ptr = self.module.target_config().pointer_type();
+        if self.tier_ids.is_some() {
+            self.ctx.func.signature.params.push(AbiParam::new(ptr));
+        } else {
+            for _ in &params {
+                self.ctx.func.signature.params.push(AbiParam::new(types::I32));
+            }
         }
         self.ctx.func.signature.returns.push(AbiParam::new(types::I32));
 
@@ -281,24 +426,32 @@ fn compile_fn(&mut self, id: cranelift_module::FuncId, params: Vec<String>, body
         builder.switch_to_block(entry_block);
         builder.seal_block(entry_block);
 
-        let mut variables = HashMap::new();
+        let mut params_scope = HashMap::new();
         for (i, name) in params.iter().enumerate() {
-            let val = builder.block_params(entry_block)[i];
+            let val = if self.tier_ids.is_some() {
+                let args = builder.block_params(entry_block)[0];
+                builder.ins().load(types::I32, MemFlags::trusted(), args, 4 * i as i32)
+            } else {
+                builder.block_params(entry_block)[i]
+            };
             let var = Variable::new(i);
             builder.declare_var(var, types::I32);
             builder.def_var(var, val);
-            variables.insert(name.clone(), var);
+            params_scope.insert(name.clone(), var);
         }
 
         let mut translator = FunctionTranslator { 
             builder, 
-            variables, 
+            scopes: vec![params_scope], 
             module: &mut self.module, 
+            globals: &self.globals,
+            strings: &mut self.strings,
+            tier_ids: self.tier_ids.as_ref(),
             next_var: params.len(),
             terminated: false 
         };
         
-        for stmt in body { translator.translate_stmt(stmt); }
+        translate(&mut translator);
         
         if !translator.terminated {
             let zero = translator.builder.ins().iconst(types::I32, 0);
@@ -308,32 +461,43 @@ fn compile_fn(&mut self, id: cranelift_module::FuncId, params: Vec<String>, body
         translator.builder.finalize();
         self.module.define_function(id, &mut self.ctx).unwrap();
         self.module.clear_context(&mut self.ctx);
-        self.module.finalize_definitions().unwrap();
-        self.module.get_finalized_function(id)
     }
 }
 
-struct FunctionTranslator<'a> {
+struct FunctionTranslator<'a, M: Module> {
     builder: FunctionBuilder<'a>,
-    variables: HashMap<String, Variable>,
-    module: &'a mut JITModule,
+    scopes: Vec<HashMap<String, Variable>>, // 區塊作用域，最後一個是最內層
+    module: &'a mut M,
+    globals: &'a HashMap<String, DataId>,
+    strings: &'a mut HashMap<String, DataId>,
+    tier_ids: Option<&'a HashMap<String, usize>>,
     next_var: usize,
     terminated: bool, // 手動追蹤當前 Block 是否已結束
 }
 
-impl<'a> FunctionTranslator<'a> {
+impl<M: Module> FunctionTranslator<'_, M> {
     fn translate_stmt(&mut self, stmt: Stmt) {
         if self.terminated { return; } // 如果已經 Return，跳過後續指令
         match stmt {
             Stmt::VarDecl(name, expr) => {
+                // let 一律在最內層建立新變數，遮蔽外層的同名變數
                 let val = self.translate_expr(expr);
-                let var = *self.variables.entry(name).or_insert_with(|| {
-                    let v = Variable::new(self.next_var);
-                    self.next_var += 1;
-                    self.builder.declare_var(v, types::I32);
-                    v
-                });
+                let var = Variable::new(self.next_var);
+                self.next_var += 1;
+                self.builder.declare_var(var, types::I32);
                 self.builder.def_var(var, val);
+                self.scopes.last_mut().unwrap().insert(name, var);
+            }
+            Stmt::Assign(name, expr) => {
+                // 指定寫回最近一層的同名變數，沒有的話就是全域變數
+                let val = self.translate_expr(expr);
+                match self.lookup(&name) {
+                    Some(var) => self.builder.def_var(var, val),
+                    None => {
+                        let addr = self.global_addr(&name);
+                        self.builder.ins().store(MemFlags::trusted(), val, addr, 0);
+                    }
+                }
             }
             Stmt::Return(expr) => {
                 let val = self.translate_expr(expr);
@@ -353,14 +517,14 @@ impl<'a> FunctionTranslator<'a> {
                 self.builder.switch_to_block(then_block);
                 self.builder.seal_block(then_block);
                 self.terminated = false;
-                for s in then_body { self.translate_stmt(s); }
+                self.translate_block(then_body);
                 if !self.terminated { self.builder.ins().jump(merge_block, &[]); }
 
                 // Else
                 self.builder.switch_to_block(else_block);
                 self.builder.seal_block(else_block);
                 self.terminated = false;
-                if let Some(eb) = else_body { for s in eb { self.translate_stmt(s); } }
+                if let Some(eb) = else_body { self.translate_block(eb); }
                 if !self.terminated { self.builder.ins().jump(merge_block, &[]); }
 
                 // Merge
@@ -372,13 +536,62 @@ impl<'a> FunctionTranslator<'a> {
         }
     }
 
+    fn translate_block(&mut self, stmts: Vec<Stmt>) {
+        self.scopes.push(HashMap::new());
+        for s in stmts { self.translate_stmt(s); }
+        self.scopes.pop();
+    }
+
+    fn lookup(&self, name: &str) -> Option<Variable> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).copied()
+    }
+
+    // 全域變數資料物件的位址
+    fn global_addr(&mut self, name: &str) -> cranelift::prelude::Value {
+        let id = *self.globals.get(name).expect("undefined variable");
+        let gv = self.module.declare_data_in_func(id, self.builder.func);
+        let ptr = self.module.target_config().pointer_type();
+        self.builder.ins().global_value(ptr, gv)
+    }
+
+    // 字串常數資料物件的位址，第一次用到時才宣告
+    fn string_addr(&mut self, s: &str) -> cranelift::prelude::Value {
+        let id = match self.strings.get(s) {
+            Some(id) => *id,
+            None => {
+                let id = self.module.declare_data(&format!("str.{}", self.strings.len()), Linkage::Local, false, false).unwrap();
+                let mut desc = DataDescription::new();
+                // 結尾補一個 0，主機函數當成 C 字串讀取
+                desc.define(s.bytes().chain([0]).collect());
+                self.module.define_data(id, &desc).unwrap();
+                self.strings.insert(s.to_string(), id);
+                id
+            }
+        };
+        let gv = self.module.declare_data_in_func(id, self.builder.func);
+        let ptr = self.module.target_config().pointer_type();
+        self.builder.ins().global_value(ptr, gv)
+    }
+
+    // 呼叫 JITBuilder 註冊的主機函數 (沒有回傳值)
+    fn call_host(&mut self, name: &str, args: &[cranelift::prelude::Value]) {
+        let mut sig = self.module.make_signature();
+        for &arg in args { sig.params.push(AbiParam::new(self.builder.func.dfg.value_type(arg))); }
+        let callee = self.module.declare_function(name, Linkage::Import, &sig).unwrap();
+        let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
+        self.builder.ins().call(local_callee, args);
+    }
+
     fn translate_expr(&mut self, expr: Expr) -> cranelift::prelude::Value {
         match expr {
             Expr::Number(n) => self.builder.ins().iconst(types::I32, n as i64),
-            Expr::Variable(name) => {
-                let var = self.variables.get(&name).expect("undefined variable");
-                self.builder.use_var(*var)
-            }
+            Expr::Variable(name) => match self.lookup(&name) {
+                Some(var) => self.builder.use_var(var),
+                None => {
+                    let addr = self.global_addr(&name);
+                    self.builder.ins().load(types::I32, MemFlags::trusted(), addr, 0)
+                }
+            },
             Expr::BinaryOp(left, op, right) => {
                 let lhs = self.translate_expr(*left);
                 let rhs = self.translate_expr(*right);
@@ -396,17 +609,26 @@ impl<'a> FunctionTranslator<'a> {
             Expr::Call(name, args) => {
                 let mut sig = self.module.make_signature();
                 if name == "print" {
-                    sig.params.push(AbiParam::new(types::I32));
-                    let callee = self.module.declare_function("print_i32", Linkage::Import, &sig).unwrap();
-                    let local_callee = self.module.declare_func_in_func(callee, &mut self.builder.func);
-                    let arg_vals: Vec<cranelift::prelude::Value> = args.into_iter().map(|a| self.translate_expr(a)).collect();
-                    self.builder.ins().call(local_callee, &[arg_vals[0]]);
+                    // 參數個數不固定：每個參數各呼叫一次 print_i32 / print_str，最後 print_end 換行
+                    for arg in args {
+                        match arg {
+                            Expr::Str(s) => {
+                                let addr = self.string_addr(&s);
+                                self.call_host("print_str", &[addr]);
+                            }
+                            arg => {
+                                let val = self.translate_expr(arg);
+                                self.call_host("print_i32", &[val]);
+                            }
+                        }
+                    }
+                    self.call_host("print_end", &[]);
                     self.builder.ins().iconst(types::I32, 0)
                 } else {
                     for _ in &args { sig.params.push(AbiParam::new(types::I32)); }
                     sig.returns.push(AbiParam::new(types::I32));
-                    let callee = self.module.declare_function(&name, Linkage::Import, &sig).unwrap();
-                    let local_callee = self.module.declare_func_in_func(callee, &mut self.builder.func);
+                    let callee = self.module.declare_function(&symbol(&name), Linkage::Import, &sig).unwrap();
+                    let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
                     let arg_vals: Vec<cranelift::prelude::Value> = args.into_iter().map(|a| self.translate_expr(a)).collect();
                     let call = self.builder.ins().call(local_callee, &arg_vals);
                     self.builder.inst_results(call)[0]
@@ -421,20 +643,91 @@ impl<'a> FunctionTranslator<'a> {
 // 5. 主程式
 // ==========================================================
 
+// --aot：產生目的檔 (執行檔的路徑加上 .o)，再和 runtime.c 一起交給 cc 連結成執行檔
+fn build_executable(program: Program, exe: &Path) {
+    let name = exe.file_stem().and_then(|s| s.to_str()).unwrap_or("p0");
+    let object = JIT::aot(name).compile_object(program);
+    let obj_path = exe.with_extension("o");
+    fs::write(&obj_path, object).expect("無法寫入目的檔");
+    println!("已輸出 {}", obj_path.display());
+
+    // 執行環境從 stdin 餵給 cc，執行檔不需要知道 runtime.c 放在哪裡
+    let mut cc = Command::new("cc")
+        .arg(&obj_path).args(["-x", "c", "-", "-o"]).arg(exe)
+        .stdin(Stdio::piped())
+        .spawn().expect("無法執行 cc");
+    cc.stdin.take().unwrap().write_all(RUNTIME.as_bytes()).expect("無法傳送執行環境給 cc");
+    if !cc.wait().expect("cc 沒有正常結束").success() { eprintln!("連結失敗"); process::exit(1); }
+    println!("已輸出 {}", exe.display());
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 { println!("用法: cargo run -- <source.p>"); return; }
-    let source = fs::read_to_string(&args[1]).expect("讀取檔案失敗");
-    let lexer = Lexer::new(&source);
-    let mut parser = Parser::new(lexer);
-    let program = parser.parse_program();
+    let usage = || -> ! {
+        println!("用法: cargo run -- [--aot [-o <執行檔>] | --tiered [--threshold <次數>] [--trace-jit]] <source.p0|source.ir>");
+        process::exit(1)
+    };
+    let (mut aot, mut output, mut file) = (false, None, None);
+    let (mut tiered, mut threshold, mut trace) = (false, tier::DEFAULT_THRESHOLD, false);
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--aot" => aot = true,
+            "-o" => {
+                i += 1;
+                output = Some(PathBuf::from(args.get(i).unwrap_or_else(|| usage())));
+            }
+            "--tiered" => tiered = true,
+            "--threshold" => {
+                i += 1;
+                threshold = args.get(i).and_then(|n| n.parse().ok()).unwrap_or_else(|| usage());
+                tiered = true;
+            }
+            "--trace-jit" => { trace = true; tiered = true; }
+            a if a.starts_with('-') => usage(),
+            a => file = Some(a.to_string()),
+        }
+        i += 1;
+    }
+    let file = file.unwrap_or_else(|| usage());
+    let source = fs::read_to_string(&file).expect("讀取檔案失敗");
+    // .ir 是 03-print 輸出 (或手寫) 的 IR，其他當成 p0 原始碼
+    let program = if Path::new(&file).extension().is_some_and(|e| e == "ir") {
+        match ir::parse(&source) {
+            Ok(functions) => Program::Ir(functions),
+            Err(e) => { eprintln!("{}: {}", file, e); process::exit(1); }
+        }
+    } else {
+        let lexer = Lexer::new(&source);
+        let mut parser = Parser::new(lexer);
+        Program::Ast(parser.parse_program())
+    };
+
+    if tiered {
+        if aot || output.is_some() { usage(); }
+        let Program::Ir(functions) = program else {
+            eprintln!("分層執行需要 .ir 檔 (p0 原始碼先用 03-print 編譯出 IR)");
+            process::exit(1);
+        };
+        let result = tier::run(functions, threshold, trace);
+        println!("回傳值: {}", result);
+        return;
+    }
+
+    if aot {
+        build_executable(program, &output.unwrap_or_else(|| Path::new(&file).with_extension("")));
+        return;
+    }
+    if output.is_some() { usage(); }
 
     let mut jit = JIT::new();
     let symbols = jit.compile(program);
     let main_ptr = symbols.get("main").expect("找不到 main 函數");
     let main_fn: extern "C" fn() -> i32 = unsafe { std::mem::transmute(*main_ptr) };
+    let init_fn: extern "C" fn() -> i32 = unsafe { std::mem::transmute(symbols[INIT]) };
+    init_fn();
 
     println!("--- JIT 執行中 ---");
     let result = main_fn();
     println!("回傳值: {}", result);
-}
\ No newline at end of file
+}
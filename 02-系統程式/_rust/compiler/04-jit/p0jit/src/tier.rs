@@ -0,0 +1,250 @@
+// ==========================================================
+// 分層執行 (--tiered)：.ir 先交給直譯器執行，呼叫次數到達門檻的函數才用 Cranelift 編譯
+//
+// 每個函數有一個呼叫計數器。直譯器和編譯後的程式碼呼叫其他函數時都經過 trampoline
+// (tier_call)，由它計數、必要時編譯，再決定直譯還是跳到機器碼，所以兩層可以互相呼叫。
+// 編譯後的函數一律是 (參數陣列的指標) -> i32，trampoline 不必知道參數個數；
+// 全域變數放在 JIT 模組的資料物件裡，兩層讀寫同一塊記憶體。
+// 呼叫深度由 trampoline 統一計算，錯誤訊息和 VM 相同。
+// 沒有 OSR：已經在直譯中的呼叫會直譯到結束，只有之後的呼叫才執行機器碼；
+// 只呼叫一次的函數 (例如 main) 裡的迴圈不會被編譯。
+// --trace-jit 在 stderr 報告哪個函數在第幾次呼叫、程式開始後多久被編譯。
+// ==========================================================
+
+use super::*;
+use crate::ir::{Function, Instr, MAX_DEPTH};
+use std::cell::{Cell, RefCell};
+use std::time::Instant;
+
+pub const DEFAULT_THRESHOLD: u32 = 100;
+
+// 直譯器每一層呼叫都用到主機的堆疊，VM 允許的呼叫深度需要比預設更大的堆疊
+const STACK_SIZE: usize = 256 << 20;
+
+// 編譯後的函數：參數依序放在陣列裡
+type Entry = extern "C" fn(*const i32) -> i32;
+
+// 直譯器的值；字串只會從 LOAD_STR 交給 print (ir::check 已經檢查過)
+#[derive(Clone, Copy)]
+enum Val<'a> {
+    Int(i32),
+    Str(&'a str),
+}
+
+impl Val<'_> {
+    fn int(self) -> i32 {
+        match self {
+            Val::Int(n) => n,
+            Val::Str(_) => unreachable!("字串只能交給 print"),
+        }
+    }
+}
+
+struct Engine {
+    functions: Vec<Function>,
+    ids: HashMap<String, usize>,
+    calls: Vec<Cell<u32>>,           // 每個函數被呼叫的次數
+    native: Vec<Cell<Option<Entry>>>, // 已經編譯的函數
+    globals: HashMap<String, *mut i32>,
+    jit: RefCell<JIT>,
+    depth: Cell<i64>, // main 以外還在執行的函數數
+    threshold: u32,
+    trace: bool,
+    started: Instant,
+    interpreted: Cell<u64>, // 直譯過的 IR 指令數 (--trace-jit 報告用)
+}
+
+thread_local! {
+    static ENGINE: Cell<*const Engine> = const { Cell::new(std::ptr::null()) };
+}
+
+// 編譯後的程式碼呼叫其他 p0 函數時經過這裡
+pub extern "C" fn tier_call(caller: i32, ip: i32, callee: i32, args: *const i32) -> i32 {
+    let engine = unsafe { &*ENGINE.with(Cell::get) };
+    let n = engine.functions[callee as usize].params.len();
+    let args = if n == 0 { &[][..] } else { unsafe { std::slice::from_raw_parts(args, n) } };
+    engine.call(caller as usize, ip as usize, callee as usize, args)
+}
+
+// 執行 __init__ 與 main，回傳 main 的回傳值
+pub fn run(functions: Vec<Function>, threshold: u32, trace: bool) -> i32 {
+    let thread = std::thread::Builder::new().stack_size(STACK_SIZE).spawn(move || {
+        let engine = Engine::new(functions, threshold, trace);
+        ENGINE.with(|e| e.set(&engine));
+        if let Some(&init) = engine.ids.get(INIT) { engine.enter(init, &[]); }
+        let Some(&main) = engine.ids.get("main") else {
+            eprintln!("找不到 main 函數");
+            process::exit(1);
+        };
+        println!("--- 分層執行中 (門檻 {} 次呼叫) ---", threshold);
+        let result = engine.enter(main, &[]);
+        if trace { engine.report(); }
+        result
+    });
+    thread.expect("無法建立執行緒").join().unwrap()
+}
+
+impl JIT {
+    fn tiered(ids: HashMap<String, usize>) -> Self {
+        let mut jit = JIT::new();
+        jit.tier_ids = Some(ids);
+        jit
+    }
+
+    // 宣告全域變數並回傳它們的位址
+    fn tier_globals(&mut self, functions: &[Function]) -> HashMap<String, *mut i32> {
+        for f in functions {
+            for instr in &f.code {
+                if let Instr::LoadGlobal(_, g) | Instr::StoreGlobal(g, _) = instr { self.declare_global(g); }
+            }
+        }
+        self.module.finalize_definitions().unwrap();
+        self.globals.iter().map(|(name, &id)| (name.clone(), self.module.get_finalized_data(id).0 as *mut i32)).collect()
+    }
+
+    // 只編譯一個函數
+    fn compile_tiered(&mut self, f: &Function) -> Entry {
+        let mut sig = self.module.make_signature();
+        sig.params.push(AbiParam::new(self.module.target_config().pointer_type()));
+        sig.returns.push(AbiParam::new(types::I32));
+        let id = self.module.declare_function(&symbol(&f.name), Linkage::Export, &sig).unwrap();
+        self.compile_fn(id, f.params.clone(), |t| t.translate_ir(f));
+        self.module.finalize_definitions().unwrap();
+        unsafe { std::mem::transmute::<*const u8, Entry>(self.module.get_finalized_function(id)) }
+    }
+}
+
+// VM 的整數運算：溢位時繞回，除以零是執行期錯誤
+fn binary(op: &str, l: i32, r: i32) -> Result<i32, &'static str> {
+    Ok(match op {
+        "ADD" => l.wrapping_add(r),
+        "SUB" => l.wrapping_sub(r),
+        "MUL" => l.wrapping_mul(r),
+        "DIV" | "MOD" if r == 0 => return Err("除以零"),
+        "DIV" => l.wrapping_div(r),
+        "MOD" => l.wrapping_rem(r),
+        "EQ" => (l == r) as i32,
+        "NE" => (l != r) as i32,
+        "LT" => (l < r) as i32,
+        "LE" => (l <= r) as i32,
+        "GT" => (l > r) as i32,
+        "GE" => (l >= r) as i32,
+        _ => unreachable!("未知的運算 {}", op),
+    })
+}
+
+// 沒有設定過的暫存值或變數是 0，和 JIT 的 Variable 一樣
+fn get<'a>(names: &HashMap<&str, Val<'a>>, name: &str) -> Val<'a> {
+    names.get(name).copied().unwrap_or(Val::Int(0))
+}
+
+impl Engine {
+    fn new(functions: Vec<Function>, threshold: u32, trace: bool) -> Self {
+        let ids: HashMap<String, usize> = functions.iter().enumerate().map(|(i, f)| (f.name.clone(), i)).collect();
+        let mut jit = JIT::tiered(ids.clone());
+        let globals = jit.tier_globals(&functions);
+        Engine {
+            calls: functions.iter().map(|_| Cell::new(0)).collect(),
+            native: functions.iter().map(|_| Cell::new(None)).collect(),
+            functions, ids, globals,
+            jit: RefCell::new(jit),
+            depth: Cell::new(0),
+            threshold, trace,
+            started: Instant::now(),
+            interpreted: Cell::new(0),
+        }
+    }
+
+    // trampoline：檢查呼叫深度後進入 callee
+    fn call(&self, caller: usize, ip: usize, callee: usize, args: &[i32]) -> i32 {
+        // 加上 main 就是 VM 的堆疊深度
+        if self.depth.get() >= MAX_DEPTH - 1 {
+            runtime_error(&format!("呼叫深度超過上限 {}", MAX_DEPTH), &self.functions[caller].name, ip);
+        }
+        self.depth.set(self.depth.get() + 1);
+        let result = self.enter(callee, args);
+        self.depth.set(self.depth.get() - 1);
+        result
+    }
+
+    // 計數，到達門檻就編譯；已經編譯的函數直接執行機器碼
+    fn enter(&self, id: usize, args: &[i32]) -> i32 {
+        let calls = self.calls[id].get() + 1;
+        self.calls[id].set(calls);
+        if calls >= self.threshold && self.native[id].get().is_none() { self.tier_up(id, calls); }
+        match self.native[id].get() {
+            Some(code) => code(args.as_ptr()),
+            None => self.interpret(id, args),
+        }
+    }
+
+    fn tier_up(&self, id: usize, calls: u32) {
+        let at = self.started.elapsed();
+        let code = self.jit.borrow_mut().compile_tiered(&self.functions[id]);
+        self.native[id].set(Some(code));
+        if self.trace {
+            eprintln!("[jit] {:.3} ms: {} 第 {} 次呼叫時編譯 (已直譯 {} 條 IR 指令，編譯花了 {:.3} ms)",
+                at.as_secs_f64() * 1000.0, self.functions[id].name, calls, self.interpreted.get(),
+                (self.started.elapsed() - at).as_secs_f64() * 1000.0);
+        }
+    }
+
+    // --trace-jit：程式結束時列出每個函數的呼叫次數，以及最後停在哪一層
+    fn report(&self) {
+        for (i, f) in self.functions.iter().enumerate() {
+            let calls = self.calls[i].get();
+            if calls == 0 { continue; }
+            let tier = if self.native[i].get().is_some() { "機器碼" } else { "直譯" };
+            eprintln!("[jit] {} 呼叫 {} 次，{}", f.name, calls, tier);
+        }
+    }
+
+    fn interpret(&self, id: usize, args: &[i32]) -> i32 {
+        let f = &self.functions[id];
+        let mut vars: HashMap<&str, Val> = f.params.iter().map(String::as_str).zip(args.iter().map(|&a| Val::Int(a))).collect();
+        let mut temps: HashMap<&str, Val> = HashMap::new();
+        let mut ip = 0;
+        while let Some(instr) = f.code.get(ip) {
+            self.interpreted.set(self.interpreted.get() + 1);
+            let mut next = ip + 1;
+            match instr {
+                Instr::Const(t, v) => { temps.insert(t, Val::Int(*v)); }
+                Instr::Str(t, s) => { temps.insert(t, Val::Str(s)); }
+                Instr::LoadVar(t, v) => { temps.insert(t, get(&vars, v)); }
+                Instr::StoreVar(v, t) => { vars.insert(v, get(&temps, t)); }
+                Instr::LoadGlobal(t, g) => { temps.insert(t, Val::Int(unsafe { *self.globals[g] })); }
+                Instr::StoreGlobal(g, t) => unsafe { *self.globals[g] = get(&temps, t).int() },
+                Instr::Binary(op, t, l, r) => {
+                    match binary(op, get(&temps, l).int(), get(&temps, r).int()) {
+                        Ok(v) => { temps.insert(t, Val::Int(v)); }
+                        Err(msg) => runtime_error(msg, &f.name, ip),
+                    }
+                }
+                Instr::Neg(t, s) => { temps.insert(t, Val::Int(get(&temps, s).int().wrapping_neg())); }
+                Instr::Not(t, s) => { temps.insert(t, Val::Int((get(&temps, s).int() == 0) as i32)); }
+                Instr::Call(callee, call_args, t) => {
+                    let val = if callee == "print" {
+                        for a in call_args {
+                            match get(&temps, a) {
+                                Val::Int(n) => print!("{} ", n),
+                                Val::Str(s) => print!("{} ", s),
+                            }
+                        }
+                        println!();
+                        0
+                    } else {
+                        let values: Vec<i32> = call_args.iter().map(|a| get(&temps, a).int()).collect();
+                        self.call(id, ip, self.ids[callee], &values)
+                    };
+                    temps.insert(t, Val::Int(val));
+                }
+                Instr::Return(t) => return get(&temps, t).int(),
+                Instr::IfFalse(t, target) => if get(&temps, t).int() == 0 { next = *target; },
+                Instr::Goto(target) => next = *target,
+                Instr::Label => {}
+            }
+            ip = next;
+        }
+        0
+    }
+}
@@ -1 +1,41 @@
-cargo run -- p0/fact.p0
\ No newline at end of file
+cargo run -- p0/fact.p0
+# 每個 p0/ 範例也交給 IR VM (03-print) 執行、以及用 --aot 編成執行檔執行，
+# print 的輸出與 main 的回傳值都要和 JIT 一樣
+work=$(mktemp -d)
+rustc ../../03-print/compiler.rs -o "$work/compiler" || exit 1
+fail=0
+for src in p0/*.p0; do
+  cp "$src" "$work/"
+  vm=$("$work/compiler" "$work/$(basename "$src")" | sed 's/^(main 結束，回傳值: \(.*\))$/回傳值: \1/')
+  jit=$(cargo run -q -- "$src" | grep -v '^--- JIT 執行中 ---$')
+  exe="$work/$(basename "$src" .p0)"
+  aot=$(cargo run -q -- --aot "$src" -o "$exe" > /dev/null && "$exe")
+  if [ "$vm" = "$jit" ] && [ "$vm" = "$aot" ]; then echo "ok   $src"; else
+    echo "FAIL $src"; diff <(echo "$vm") <(echo "$jit"); diff <(echo "$vm") <(echo "$aot"); fail=1
+  fi
+done
+
+# 03-print 的範例先由 VM 編譯出 .ir，連同手寫的 .ir 與 p0/*.ir 分別交給 IR VM、JIT
+# 與分層執行 (門檻 2 次呼叫，直譯與編譯後的函數互相呼叫) 執行，標準輸出與結束碼都要一樣；
+# 用到 JIT 不支援的功能 (陣列、print 以外的內建函數) 的檔案略過
+run_vm() { "$work/compiler" "$1" 2> /dev/null < /dev/null | sed 's/^(main 結束，回傳值: \(.*\))$/回傳值: \1/'; return "${PIPESTATUS[0]}"; }
+run_jit() { cargo run -q -- "$@" 2> "$work/jit.err" | grep -v '^--- .*執行中.* ---$'; return "${PIPESTATUS[0]}"; }
+mkdir "$work/p0" && cp -r ../../03-print/p0/. "$work/p0/"
+for src in "$work"/p0/*.p0; do "$work/compiler" -I "$work/p0/lib" "$src" < /dev/null > /dev/null 2>&1; done
+for ir in "$work"/p0/*.ir p0/*.ir; do
+  name=${ir/#"$work"/03-print}
+  vm=$(run_vm "$ir"); vm_status=$?
+  jit=$(run_jit "$ir"); jit_status=$?
+  if grep -q '不支援' "$work/jit.err"; then echo "skip $name ($(sed "s#^$work/##" "$work/jit.err"))"; continue; fi
+  tier=$(run_jit --threshold 2 "$ir"); tier_status=$?
+  if [ "$vm" = "$jit" ] && [ $vm_status = $jit_status ] && [ "$vm" = "$tier" ] && [ $vm_status = $tier_status ]; then echo "ok   $name (結束碼 $vm_status)"
+  else
+    echo "FAIL $name: VM 結束碼 $vm_status，JIT 結束碼 $jit_status，分層執行結束碼 $tier_status"
+    diff <(echo "$vm") <(echo "$jit"); diff <(echo "$vm") <(echo "$tier"); fail=1
+  fi
+done
+
+# --trace-jit：門檻 3 次時 factorial 在第 3 次呼叫被編譯，main 只呼叫一次，一直是直譯
+cargo run -q -- --threshold 3 --trace-jit p0/fact.ir 2>&1 >/dev/null | sed 's/[0-9.]* ms/_ ms/g'
+rm -rf "$work"
+exit $fail
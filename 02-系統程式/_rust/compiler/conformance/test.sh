@@ -0,0 +1,24 @@
+# 同一組 p0 程式分別交給 IR VM (03-print，-O0 與 -O1) 與 Cranelift JIT (04-jit/p0jit) 執行，
+# main 的回傳值都必須等於 .expect 檔的內容
+cd "$(dirname "$0")"
+work=$(mktemp -d)
+rustc ../03-print/compiler.rs -o "$work/compiler" || exit 1
+cargo build -q --manifest-path ../04-jit/p0jit/Cargo.toml || exit 1
+jit=../04-jit/p0jit/target/debug/p0jit
+fail=0
+for src in *.p0; do
+  name=${src%.p0}
+  expect=$(cat "$name.expect")
+  cp "$src" "$work/"
+  vm=$("$work/compiler" "$work/$src" | sed -n 's/^(main 結束，回傳值: \(.*\))$/\1/p')
+  vm_opt=$("$work/compiler" -O1 "$work/$src" 2> /dev/null | sed -n 's/^(main 結束，回傳值: \(.*\))$/\1/p')
+  jit_out=$("$jit" "$src" | sed -n 's/^回傳值: //p')
+  if [ "$vm" = "$expect" ] && [ "$vm_opt" = "$expect" ] && [ "$jit_out" = "$expect" ]; then
+    echo "ok   $name ($expect)"
+  else
+    echo "FAIL $name: 預期 $expect，VM=$vm，VM -O1=$vm_opt，JIT=$jit_out"
+    fail=1
+  fi
+done
+rm -rf "$work"
+exit $fail
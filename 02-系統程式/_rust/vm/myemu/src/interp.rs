@@ -0,0 +1,185 @@
+// RV64I 直譯器：逐條解碼執行，不依賴宿主架構
+// (JIT 模式只能在 ARM64 上跑，而且目前只認得少數幾個指令)
+use super::Cpu;
+use std::io::Write;
+
+const SYS_WRITE: u64 = 64;
+const SYS_EXIT: u64 = 93;
+
+fn sext(v: u64, bits: u32) -> u64 {
+    (((v << (64 - bits)) as i64) >> (64 - bits)) as u64
+}
+
+fn range(mem: &[u8], addr: u64, size: usize) -> Result<std::ops::Range<usize>, String> {
+    let start = addr as usize;
+    if addr > mem.len() as u64 || start + size > mem.len() {
+        return Err(format!("記憶體存取越界: 0x{:x}", addr));
+    }
+    Ok(start..start + size)
+}
+
+fn load(mem: &[u8], addr: u64, size: usize) -> Result<u64, String> {
+    let mut bytes = [0u8; 8];
+    bytes[..size].copy_from_slice(&mem[range(mem, addr, size)?]);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn store(mem: &mut [u8], addr: u64, size: usize, v: u64) -> Result<(), String> {
+    let r = range(mem, addr, size)?;
+    mem[r].copy_from_slice(&v.to_le_bytes()[..size]);
+    Ok(())
+}
+
+impl Cpu {
+    // 執行一條指令；程式呼叫 exit 時回傳 Some(結束碼)
+    pub fn step(&mut self, mem: &mut [u8]) -> Result<Option<i32>, String> {
+        let pc = self.pc;
+        let ins = load(mem, pc, 4)? as u32;
+        let opcode = ins & 0x7f;
+        let rd = ((ins >> 7) & 0x1f) as usize;
+        let funct3 = (ins >> 12) & 0x7;
+        let rs1 = ((ins >> 15) & 0x1f) as usize;
+        let rs2 = ((ins >> 20) & 0x1f) as usize;
+        let funct7 = ins >> 25;
+        let (a, b) = (self.regs[rs1], self.regs[rs2]);
+
+        let imm_i = sext((ins >> 20) as u64, 12);
+        let imm_s = sext((((ins >> 25) << 5) | ((ins >> 7) & 0x1f)) as u64, 12);
+        let imm_b = sext(
+            (((ins >> 31) << 12) | (((ins >> 7) & 1) << 11) | (((ins >> 25) & 0x3f) << 5) | (((ins >> 8) & 0xf) << 1)) as u64,
+            13,
+        );
+        let imm_u = sext((ins & 0xfffff000) as u64, 32);
+        let imm_j = sext(
+            (((ins >> 31) << 20) | (((ins >> 12) & 0xff) << 12) | (((ins >> 20) & 1) << 11) | (((ins >> 21) & 0x3ff) << 1)) as u64,
+            21,
+        );
+        let illegal = || Err(format!("不支援的指令 0x{:08x} (PC 0x{:x})", ins, pc));
+
+        let mut next = pc.wrapping_add(4);
+        let mut result = None;
+        match opcode {
+            0x37 => result = Some(imm_u),                  // LUI
+            0x17 => result = Some(pc.wrapping_add(imm_u)), // AUIPC
+            0x6f => {
+                // JAL
+                result = Some(next);
+                next = pc.wrapping_add(imm_j);
+            }
+            0x67 => {
+                // JALR
+                result = Some(next);
+                next = a.wrapping_add(imm_i) & !1;
+            }
+            0x63 => {
+                let taken = match funct3 {
+                    0 => a == b,
+                    1 => a != b,
+                    4 => (a as i64) < (b as i64),
+                    5 => (a as i64) >= (b as i64),
+                    6 => a < b,
+                    7 => a >= b,
+                    _ => return illegal(),
+                };
+                if taken {
+                    next = pc.wrapping_add(imm_b);
+                }
+            }
+            0x03 => {
+                let addr = a.wrapping_add(imm_i);
+                result = Some(match funct3 {
+                    0 => sext(load(mem, addr, 1)?, 8),
+                    1 => sext(load(mem, addr, 2)?, 16),
+                    2 => sext(load(mem, addr, 4)?, 32),
+                    3 => load(mem, addr, 8)?,
+                    4 => load(mem, addr, 1)?,
+                    5 => load(mem, addr, 2)?,
+                    6 => load(mem, addr, 4)?,
+                    _ => return illegal(),
+                });
+            }
+            0x23 => {
+                let size = match funct3 {
+                    0..=3 => 1 << funct3,
+                    _ => return illegal(),
+                };
+                store(mem, a.wrapping_add(imm_s), size, b)?;
+            }
+            0x13 | 0x33 => {
+                let (b, alt) = if opcode == 0x13 {
+                    // 立即數版本：移位量只取低 6 位，SRAI 由 bit 30 區分
+                    (imm_i, funct3 == 5 && ins & (1 << 30) != 0)
+                } else {
+                    if funct7 & !0x20 != 0 {
+                        return illegal();
+                    }
+                    (b, funct7 == 0x20)
+                };
+                let sh = (b & 0x3f) as u32;
+                result = Some(match funct3 {
+                    0 if alt && opcode == 0x33 => a.wrapping_sub(b),
+                    0 => a.wrapping_add(b),
+                    1 => a << sh,
+                    2 => ((a as i64) < (b as i64)) as u64,
+                    3 => (a < b) as u64,
+                    4 => a ^ b,
+                    5 if alt => ((a as i64) >> sh) as u64,
+                    5 => a >> sh,
+                    6 => a | b,
+                    _ => a & b,
+                });
+            }
+            0x1b | 0x3b => {
+                // 32 位元運算 (ADDIW、ADDW、SUBW、SLLW ...)，結果做符號延伸
+                let (b, alt) = if opcode == 0x1b {
+                    (imm_i, ins & (1 << 30) != 0)
+                } else {
+                    // 只有 funct7 = 0x00/0x20；MULW、DIVW 等 (M 擴充) 不支援
+                    if funct7 & !0x20 != 0 {
+                        return illegal();
+                    }
+                    (b, funct7 == 0x20)
+                };
+                let (a32, b32, sh) = (a as u32, b as u32, (b & 0x1f) as u32);
+                let v = match funct3 {
+                    0 if alt && opcode == 0x3b => a32.wrapping_sub(b32),
+                    0 => a32.wrapping_add(b32),
+                    1 => a32 << sh,
+                    5 if alt => ((a32 as i32) >> sh) as u32,
+                    5 => a32 >> sh,
+                    _ => return illegal(),
+                };
+                result = Some(v as i32 as i64 as u64);
+            }
+            0x0f => {} // FENCE
+            0x73 if ins == 0x73 => {
+                // ECALL：沿用 Linux 的系統呼叫編號
+                let (a0, a1, a2) = (self.regs[10], self.regs[11], self.regs[12]);
+                match self.regs[17] {
+                    SYS_WRITE => {
+                        let bytes = &mem[range(mem, a1, a2 as usize)?];
+                        let written = match a0 {
+                            1 => std::io::stdout().write_all(bytes),
+                            2 => std::io::stderr().write_all(bytes),
+                            _ => return Err(format!("write: 不支援的 fd {}", a0)),
+                        };
+                        self.regs[10] = if written.is_ok() { a2 } else { u64::MAX };
+                    }
+                    SYS_EXIT => {
+                        std::io::stdout().flush().ok();
+                        return Ok(Some(a0 as i32));
+                    }
+                    n => return Err(format!("不支援的系統呼叫 {}", n)),
+                }
+            }
+            _ => return illegal(),
+        }
+        if let Some(v) = result
+            && rd != 0
+        {
+            self.regs[rd] = v;
+        }
+        self.pc = next;
+        Ok(None)
+    }
+}
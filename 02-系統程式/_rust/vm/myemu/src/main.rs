@@ -3,6 +3,10 @@ use goblin::elf::Elf;
 use std::fs;
 use std::mem;
 
+mod interp;
+
+const MEM_SIZE: usize = 1024 * 1024;
+
 #[repr(C)]
 struct Cpu {
     regs: [u64; 32],
@@ -107,16 +111,16 @@ impl Jitter {
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        println!("Usage: cargo run <riscv64_elf_file>");
+    let interp = args.iter().any(|a| a == "--interp");
+    let Some(path) = args.iter().skip(1).find(|a| !a.starts_with("--")) else {
+        println!("Usage: cargo run [--interp] <riscv64_elf_file>");
         return;
-    }
+    };
 
-    let path = &args[1];
     let buffer = fs::read(path).expect("Failed to read file");
     let elf = Elf::parse(&buffer).expect("Failed to parse ELF");
 
-    let mut mem_space = vec![0u8; 1024 * 1024]; 
+    let mut mem_space = vec![0u8; MEM_SIZE];
     let entry_point = elf.entry;
     
     for ph in elf.program_headers {
@@ -131,6 +135,24 @@ fn main() {
     }
 
     let mut cpu = Cpu::new(entry_point);
+
+    // --interp：用直譯器執行到程式呼叫 exit，並以它的結束碼結束 myemu。
+    // 直譯器真的會存取堆疊，SP 要放在 mem_space 的頂端往下長
+    if interp {
+        cpu.regs[2] = (MEM_SIZE - 16) as u64;
+        loop {
+            cpu.regs[0] = 0;
+            match cpu.step(&mut mem_space) {
+                Ok(None) => {}
+                Ok(Some(code)) => std::process::exit(code),
+                Err(e) => {
+                    eprintln!("myemu: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
     println!("myemu: Starting at PC 0x{:x}", cpu.pc);
 
     loop {